@@ -0,0 +1,71 @@
+//! How long writing 100,000 quotes takes as JSON lines, like --yaml-flow prints them, and as the
+//! length-prefixed MessagePack of --msgpack.
+
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+use serde_json::{json, Value};
+
+const PACKETS: u64 = 100_000;
+
+// Quotes with the fields --yaml and --msgpack write, and books that vary from one to the next.
+fn quotes() -> Vec<Value> {
+    (0..PACKETS)
+        .map(|i| {
+            let side = |base: u64, step: i64| {
+                (0..5)
+                    .map(|level| {
+                        let price = (base as i64 + step * level) as u64;
+                        json!({ "price": price, "quantity": (i * 7 + level as u64 * 13) % 1000 })
+                    })
+                    .collect::<Vec<_>>()
+            };
+            let mid = 10_000 + i % 500;
+            json!({
+                "type": "quote",
+                "number": i + 1,
+                "offset": 24 + i * 257,
+                "message_type": "B6034",
+                "time_stamp": "2011-02-16 09:00:00.123456",
+                "accept_time": "2011-02-16 09:00:00.12",
+                "issue_code": "KR4201011009",
+                "issue_sequence": format!("{:03}", i % 1000),
+                "market_status": "20",
+                "bids": side(mid - 1, -1),
+                "asks": side(mid + 1, 1),
+            })
+        })
+        .collect()
+}
+
+fn serialize(c: &mut Criterion) {
+    let quotes = quotes();
+    let mut buffer = Vec::new();
+    let mut group = c.benchmark_group("serialize");
+    group.throughput(Throughput::Elements(PACKETS));
+    group.bench_function("json", |b| {
+        b.iter(|| {
+            buffer.clear();
+            for quote in &quotes {
+                serde_json::to_writer(&mut buffer, quote).unwrap();
+                buffer.push(b'\n');
+            }
+            buffer.len()
+        })
+    });
+    group.bench_function("msgpack", |b| {
+        b.iter(|| {
+            buffer.clear();
+            for quote in &quotes {
+                let start = buffer.len();
+                buffer.extend_from_slice(&[0; 4]);
+                rmp_serde::encode::write_named(&mut buffer, quote).unwrap();
+                let length = (buffer.len() - start - 4) as u32;
+                buffer[start..start + 4].copy_from_slice(&length.to_be_bytes());
+            }
+            buffer.len()
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, serialize);
+criterion_main!(benches);
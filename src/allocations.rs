@@ -0,0 +1,55 @@
+//! Counting what the packet loop allocates for `--bench`, with the `count-allocations` feature.
+//!
+//! The counting allocator wraps the system one, but still costs a thread-local lookup on every
+//! allocation, which is why builds without the feature keep the system allocator as it is.
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+static ALLOCATIONS: AtomicU64 = AtomicU64::new(0);
+
+thread_local! {
+    // Only the thread parsing the capture counts its allocations, since the ctrl-c handler's
+    // thread can still be starting up while the packet loop runs.
+    static COUNT_ALLOCATIONS: Cell<bool> = const { Cell::new(false) };
+}
+
+fn count_allocation() {
+    if COUNT_ALLOCATIONS.try_with(Cell::get).unwrap_or(false) {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+// The system allocator, counting allocations so that `--bench` can show what the packet loop
+// allocates, which should be nothing once its buffers have grown.
+struct CountingAllocator;
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        count_allocation();
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        count_allocation();
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+// Starts counting the allocations of the calling thread.
+pub(crate) fn count_this_thread() {
+    COUNT_ALLOCATIONS.with(|count| count.set(true));
+}
+
+// How many allocations have been counted so far.
+pub(crate) fn counted() -> u64 {
+    ALLOCATIONS.load(Ordering::Relaxed)
+}
@@ -0,0 +1,271 @@
+//! `--full-sort`, which puts the packets of a capture in event time order however far they are from
+//! it, unlike -r, which counts on the timestamps going up.
+//!
+//! Packets are gathered in runs of `--sort-run-size`. Each full run is sorted and written to a
+//! temporary file in a compact binary form, and once the capture has been read, the runs are merged
+//! by always taking the earliest of their next packets. The last run is never written out, so a
+//! capture that fits in one run is sorted in memory. The run files are removed when the sort is
+//! dropped, which happens on errors as well.
+//!
+//! A run file is just its packets one after the other, each a tag byte followed by fixed-size
+//! fields in little endian:
+//!
+//! | Tag | Fields                                                                                  |
+//! |-----|-----------------------------------------------------------------------------------------|
+//! | `Q` | offset, number, message type, timestamp, accept time, issue code, issue sequence,       |
+//! |     | market status, the number of levels on each side as a byte, then the quantity and price |
+//! |     | of every bid and ask level, best first                                                  |
+//! | `T` | offset, number, message type, timestamp, trade time, issue code, issue sequence,        |
+//! |     | board ID, price, quantity                                                               |
+//!
+//! Times are their Unix seconds as an i64 and nanoseconds as a u32. The aggressor of trades and the
+//! moving average of quotes aren't kept, since they're only worked out when packets are written.
+
+use crate::{naive_from_timestamp, QuotePacket, Record, TradePacket, INVALID_TIMESTAMP};
+use chrono::NaiveDateTime;
+use parse_quote::payload::{QuoteData, QUOTE_EXT_LEVELS};
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufReader, BufWriter, ErrorKind, Read, Write};
+use std::path::{Path, PathBuf};
+use std::{process, vec};
+
+const QUOTE_TAG: u8 = b'Q';
+const TRADE_TAG: u8 = b'T';
+
+fn push_time(buf: &mut Vec<u8>, time: NaiveDateTime) {
+    let time = time.and_utc();
+    buf.extend_from_slice(&time.timestamp().to_le_bytes());
+    buf.extend_from_slice(&time.timestamp_subsec_nanos().to_le_bytes());
+}
+
+fn encode(record: &Record, buf: &mut Vec<u8>) {
+    match record {
+        Record::Quote(quote_packet) => {
+            buf.push(QUOTE_TAG);
+            buf.extend_from_slice(&quote_packet.offset.to_le_bytes());
+            buf.extend_from_slice(&quote_packet.number.to_le_bytes());
+            buf.extend_from_slice(&quote_packet.message_type);
+            push_time(buf, quote_packet.time_stamp);
+            push_time(buf, quote_packet.quote_accept_time);
+            buf.extend_from_slice(&quote_packet.issue_code);
+            buf.extend_from_slice(&quote_packet.issue_sequence);
+            buf.extend_from_slice(&quote_packet.market_status);
+            buf.push(quote_packet.levels as u8);
+            for &(quantity, price) in quote_packet.bids().iter().chain(quote_packet.asks()) {
+                buf.extend_from_slice(&quantity.to_le_bytes());
+                buf.extend_from_slice(&price.to_le_bytes());
+            }
+        }
+        Record::Trade(trade_packet) => {
+            buf.push(TRADE_TAG);
+            buf.extend_from_slice(&trade_packet.offset.to_le_bytes());
+            buf.extend_from_slice(&trade_packet.number.to_le_bytes());
+            buf.extend_from_slice(&trade_packet.message_type);
+            push_time(buf, trade_packet.time_stamp);
+            push_time(buf, trade_packet.trade_time);
+            buf.extend_from_slice(&trade_packet.issue_code);
+            buf.extend_from_slice(&trade_packet.issue_sequence);
+            buf.extend_from_slice(&trade_packet.board_id);
+            buf.extend_from_slice(&trade_packet.price.to_le_bytes());
+            buf.extend_from_slice(&trade_packet.quantity.to_le_bytes());
+        }
+    }
+}
+
+// Reads the fields of an encoded packet back in the order they were written.
+struct Fields<'a, R: Read>(&'a mut R);
+
+impl<R: Read> Fields<'_, R> {
+    fn bytes<const N: usize>(&mut self) -> Result<[u8; N], io::Error> {
+        let mut buf = [0; N];
+        self.0.read_exact(&mut buf)?;
+        Ok(buf)
+    }
+
+    fn u8(&mut self) -> Result<u8, io::Error> {
+        Ok(self.bytes::<1>()?[0])
+    }
+
+    fn u32(&mut self) -> Result<u32, io::Error> {
+        Ok(u32::from_le_bytes(self.bytes()?))
+    }
+
+    fn u64(&mut self) -> Result<u64, io::Error> {
+        Ok(u64::from_le_bytes(self.bytes()?))
+    }
+
+    fn time(&mut self) -> Result<NaiveDateTime, io::Error> {
+        let seconds = i64::from_le_bytes(self.bytes()?);
+        naive_from_timestamp(seconds, self.u32()?)
+            .ok_or_else(|| io::Error::new(ErrorKind::InvalidData, INVALID_TIMESTAMP))
+    }
+}
+
+// Reads the next packet of a run file, or `None` at its end.
+fn decode(reader: &mut impl Read) -> Result<Option<Record>, io::Error> {
+    let mut tag = [0];
+    if reader.read(&mut tag)? == 0 {
+        return Ok(None);
+    }
+    let mut fields = Fields(reader);
+    let record = match tag[0] {
+        QUOTE_TAG => {
+            let mut quote_packet = QuotePacket {
+                offset: fields.u64()?,
+                number: fields.u64()?,
+                message_type: fields.bytes()?,
+                time_stamp: fields.time()?,
+                quote_accept_time: fields.time()?,
+                issue_code: fields.bytes()?,
+                issue_sequence: fields.bytes()?,
+                market_status: fields.bytes()?,
+                bids: [(0, 0); QUOTE_EXT_LEVELS],
+                asks: [(0, 0); QUOTE_EXT_LEVELS],
+                levels: usize::from(fields.u8()?),
+                ema: None,
+            };
+            if quote_packet.levels > QUOTE_EXT_LEVELS {
+                let message = format!(
+                    "Quote with {} book levels in a sort run file",
+                    quote_packet.levels
+                );
+                return Err(io::Error::new(ErrorKind::InvalidData, message));
+            }
+            let levels = quote_packet.bids[..quote_packet.levels]
+                .iter_mut()
+                .chain(&mut quote_packet.asks[..quote_packet.levels]);
+            for level in levels {
+                *level = (fields.u32()?, fields.u32()?);
+            }
+            Record::Quote(quote_packet)
+        }
+        TRADE_TAG => Record::Trade(TradePacket {
+            offset: fields.u64()?,
+            number: fields.u64()?,
+            message_type: fields.bytes()?,
+            time_stamp: fields.time()?,
+            trade_time: fields.time()?,
+            issue_code: fields.bytes()?,
+            issue_sequence: fields.bytes()?,
+            board_id: fields.bytes()?,
+            price: fields.u32()?,
+            quantity: fields.u32()?,
+            aggressor: None,
+        }),
+        tag => {
+            let message = format!("Unknown packet tag {:#04x} in a sort run file", tag);
+            return Err(io::Error::new(ErrorKind::InvalidData, message));
+        }
+    };
+    Ok(Some(record))
+}
+
+// The packets of one sorted run, earliest first.
+enum Run {
+    File(BufReader<File>),
+    Memory(vec::IntoIter<Record>),
+}
+
+impl Run {
+    fn next(&mut self) -> Result<Option<Record>, io::Error> {
+        match self {
+            Run::File(reader) => decode(reader),
+            Run::Memory(records) => Ok(records.next()),
+        }
+    }
+}
+
+pub(crate) struct ExternalSort {
+    dir: PathBuf,
+    run_size: usize,
+    // The run being gathered, and the files of the ones already written out.
+    run: Vec<Record>,
+    paths: Vec<PathBuf>,
+    runs: Vec<Run>,
+    // The next packet of every run, with the run it came from.
+    heads: BinaryHeap<(Record, Reverse<usize>)>,
+}
+
+impl ExternalSort {
+    pub(crate) fn new(dir: &Path, run_size: usize) -> Self {
+        ExternalSort {
+            dir: dir.to_path_buf(),
+            run_size,
+            run: Vec::new(),
+            paths: Vec::new(),
+            runs: Vec::new(),
+            heads: BinaryHeap::new(),
+        }
+    }
+
+    pub(crate) fn push(&mut self, record: Record) -> Result<(), io::Error> {
+        self.run.push(record);
+        if self.run.len() == self.run_size {
+            self.spill()?;
+        }
+        Ok(())
+    }
+
+    // Earlier records compare greater, so this sorts them earliest first.
+    fn sort_run(&mut self) {
+        self.run.sort_unstable_by(|a, b| b.cmp(a));
+    }
+
+    fn spill(&mut self) -> Result<(), io::Error> {
+        self.sort_run();
+        let name = format!("parse-quote-{}-{}.run", process::id(), self.paths.len());
+        let path = self.dir.join(name);
+        let file = OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&path)?;
+        self.paths.push(path);
+        let mut writer = BufWriter::new(file);
+        let mut buf = Vec::new();
+        for record in self.run.drain(..) {
+            buf.clear();
+            encode(&record, &mut buf);
+            writer.write_all(&buf)?;
+        }
+        writer.flush()
+    }
+
+    // Called once every packet has been pushed, before taking them out with `next`.
+    pub(crate) fn finish(&mut self) -> Result<(), io::Error> {
+        self.sort_run();
+        for path in &self.paths {
+            self.runs.push(Run::File(BufReader::new(File::open(path)?)));
+        }
+        self.runs
+            .push(Run::Memory(std::mem::take(&mut self.run).into_iter()));
+        for (index, run) in self.runs.iter_mut().enumerate() {
+            if let Some(record) = run.next()? {
+                self.heads.push((record, Reverse(index)));
+            }
+        }
+        Ok(())
+    }
+
+    pub(crate) fn next(&mut self) -> Result<Option<Record>, io::Error> {
+        let (record, Reverse(index)) = match self.heads.pop() {
+            Some(head) => head,
+            None => return Ok(None),
+        };
+        if let Some(next) = self.runs[index].next()? {
+            self.heads.push((next, Reverse(index)));
+        }
+        Ok(Some(record))
+    }
+}
+
+impl Drop for ExternalSort {
+    fn drop(&mut self) {
+        // The files have to be closed before they can be removed on some platforms.
+        self.runs.clear();
+        for path in &self.paths {
+            let _ = fs::remove_file(path);
+        }
+    }
+}
@@ -0,0 +1,152 @@
+//! Captures given as `http://` or `https://` URLs, which are downloaded to a temporary file and
+//! then parsed like any other, since parsing seeks around in the capture. Nothing is parsed, and so
+//! nothing printed, until the whole capture has been downloaded.
+//!
+//! A download that breaks off is resumed with a Range request for the bytes after the last one
+//! written, up to `--read-retries` times in a row. Bodies sent with gzip content encoding are
+//! decoded on the fly, and resumed requests ask for them unencoded so that the range lines up with
+//! what's been written. A server that answers a Range request with anything but the bytes asked
+//! for makes the download start over.
+
+use crate::RETRY_BACKOFF;
+use log::debug;
+use std::env;
+use std::error::Error;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, ErrorKind, Read, Seek, SeekFrom, Write};
+use std::process;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::thread;
+use std::time::Duration;
+
+const CHUNK_SIZE: usize = 64 * 1024;
+const TIMEOUT: Duration = Duration::from_secs(30);
+// Without a Content-Length, progress is logged every this many bytes.
+const PROGRESS_STEP: u64 = 64 * 1024 * 1024;
+
+static DOWNLOADS: AtomicU64 = AtomicU64::new(0);
+
+// The offset the body of a 206 response starts at, from `Content-Range: bytes start-end/length`.
+fn range_start(response: &ureq::Response) -> Option<u64> {
+    let range = response.header("Content-Range")?.strip_prefix("bytes ")?;
+    range.split('-').next()?.trim().parse().ok()
+}
+
+// Appends the body to `file` until it ends, logging progress at `-v`.
+fn copy_body(
+    body: &mut impl Read,
+    file: &mut File,
+    downloaded: &mut u64,
+    length: Option<u64>,
+) -> Result<(), io::Error> {
+    let step = length.map_or(PROGRESS_STEP, |length| (length / 10).max(1));
+    let mut buf = vec![0; CHUNK_SIZE];
+    loop {
+        let read = match body.read(&mut buf) {
+            Ok(0) => break,
+            Ok(read) => read,
+            Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+        file.write_all(&buf[..read])?;
+        let before = *downloaded;
+        *downloaded += read as u64;
+        if before / step != *downloaded / step {
+            match length {
+                Some(length) => debug!("Downloaded {} of {} bytes", downloaded, length),
+                None => debug!("Downloaded {} bytes", downloaded),
+            }
+        }
+    }
+    match length {
+        Some(length) if *downloaded < length => Err(io::Error::new(
+            ErrorKind::UnexpectedEof,
+            format!("connection closed after {} of {} bytes", downloaded, length),
+        )),
+        _ => Ok(()),
+    }
+}
+
+/// Downloads `url` and returns the file it was written to, at its start.
+pub fn download(url: &str, retries: u32) -> Result<File, Box<dyn Error>> {
+    let path = env::temp_dir().join(format!(
+        "parse-quote-{}-{}.pcap",
+        process::id(),
+        DOWNLOADS.fetch_add(1, Ordering::SeqCst)
+    ));
+    let mut file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create_new(true)
+        .open(&path)?;
+    // Only the handle is needed from here on, and where the file can't be removed while it's open
+    // it's left to the temporary directory.
+    let _ = fs::remove_file(&path);
+    let agent = ureq::AgentBuilder::new()
+        .timeout_connect(TIMEOUT)
+        .timeout_read(TIMEOUT)
+        .build();
+    let mut downloaded = 0;
+    let mut length = None;
+    let mut failures = 0;
+    loop {
+        let mut request = agent.get(url);
+        if downloaded > 0 {
+            request = request
+                .set("Range", &format!("bytes={}-", downloaded))
+                .set("Accept-Encoding", "identity");
+        }
+        let response = match request.call() {
+            Ok(response) => response,
+            Err(ureq::Error::Transport(e)) if failures < retries => {
+                failures += 1;
+                eprintln!("Warning: could not reach {} ({}), retrying", url, e);
+                // Backing off a little longer every time, like reads that time out.
+                thread::sleep(RETRY_BACKOFF * failures);
+                continue;
+            }
+            Err(e) => return Err(e.into()),
+        };
+        let encoded = response.header("Content-Encoding").is_some();
+        let resumed =
+            response.status() == 206 && !encoded && range_start(&response) == Some(downloaded);
+        if !resumed && downloaded > 0 {
+            debug!("The server didn't resume the download, starting over");
+            file.set_len(0)?;
+            file.seek(SeekFrom::Start(0))?;
+            downloaded = 0;
+            length = None;
+        }
+        // A compressed body is longer once decoded, so its length says nothing about progress.
+        let remaining = response
+            .header("Content-Length")
+            .filter(|_| !encoded)
+            .and_then(|length| length.parse::<u64>().ok());
+        length = remaining.map(|remaining| downloaded + remaining).or(length);
+        let before = downloaded;
+        match copy_body(
+            &mut response.into_reader(),
+            &mut file,
+            &mut downloaded,
+            length,
+        ) {
+            Ok(()) => break,
+            Err(e) => {
+                if downloaded > before {
+                    failures = 0;
+                }
+                if failures >= retries {
+                    return Err(e.into());
+                }
+                failures += 1;
+                eprintln!(
+                    "Warning: the download of {} broke off after {} bytes ({}), resuming",
+                    url, downloaded, e
+                );
+            }
+        }
+    }
+    debug!("Downloaded {} bytes from {}", downloaded, url);
+    file.seek(SeekFrom::Start(0))?;
+    Ok(file)
+}
@@ -0,0 +1,152 @@
+//! `--latency-stats`, the distribution of how long after its accept time every quote was captured.
+//!
+//! Latencies are counted in a histogram of nanoseconds instead of being kept, so memory stays the
+//! same however long the capture is. Below 64ns every value has a bucket of its own, and above it
+//! every power of two is split into 64 buckets, so a quantile is off by less than 1 part in 128 of
+//! its value. Negative latencies, from a capture clock behind the exchange's, have buckets of their
+//! own, mirroring the others. The minimum and maximum are exact.
+
+const SUB_BUCKET_BITS: u32 = 6;
+const SUB_BUCKETS: u64 = 1 << SUB_BUCKET_BITS;
+const BUCKETS: usize = ((64 - SUB_BUCKET_BITS + 1) as u64 * SUB_BUCKETS) as usize;
+
+// The bucket of a latency of `magnitude` nanoseconds.
+fn bucket(magnitude: u64) -> usize {
+    if magnitude < SUB_BUCKETS {
+        return magnitude as usize;
+    }
+    let shift = 63 - magnitude.leading_zeros() - SUB_BUCKET_BITS;
+    ((u64::from(shift) + 1) * SUB_BUCKETS + (magnitude >> shift) - SUB_BUCKETS) as usize
+}
+
+// The middle of the values that fall into `bucket`.
+fn bucket_value(bucket: usize) -> u64 {
+    let bucket = bucket as u64;
+    if bucket < SUB_BUCKETS {
+        return bucket;
+    }
+    let shift = bucket / SUB_BUCKETS - 1;
+    let low = (bucket % SUB_BUCKETS + SUB_BUCKETS) << shift;
+    low + (1 << shift) / 2
+}
+
+pub(crate) struct Histogram {
+    negative: Vec<u64>,
+    positive: Vec<u64>,
+    count: u64,
+    min: i64,
+    max: i64,
+}
+
+impl Default for Histogram {
+    fn default() -> Self {
+        Histogram {
+            negative: vec![0; BUCKETS],
+            positive: vec![0; BUCKETS],
+            count: 0,
+            min: i64::MAX,
+            max: i64::MIN,
+        }
+    }
+}
+
+impl Histogram {
+    pub(crate) fn record(&mut self, nanoseconds: i64) {
+        match nanoseconds {
+            n if n < 0 => self.negative[bucket(n.unsigned_abs())] += 1,
+            n => self.positive[bucket(n as u64)] += 1,
+        }
+        self.count += 1;
+        self.min = self.min.min(nanoseconds);
+        self.max = self.max.max(nanoseconds);
+    }
+
+    pub(crate) fn count(&self) -> u64 {
+        self.count
+    }
+
+    pub(crate) fn min(&self) -> Option<i64> {
+        Some(self.min).filter(|_| self.count > 0)
+    }
+
+    pub(crate) fn max(&self) -> Option<i64> {
+        Some(self.max).filter(|_| self.count > 0)
+    }
+
+    // The latency that `quantile` of them are at or below.
+    pub(crate) fn quantile(&self, quantile: f64) -> Option<i64> {
+        if self.count == 0 {
+            return None;
+        }
+        let rank = ((quantile * self.count as f64).ceil() as u64).clamp(1, self.count);
+        // The top buckets reach past what an i64 holds, which the clamp below makes up for.
+        let value = |bucket| bucket_value(bucket).min(i64::MAX as u64) as i64;
+        // The negative buckets go from the largest magnitude down, before all the others.
+        let negative = self
+            .negative
+            .iter()
+            .enumerate()
+            .rev()
+            .map(|(bucket, &count)| (-value(bucket), count));
+        let positive = self
+            .positive
+            .iter()
+            .enumerate()
+            .map(|(bucket, &count)| (value(bucket), count));
+        let mut seen = 0;
+        for (value, count) in negative.chain(positive) {
+            seen += count;
+            if seen >= rank {
+                return Some(value.clamp(self.min, self.max));
+            }
+        }
+        unreachable!("the buckets hold every latency counted")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn buckets_stay_within_their_precision() {
+        for &value in &[
+            0,
+            1,
+            63,
+            64,
+            65,
+            127,
+            128,
+            1_000,
+            999_999,
+            2_997_000_000,
+            u64::MAX,
+        ] {
+            let middle = bucket_value(bucket(value));
+            assert!(
+                middle.abs_diff(value) <= value / 128,
+                "{} in {}",
+                value,
+                middle
+            );
+        }
+        assert_eq!(bucket(u64::MAX), BUCKETS - 1);
+    }
+
+    #[test]
+    fn takes_quantiles_across_the_sign() {
+        let mut histogram = Histogram::default();
+        assert_eq!(histogram.quantile(0.5), None);
+        for latency in -50..50 {
+            histogram.record(latency * 1_000);
+        }
+        assert_eq!(histogram.count(), 100);
+        assert_eq!(histogram.min(), Some(-50_000));
+        assert_eq!(histogram.max(), Some(49_000));
+        let median = histogram.quantile(0.5).unwrap();
+        assert!(median.abs_diff(-1_000) <= 1_000 / 128, "{}", median);
+        let top = histogram.quantile(1.0).unwrap();
+        assert!(top.abs_diff(49_000) <= 49_000 / 128, "{}", top);
+    }
+}
@@ -0,0 +1,9 @@
+//! Format knowledge for the KRX market data feed, kept apart from reading pcap captures so it can
+//! be used on its own, including with `#![no_std]` by turning off the default `std` feature. With
+//! `std`, [`pcap`] finds the quote and trade payloads in a capture too.
+
+#![cfg_attr(not(feature = "std"), no_std)]
+
+pub mod payload;
+#[cfg(feature = "std")]
+pub mod pcap;
@@ -1,275 +1,3790 @@
-use chrono::NaiveDateTime;
-use std::cmp::Ordering;
-use std::collections::BinaryHeap;
+use chrono::{DateTime, Duration, NaiveDate, NaiveDateTime, NaiveTime, Timelike};
+use clap::{ArgAction, CommandFactory, Parser as _, ValueEnum};
+use clap_complete::Shell;
+use log::{debug, LevelFilter};
+use parse_quote::payload::{
+    PayloadError, QuoteData, BOARD_ID_SIZE, END_OF_MESSAGE, ISSUE_SEQUENCE_SIZE,
+    MARKET_STATUS_SIZE, QUOTE_EXT_LEVELS, QUOTE_EXT_PAYLOAD_SIZE, QUOTE_MESSAGE_TYPE,
+    QUOTE_PAYLOAD_SIZE, TRADE_MESSAGE_TYPE, TRADE_PAYLOAD_SIZE,
+};
+use parse_quote::pcap::Endianness::{self, *};
+use parse_quote::pcap::LinkType;
+use parse_quote::pcap::Precision::*;
+use serde_json::json;
+use std::cmp::{Ordering, Reverse};
+use std::collections::{BTreeMap, BinaryHeap, HashMap, HashSet, VecDeque};
+use std::convert::TryInto;
 use std::env;
 use std::error::Error;
 use std::fmt;
-use std::fs::File;
-use std::io::{self, ErrorKind, Read, Seek, SeekFrom, Write};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, ErrorKind, IsTerminal, Read, Seek, SeekFrom, Write};
+use std::mem;
+use std::num::ParseIntError;
+use std::path::{Path, PathBuf};
 use std::process;
-use std::str;
-use Endianness::*;
-use Parser::*;
-use Precision::*;
+use std::str::{self, Utf8Error};
+use std::sync::atomic::{self, AtomicBool, AtomicU32, AtomicU64};
+use std::thread;
+use std::time;
+#[cfg(feature = "count-allocations")]
+mod allocations;
+mod full_sort;
+#[cfg(feature = "http")]
+mod http_input;
+mod latency;
+#[cfg(feature = "parquet")]
+mod parquet_output;
+mod parser;
+
+use parser::Parser::*;
+use parser::{
+    captured_size, decode_u32, link_payload_offset, parse_header, parse_packet, read_exact,
+    read_full, read_pcap_header, record_data_offset, Header, PacketScratch, ParserState,
+    HEADER_SIZE, RECORD_HEADER_SIZE,
+};
 
-const INVALID_INPUT: &str = "Invalid file format";
 const INVALID_TIMESTAMP: &str = "Invalid timestamp format";
-const HEADER_TIME_ZONE_OFFSET: i64 = 4;
-const HEADER_END_OFFSET: i64 = 12;
-const QUOTE_PACKET_OFFSET: i64 = 46;
-const QUOTE_PACKET_SIZE: i64 = 215;
-const BIDS_OFFSET: i64 = 12;
-const PRICE_OFFSET: usize = 5;
-const QUANTITY_OFFSET: usize = 7;
-const QUOTE_ACCEPT_OFFSET: i64 = 50;
-const QUOTE_ACCEPT_SIZE: usize = 8;
+const QUOTE_PACKET_SIZE: i64 = QUOTE_PAYLOAD_SIZE as i64;
+const QUOTE_EXT_PACKET_SIZE: i64 = QUOTE_EXT_PAYLOAD_SIZE as i64;
+const TRADE_PACKET_SIZE: i64 = TRADE_PAYLOAD_SIZE as i64;
 const SECONDS_IN_A_DAY: i64 = 24 * 3_600;
 const KST_OFFSET: i64 = 9 * 3_600;
 const MAX_DIFF: i64 = 3;
-const QUOTE_PACKET_HEADER: &[u8; 5] = b"B6034";
+const MAX_RECORD_SIZE: u32 = 256 * 1024;
+const MAX_TIME_JUMP: i64 = SECONDS_IN_A_DAY;
+const DUMP_SIZE: i64 = 64;
+const SCRATCH_SIZE: usize = 64 * 1024;
+const STUFFING_WINDOW_MS: i64 = 100;
+// How many records have to be read without a single one parsing before the framing is suspected.
+const FRAMING_HINT_RECORDS: u64 = 10;
+const RETRY_BACKOFF: time::Duration = time::Duration::from_millis(10);
+const WATCH_INTERVAL: time::Duration = time::Duration::from_millis(100);
+const GREEN: &[u8] = b"\x1b[32m";
+const RED: &[u8] = b"\x1b[31m";
+const RESET: &[u8] = b"\x1b[0m";
+
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+static MESSAGES: AtomicU64 = AtomicU64::new(0);
+static DUMPED: AtomicU64 = AtomicU64::new(0);
+static READ_RETRIES: AtomicU32 = AtomicU32::new(0);
+// Under `--bench`, reads are timed and the nanoseconds spent in them added up here.
+static TIME_READS: AtomicBool = AtomicBool::new(false);
+static READ_NANOS: AtomicU64 = AtomicU64::new(0);
 
-#[derive(Eq, PartialEq)]
+#[derive(Clone, Eq, PartialEq)]
 struct QuotePacket {
+    offset: u64,
+    number: u64,
+    message_type: [u8; 5],
     time_stamp: NaiveDateTime,
     quote_accept_time: NaiveDateTime,
     issue_code: [u8; 12],
-    bids: [(u32, u32); 5],
-    asks: [(u32, u32); 5],
+    issue_sequence: [u8; ISSUE_SEQUENCE_SIZE],
+    market_status: [u8; MARKET_STATUS_SIZE],
+    // Room for the book of an extended quote, of which the first `levels` on each side are used.
+    bids: [(u32, u32); QUOTE_EXT_LEVELS],
+    asks: [(u32, u32); QUOTE_EXT_LEVELS],
+    levels: usize,
+    // The moving average of the mid price up to this quote under `--ema`, rounded.
+    ema: Option<i64>,
+}
+
+impl QuoteData for QuotePacket {
+    fn bids(&self) -> &[(u32, u32)] {
+        &self.bids[..self.levels]
+    }
+
+    fn asks(&self) -> &[(u32, u32)] {
+        &self.asks[..self.levels]
+    }
+}
+
+impl QuotePacket {
+    // Takes the book of a parsed quote, whichever variant it is.
+    fn set_book(&mut self, quote: &impl QuoteData) {
+        self.levels = quote.bids().len();
+        self.bids[..self.levels].copy_from_slice(quote.bids());
+        self.asks[..self.levels].copy_from_slice(quote.asks());
+    }
+
+    // The sum of the best bid and ask prices, twice the mid price, or `None` if a side is empty.
+    fn double_mid_price(&self) -> Option<u64> {
+        let (bid, ask) = (self.bids[0].1, self.asks[0].1);
+        if bid == 0 || ask == 0 {
+            None
+        } else {
+            Some(u64::from(bid) + u64::from(ask))
+        }
+    }
+
+    // How many bid and ask levels aren't empty.
+    fn populated_levels(&self) -> (usize, usize) {
+        let populated =
+            |levels: &[(u32, u32)]| levels.iter().filter(|&&level| level != (0, 0)).count();
+        (populated(self.bids()), populated(self.asks()))
+    }
+
+    // Whether the best bid reaches the best ask, which happens during auctions or with a bad feed.
+    // A side without a price has no best level, so it can't cross.
+    fn crossed(&self) -> Option<&'static str> {
+        let (bid, ask) = (self.bids[0].1, self.asks[0].1);
+        if bid == 0 || ask == 0 || bid < ask {
+            None
+        } else if bid == ask {
+            Some("LOCKED")
+        } else {
+            Some("CROSSED")
+        }
+    }
+
+    // The timestamps and issue code in front are written by `write_record`, which knows how to
+    // render them.
+    fn write_levels(
+        &self,
+        line: &mut Vec<u8>,
+        empty_as: EmptyAs,
+        field_order: FieldOrder,
+        cumulative: bool,
+        printed: [bool; QUOTE_EXT_LEVELS],
+        colored: bool,
+    ) {
+        // Omitting empty levels drops the deepest ones on either side.
+        let depth = |levels: &[(u32, u32)]| match empty_as {
+            EmptyAs::Omit => levels
+                .iter()
+                .rposition(|&level| level != (0, 0))
+                .map_or(0, |i| i + 1),
+            EmptyAs::Zero | EmptyAs::Dash => levels.len(),
+        };
+        let bids = &self.bids()[..depth(self.bids())];
+        let asks = &self.asks()[..depth(self.asks())];
+        // Each side is stored best level first, and is printed reversed when the flag is set. The
+        // best level is colored green for bids and red for asks.
+        let sides = match field_order {
+            FieldOrder::Book => [(bids, true, GREEN), (asks, false, RED)],
+            FieldOrder::Descending => [(asks, true, RED), (bids, false, GREEN)],
+            FieldOrder::BestFirst => [(bids, false, GREEN), (asks, false, RED)],
+            FieldOrder::AsksFirst => [(asks, false, RED), (bids, false, GREEN)],
+        };
+        for &(levels, reversed, color) in sides.iter() {
+            for i in 0..levels.len() {
+                let level = if reversed { levels.len() - 1 - i } else { i };
+                if !printed[level] {
+                    continue;
+                }
+                // The color goes after the space in front of the level.
+                let start = line.len() + 1;
+                let (quantity, price) = levels[level];
+                match empty_as {
+                    EmptyAs::Dash if (quantity, price) == (0, 0) => line.extend_from_slice(b" -"),
+                    // Ten 7-digit quantities add up to less than 100 million, so this can't overflow.
+                    _ if cumulative => {
+                        let total: u32 =
+                            levels[..=level].iter().map(|&(quantity, _)| quantity).sum();
+                        line.extend_from_slice(b" C");
+                        push_decimal(line, u64::from(total));
+                        line.push(b'@');
+                        push_decimal(line, u64::from(price));
+                    }
+                    _ => push_level(line, quantity, price),
+                }
+                if colored && level == 0 {
+                    line.splice(start..start, color.iter().copied());
+                    line.extend_from_slice(RESET);
+                }
+            }
+        }
+    }
+}
+
+#[derive(Clone, Eq, PartialEq)]
+struct TradePacket {
+    offset: u64,
+    number: u64,
+    message_type: [u8; 5],
+    time_stamp: NaiveDateTime,
+    trade_time: NaiveDateTime,
+    issue_code: [u8; 12],
+    issue_sequence: [u8; ISSUE_SEQUENCE_SIZE],
+    board_id: [u8; BOARD_ID_SIZE],
+    price: u32,
+    quantity: u32,
+    aggressor: Option<Aggressor>,
+}
+
+// Which side initiated a trade, inferred from the best bid and ask of the latest quote for the
+// issue: a trade at the ask was a buyer lifting the offer and one at the bid a seller hitting it.
+#[derive(Copy, Clone, Eq, PartialEq)]
+enum Aggressor {
+    Buyer,
+    Seller,
+    Unknown,
+}
+
+impl Aggressor {
+    fn as_bytes(self) -> &'static [u8] {
+        match self {
+            Aggressor::Buyer => b"buy",
+            Aggressor::Seller => b"sell",
+            Aggressor::Unknown => b"-",
+        }
+    }
+}
+
+impl TradePacket {
+    fn write_fill(&self, line: &mut Vec<u8>) {
+        push_level(line, self.quantity, self.price);
+        if let Some(aggressor) = self.aggressor {
+            line.push(b' ');
+            line.extend_from_slice(aggressor.as_bytes());
+        }
+    }
+}
+
+// Output lines are put together by hand instead of with `write!`, which would otherwise take most
+// of the time spent on every packet once the output is buffered.
+
+// Appends the decimal digits of `n`.
+fn push_decimal(line: &mut Vec<u8>, mut n: u64) {
+    let mut digits = [0; 20];
+    let mut start = digits.len();
+    loop {
+        start -= 1;
+        digits[start] = b'0' + (n % 10) as u8;
+        n /= 10;
+        if n == 0 {
+            break;
+        }
+    }
+    line.extend_from_slice(&digits[start..]);
+}
+
+// Formats `n` with a comma between every three digits.
+fn grouped(n: u64) -> String {
+    let digits = n.to_string();
+    let mut grouped = String::with_capacity(digits.len() + digits.len() / 3);
+    for (i, digit) in digits.chars().enumerate() {
+        if i > 0 && (digits.len() - i).is_multiple_of(3) {
+            grouped.push(',');
+        }
+        grouped.push(digit);
+    }
+    grouped
+}
+
+fn push_signed(line: &mut Vec<u8>, n: i64) {
+    if n < 0 {
+        line.push(b'-');
+    }
+    push_decimal(line, n.unsigned_abs());
+}
+
+// Appends `n` zero padded to `width` digits, of which it mustn't have more.
+fn push_padded(line: &mut Vec<u8>, mut n: u32, width: usize) {
+    let start = line.len();
+    line.resize(start + width, b'0');
+    for digit in line[start..].iter_mut().rev() {
+        *digit = b'0' + (n % 10) as u8;
+        n /= 10;
+    }
+}
+
+// Appends a book level or a fill as ` quantity@price`.
+fn push_level(line: &mut Vec<u8>, quantity: u32, price: u32) {
+    line.push(b' ');
+    push_decimal(line, u64::from(quantity));
+    line.push(b'@');
+    push_decimal(line, u64::from(price));
+}
+
+// The line being written by `write_record`, reused from one record to the next, and the date part
+// of the last formatted time, which only changes at midnight.
+#[derive(Default)]
+struct LineBuffer {
+    line: Vec<u8>,
+    date: Option<(NaiveDate, Vec<u8>)>,
+}
+
+impl LineBuffer {
+    // Appends the time like `Time` displays it, which is how chrono displays it for dates.
+    fn push_time(&mut self, time: NaiveDateTime, epoch: Option<Epoch>) {
+        let line = &mut self.line;
+        match epoch {
+            None => {}
+            Some(Epoch::S) => return push_signed(line, time.and_utc().timestamp()),
+            Some(Epoch::Ms) => return push_signed(line, time.and_utc().timestamp_millis()),
+            Some(Epoch::Us) => return push_signed(line, timestamp_nanos(time) / 1_000),
+            Some(Epoch::Ns) => return push_signed(line, timestamp_nanos(time)),
+        }
+        let date = time.date();
+        if self.date.as_ref().is_none_or(|(cached, _)| *cached != date) {
+            self.date = Some((date, format!("{} ", date).into_bytes()));
+        }
+        if let Some((_, formatted)) = &self.date {
+            line.extend_from_slice(formatted);
+        }
+        // A leap second is stored as an overlong fraction of the second before it.
+        let (second, nanosecond) = match time.nanosecond() {
+            nanosecond if nanosecond >= 1_000_000_000 => (60, nanosecond - 1_000_000_000),
+            nanosecond => (time.second(), nanosecond),
+        };
+        push_padded(line, time.hour(), 2);
+        line.push(b':');
+        push_padded(line, time.minute(), 2);
+        line.push(b':');
+        push_padded(line, second, 2);
+        // Like chrono, print the fraction in as few groups of three digits as it takes.
+        let (fraction, width) = match nanosecond {
+            0 => return,
+            nanosecond if nanosecond % 1_000_000 == 0 => (nanosecond / 1_000_000, 3),
+            nanosecond if nanosecond % 1_000 == 0 => (nanosecond / 1_000, 6),
+            nanosecond => (nanosecond, 9),
+        };
+        line.push(b'.');
+        push_padded(line, fraction, width);
+    }
+}
+
+#[derive(Copy, Clone, ValueEnum)]
+enum EmptyAs {
+    Zero,
+    Dash,
+    Omit,
+}
+
+#[derive(Copy, Clone, PartialEq, ValueEnum)]
+enum SortKey {
+    Accept,
+    Packet,
+    IssueAccept,
+}
+
+#[derive(Copy, Clone, ValueEnum)]
+enum FieldOrder {
+    Book,
+    Descending,
+    BestFirst,
+    AsksFirst,
+}
+
+#[derive(Copy, Clone, ValueEnum)]
+enum Epoch {
+    S,
+    Ms,
+    Us,
+    Ns,
+}
+
+// A point in time as printed, either formatted or as a Unix epoch count in the chosen unit.
+struct Time(NaiveDateTime, Option<Epoch>);
+
+// Times are kept as naive UTC, while chrono converts from and to Unix timestamps through `DateTime`.
+fn naive_from_timestamp(seconds: i64, nanoseconds: u32) -> Option<NaiveDateTime> {
+    DateTime::from_timestamp(seconds, nanoseconds).map(|time| time.naive_utc())
+}
+
+fn timestamp_nanos(time: NaiveDateTime) -> i64 {
+    time.and_utc()
+        .timestamp_nanos_opt()
+        .expect("time within the range of nanosecond timestamps")
+}
+
+impl fmt::Display for Time {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let Time(time, epoch) = *self;
+        match epoch {
+            None => write!(f, "{}", time),
+            Some(Epoch::S) => write!(f, "{}", time.and_utc().timestamp()),
+            Some(Epoch::Ms) => write!(f, "{}", time.and_utc().timestamp_millis()),
+            Some(Epoch::Us) => write!(f, "{}", timestamp_nanos(time) / 1_000),
+            Some(Epoch::Ns) => write!(f, "{}", timestamp_nanos(time)),
+        }
+    }
+}
+
+// Everything we know how to parse out of a capture. Records are ordered by the time the exchange
+// says the event happened, which for quotes is the accept time and for trades the trade time, then
+// by capture timestamp and then by packet number, which goes up as the capture is read. That makes
+// it a total order for the records of one capture, so -r prints ties in the order they arrived in
+// rather than however the buffer happens to hold them. Earlier records compare greater.
+#[derive(Clone, Eq, PartialEq)]
+enum Record {
+    Quote(QuotePacket),
+    Trade(TradePacket),
+}
+
+impl Record {
+    fn offset(&self) -> u64 {
+        match self {
+            Record::Quote(quote_packet) => quote_packet.offset,
+            Record::Trade(trade_packet) => trade_packet.offset,
+        }
+    }
+
+    fn number(&self) -> u64 {
+        match self {
+            Record::Quote(quote_packet) => quote_packet.number,
+            Record::Trade(trade_packet) => trade_packet.number,
+        }
+    }
+
+    fn number_mut(&mut self) -> &mut u64 {
+        match self {
+            Record::Quote(quote_packet) => &mut quote_packet.number,
+            Record::Trade(trade_packet) => &mut trade_packet.number,
+        }
+    }
+
+    fn time_stamp(&self) -> NaiveDateTime {
+        match self {
+            Record::Quote(quote_packet) => quote_packet.time_stamp,
+            Record::Trade(trade_packet) => trade_packet.time_stamp,
+        }
+    }
+
+    fn issue_code(&self) -> &[u8; 12] {
+        match self {
+            Record::Quote(quote_packet) => &quote_packet.issue_code,
+            Record::Trade(trade_packet) => &trade_packet.issue_code,
+        }
+    }
+
+    // The issue code without the spaces padding it to its fixed width.
+    fn issue_code_str(&self) -> &str {
+        // Issue codes are checked to be printable ASCII when they're parsed.
+        str::from_utf8(self.issue_code())
+            .unwrap_or_default()
+            .trim_end_matches(' ')
+    }
+
+    // Whether both records carry the same exchange message, as when two taps capture one feed.
+    fn same_message(&self, other: &Record) -> bool {
+        match (self, other) {
+            (Record::Quote(a), Record::Quote(b)) => {
+                a.message_type == b.message_type
+                    && a.quote_accept_time == b.quote_accept_time
+                    && a.issue_code == b.issue_code
+                    && a.issue_sequence == b.issue_sequence
+                    && a.market_status == b.market_status
+                    && a.bids() == b.bids()
+                    && a.asks() == b.asks()
+            }
+            (Record::Trade(a), Record::Trade(b)) => {
+                a.message_type == b.message_type
+                    && a.trade_time == b.trade_time
+                    && a.issue_code == b.issue_code
+                    && a.issue_sequence == b.issue_sequence
+                    && a.board_id == b.board_id
+                    && a.price == b.price
+                    && a.quantity == b.quantity
+            }
+            _ => false,
+        }
+    }
+
+    fn event_time(&self) -> NaiveDateTime {
+        match self {
+            Record::Quote(quote_packet) => quote_packet.quote_accept_time,
+            Record::Trade(trade_packet) => trade_packet.trade_time,
+        }
+    }
 }
 
-impl Ord for QuotePacket {
+impl Ord for Record {
     fn cmp(&self, other: &Self) -> Ordering {
-        self.quote_accept_time
-            .cmp(&other.quote_accept_time)
-            .reverse()
+        let key = |record: &Record| (record.event_time(), record.time_stamp(), record.number());
+        key(self).cmp(&key(other)).reverse()
     }
 }
 
-impl PartialOrd for QuotePacket {
+impl PartialOrd for Record {
     fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
         Some(self.cmp(other))
     }
 }
 
-impl fmt::Display for QuotePacket {
+// Violations of the quote packet format, each carrying the absolute file offset of the offending
+// field. `PriceLevel` and `Timestamp` are always checked for, `NonMonotone` under
+// `--check-monotone` and `ReorderBuffer` under `--max-buffer`, while the rest are only checked for
+// under `--strict`.
+#[derive(Debug)]
+enum ParseError {
+    RecordLength {
+        offset: u64,
+        length: i64,
+        expected: i64,
+    },
+    EndOfMessage {
+        offset: u64,
+        byte: u8,
+    },
+    NonDigit {
+        offset: u64,
+        field: String,
+    },
+    AcceptTimeRange {
+        offset: u64,
+        name: String,
+        field: &'static str,
+        value: u32,
+    },
+    IssueCode {
+        offset: u64,
+        bytes: [u8; 12],
+    },
+    BadAcceptTime {
+        offset: u64,
+        name: String,
+        bytes: [u8; 8],
+    },
+    PriceLevel {
+        offset: u64,
+        field: String,
+        bytes: Vec<u8>,
+    },
+    NonMonotone {
+        offset: u64,
+        name: &'static str,
+        current: NaiveDateTime,
+        previous: NaiveDateTime,
+        previous_offset: u64,
+    },
+    ReorderBuffer {
+        offset: u64,
+        max: usize,
+    },
+    Truncated {
+        offset: u64,
+        captured: u32,
+        original: u32,
+    },
+    Timestamp {
+        offset: u64,
+        seconds: i64,
+        nanoseconds: u64,
+    },
+}
+
+impl fmt::Display for ParseError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        use fmt::Write;
-        write!(f, "{} {} ", self.time_stamp, self.quote_accept_time)?;
-        for &c in self.issue_code.iter() {
-            f.write_char(c as char)?;
-        }
-        for &(quantity, price) in self.bids.iter().rev() {
-            write!(f, " {}@{}", quantity, price)?;
+        match self {
+            ParseError::RecordLength {
+                offset,
+                length,
+                expected,
+            } => write!(
+                f,
+                "Quote record at offset {:#x} captured {} bytes instead of {}",
+                offset, length, expected
+            ),
+            ParseError::EndOfMessage { offset, byte } => write!(
+                f,
+                "End-of-message byte at offset {:#x} is {:#04x} instead of {:#04x}",
+                offset, byte, END_OF_MESSAGE
+            ),
+            ParseError::NonDigit { offset, field } => {
+                write!(f, "Non-digit byte in {} at offset {:#x}", field, offset)
+            }
+            ParseError::AcceptTimeRange {
+                offset,
+                name,
+                field,
+                value,
+            } => {
+                let mut name = name.clone();
+                name[..1].make_ascii_uppercase();
+                write!(
+                    f,
+                    "{} {} {} at offset {:#x} is out of range",
+                    name, field, value, offset
+                )
+            }
+            ParseError::IssueCode { offset, bytes } => write!(
+                f,
+                "Issue code at offset {:#x} is not printable ASCII: {:02x?}",
+                offset, bytes
+            ),
+            ParseError::BadAcceptTime {
+                offset,
+                name,
+                bytes,
+            } => write!(
+                f,
+                "Malformed {} at offset {:#x} from {:02x?}, the capture may use another payload \
+                 layout",
+                name, offset, bytes
+            ),
+            ParseError::PriceLevel {
+                offset,
+                field,
+                bytes,
+            } => write!(
+                f,
+                "Can't parse {} at offset {:#x} from {:02x?}",
+                field, offset, bytes
+            ),
+            ParseError::NonMonotone {
+                offset,
+                name,
+                current,
+                previous,
+                previous_offset,
+            } => write!(
+                f,
+                "{} went backward at offset {:#x}: {} after {} at offset {:#x}",
+                name, offset, current, previous, previous_offset
+            ),
+            ParseError::ReorderBuffer { offset, max } => write!(
+                f,
+                "More than {} packets buffered for reordering at offset {:#x}, the accept times \
+                 may never become final",
+                max, offset
+            ),
+            ParseError::Truncated {
+                offset,
+                captured,
+                original,
+            } => write!(
+                f,
+                "Record at offset {:#x} captured {} of its {} bytes",
+                offset, captured, original
+            ),
+            ParseError::Timestamp {
+                offset,
+                seconds,
+                nanoseconds,
+            } => write!(
+                f,
+                "Timestamp of {} seconds and {} nanoseconds at offset {:#x} is out of range",
+                seconds, nanoseconds, offset
+            ),
         }
-        for &(quantity, price) in self.asks.iter() {
-            write!(f, " {}@{}", quantity, price)?;
+    }
+}
+
+impl Error for ParseError {}
+
+impl ParseError {
+    fn kind(&self) -> &'static str {
+        match self {
+            ParseError::RecordLength { .. } => "record_length",
+            ParseError::EndOfMessage { .. } => "end_of_message",
+            ParseError::NonDigit { .. } => "non_digit",
+            ParseError::AcceptTimeRange { .. } => "accept_time_range",
+            ParseError::IssueCode { .. } => "issue_code",
+            ParseError::BadAcceptTime { .. } => "bad_accept_time",
+            ParseError::PriceLevel { .. } => "price_level",
+            ParseError::NonMonotone { .. } => "non_monotone",
+            ParseError::ReorderBuffer { .. } => "reorder_buffer",
+            ParseError::Truncated { .. } => "truncated",
+            ParseError::Timestamp { .. } => "timestamp",
         }
-        Ok(())
     }
 }
 
-#[derive(Copy, Clone)]
-enum Endianness {
-    LittleEndian,
-    BigEndian,
+// An error tied to the packet record at `offset`.
+#[derive(Debug)]
+struct PacketError {
+    offset: u64,
+    source: Box<dyn Error>,
+}
+
+impl fmt::Display for PacketError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} (packet at offset {:#x})", self.source, self.offset)
+    }
 }
 
-#[derive(Copy, Clone)]
-enum Precision {
-    Microsecond = 1_000,
-    Nanosecond = 1,
+impl Error for PacketError {}
+
+fn error_kind(e: &(dyn Error + 'static)) -> &'static str {
+    if let Some(e) = e.downcast_ref::<ParseError>() {
+        e.kind()
+    } else if e.is::<io::Error>() {
+        "io"
+    } else if e.is::<ParseIntError>() {
+        "invalid_number"
+    } else if e.is::<Utf8Error>() {
+        "invalid_utf8"
+    } else {
+        "invalid_input"
+    }
 }
 
-fn read_u32(file: &mut File, end: Endianness) -> Result<u32, io::Error> {
-    let mut buf = [0; 4];
-    file.read_exact(&mut buf)?;
-    Ok(match end {
-        LittleEndian => u32::from_le_bytes(buf),
-        BigEndian => u32::from_be_bytes(buf),
+fn error_json(e: &(dyn Error + 'static), offset: Option<u64>) -> serde_json::Value {
+    json!({
+        "kind": error_kind(e),
+        "message": e.to_string(),
+        "offset": offset,
     })
 }
 
-fn parse_header(file: &mut File) -> Result<(Endianness, Precision, i64), Box<dyn Error>> {
-    let mut buf = [0; 4];
-    file.read_exact(&mut buf)?;
-    let (end, precision) = match buf {
-        [0xD4, 0xC3, 0xB2, 0xA1] => (LittleEndian, Microsecond),
-        [0xA1, 0xB2, 0xC3, 0xD4] => (BigEndian, Microsecond),
-        [0x4D, 0x3C, 0xB2, 0xA1] => (LittleEndian, Nanosecond),
-        [0xA1, 0xB2, 0x3C, 0x4D] => (BigEndian, Nanosecond),
-        _ => return Err(INVALID_INPUT.into()),
-    };
-    file.seek(SeekFrom::Current(HEADER_TIME_ZONE_OFFSET))?;
-    let this_zone = i64::from(read_u32(file, end)?);
-    file.seek(SeekFrom::Current(HEADER_END_OFFSET))?;
-    Ok((end, precision, this_zone))
+// Turns an error in `payload`, which starts at `start` in the file, into one with its offset in the
+// file.
+fn payload_error(start: u64, payload: &[u8], e: PayloadError) -> Box<dyn Error> {
+    let offset = |offset: usize| start + offset as u64;
+    match e {
+        PayloadError::BadTime {
+            offset: field_start,
+            field,
+            bytes,
+        } => ParseError::BadAcceptTime {
+            offset: offset(field_start),
+            name: field.to_string(),
+            bytes,
+        }
+        .into(),
+        PayloadError::Level {
+            offset: field_start,
+            len,
+            field,
+        } => ParseError::PriceLevel {
+            offset: offset(field_start),
+            field: field.to_string(),
+            bytes: payload[field_start..field_start + len].to_vec(),
+        }
+        .into(),
+        PayloadError::NonDigit {
+            offset: field_start,
+            field,
+        } => ParseError::NonDigit {
+            offset: offset(field_start),
+            field: field.to_string(),
+        }
+        .into(),
+        PayloadError::TimeRange {
+            offset: field_start,
+            field,
+            unit,
+            value,
+        } => ParseError::AcceptTimeRange {
+            offset: offset(field_start),
+            name: field.to_string(),
+            field: unit,
+            value,
+        }
+        .into(),
+        PayloadError::IssueCode {
+            offset: field_start,
+            bytes,
+        } => ParseError::IssueCode {
+            offset: offset(field_start),
+            bytes,
+        }
+        .into(),
+        PayloadError::EndOfMessage {
+            offset: field_start,
+            byte,
+        } => ParseError::EndOfMessage {
+            offset: offset(field_start),
+            byte,
+        }
+        .into(),
+    }
+}
+
+// The time and offset of the packet before, for checking that a time never goes backward from one
+// packet to the next. Every packet becomes the one before the next, backward or not, so a single
+// late packet is reported once rather than along with everything after it.
+#[derive(Default)]
+struct MonotoneCheck {
+    previous: Option<(NaiveDateTime, u64)>,
+}
+
+impl MonotoneCheck {
+    // Checks `current`, the time called `name` of the packet at `offset`, against the packet
+    // before, returning the time and offset of that one if `current` doesn't go back from it.
+    fn check(
+        &mut self,
+        name: &'static str,
+        current: NaiveDateTime,
+        offset: u64,
+    ) -> Result<Option<(NaiveDateTime, u64)>, ParseError> {
+        match self.previous.replace((current, offset)) {
+            Some((previous, previous_offset)) if current < previous => {
+                Err(ParseError::NonMonotone {
+                    offset,
+                    name,
+                    current,
+                    previous,
+                    previous_offset,
+                })
+            }
+            previous => Ok(previous),
+        }
+    }
 }
 
-fn parse_bids_or_asks(file: &mut File, bids: &mut [(u32, u32); 5]) -> Result<(), Box<dyn Error>> {
-    let mut buf = [0; PRICE_OFFSET + QUANTITY_OFFSET];
-    for (quantity, price) in bids {
-        file.read_exact(&mut buf)?;
-        let str_buf = str::from_utf8(&buf)?;
-        *price = str_buf[0..PRICE_OFFSET].parse()?;
-        *quantity = str_buf[PRICE_OFFSET..].parse()?;
+// Reports a record that breaks the ordering `--check-monotone` expects, which is fatal under
+// `--strict`.
+fn non_monotone(options: &Options, e: ParseError) -> Result<(), Box<dyn Error>> {
+    if options.strict {
+        return Err(e.into());
+    }
+    if within_message_limit(options) {
+        match options.error_format {
+            ErrorFormat::Text => eprintln!("WARN: {}", e),
+            ErrorFormat::Json => eprintln!("{}", error_json(&e, None)),
+        }
     }
     Ok(())
 }
 
-fn parse_quote_accept_time(
-    file: &mut File,
-    time_stamp: i64,
-) -> Result<NaiveDateTime, Box<dyn Error>> {
-    let mut buf = [0; QUOTE_ACCEPT_SIZE];
-    file.read_exact(&mut buf)?;
-    let str_buf = str::from_utf8(&buf)?;
-    let seconds = str_buf[0..2].parse::<i64>()? * 3_600
-        + str_buf[2..4].parse::<i64>()? * 60
-        + str_buf[4..6].parse::<i64>()?;
-    let nanoseconds = str_buf[7..8].parse::<u32>()? * 1_000_000;
-    // We converted the timestamp to UTC, while the market feed data is in KST. We'll also convert
-    // it to UTC and calculate the date accounting for the subtle difference in time that leads to
-    // a few edge cases when for instance the quote accept time is 2011-02-16 8:59:59 and the
-    // timestamp is 2011-02-16 0:00:00 leading to the date warping to 2011-02-15 23:59:59.
-    let remainder = time_stamp % SECONDS_IN_A_DAY;
-    let difference = (SECONDS_IN_A_DAY - KST_OFFSET + seconds) % SECONDS_IN_A_DAY - remainder;
-    NaiveDateTime::from_timestamp_opt(
-        if difference.abs() > MAX_DIFF {
-            if difference < 0 {
-                time_stamp + difference + SECONDS_IN_A_DAY
-            } else {
-                time_stamp + difference - SECONDS_IN_A_DAY
+// Counts diagnostic lines against `--max-warnings`, announcing once when the limit is reached.
+fn within_message_limit(options: &Options) -> bool {
+    let printed = MESSAGES.fetch_add(1, atomic::Ordering::SeqCst);
+    match options.max_warnings {
+        Some(max) if printed == max => {
+            eprintln!(
+                "Reached --max-warnings {}, suppressing further messages",
+                max
+            );
+            false
+        }
+        Some(max) => printed < max,
+        None => true,
+    }
+}
+
+#[derive(Default)]
+struct Summary {
+    // Every message of a datagram holding several counts as a record of its own, so that each one
+    // is either valid or skipped.
+    records: u64,
+    quotes: u64,
+    trades: u64,
+    crossed: u64,
+    duplicates: u64,
+    // Records that aren't quote or trade packets, apart from the malformed ones.
+    invalid: u64,
+    // The fewest and most bytes captured of any of them.
+    invalid_sizes: Option<(u32, u32)>,
+    // Those of them the capture kept only the start of, and how many of those were cut short of a
+    // whole quote packet by the snaplen.
+    truncated: u64,
+    short_quotes: u64,
+    malformed: u64,
+    // Those of them that didn't end with the end-of-message byte.
+    framing: u64,
+    discarded: u64,
+    // Bytes after the last quote message of datagrams holding several.
+    trailing: u64,
+    interrupted: bool,
+    flushed: usize,
+    // The most packets buffered for reordering at once, and how many were left at the end.
+    reorder_buffer: Option<(usize, usize)>,
+}
+
+impl Summary {
+    fn malformed(
+        &mut self,
+        offset: u64,
+        e: impl FnOnce() -> Box<dyn Error>,
+        options: &Options,
+    ) -> Result<(), Box<dyn Error>> {
+        if options.strict {
+            let source = e();
+            return Err(PacketError { offset, source }.into());
+        }
+        if options.warn && within_message_limit(options) {
+            let e = e();
+            match options.error_format {
+                ErrorFormat::Text => eprintln!(
+                    "Warning: skipped malformed packet at offset {:#x}: {}",
+                    offset, e
+                ),
+                ErrorFormat::Json => eprintln!("{}", error_json(&*e, Some(offset))),
             }
-        } else {
-            time_stamp + difference
-        },
-        nanoseconds,
-    )
-    .ok_or_else(|| INVALID_TIMESTAMP.into())
-}
-
-enum Parser {
-    Valid(QuotePacket),
-    Invalid,
-    EOF,
-}
-
-fn parse_packet(
-    file: &mut File,
-    end: Endianness,
-    precision: Precision,
-    this_zone: i64,
-) -> Result<Parser, Box<dyn Error>> {
-    let seconds = match read_u32(file, end) {
-        // Converting the packet timestamp to UTC
-        Ok(seconds) => i64::from(seconds) + this_zone,
-        Err(e) => {
-            return if e.kind() == ErrorKind::UnexpectedEof {
-                Ok(EOF)
-            } else {
-                Err(e.into())
+        }
+        self.malformed += 1;
+        Ok(())
+    }
+
+    // Prints what happened to the records that didn't make it to the output, and under
+    // `--dry-run` whether the capture parsed cleanly, failing when it didn't.
+    fn report(&self, options: &Options) -> Result<(), Box<dyn Error>> {
+        if options.print_skipped_ratio {
+            let skipped = self.invalid + self.malformed;
+            let ratio = match self.records {
+                0 => 0.0,
+                records => skipped as f64 * 100.0 / records as f64,
+            };
+            eprintln!(
+                "Parsed: {} valid, {} invalid ({:.1}% skipped)",
+                grouped(self.records.saturating_sub(skipped)),
+                grouped(skipped),
+                ratio
+            );
+        }
+        // When nothing in a sizable capture parses, its packets are most likely framed differently
+        // than we expect, which would otherwise only show up as empty output.
+        if self.records >= FRAMING_HINT_RECORDS
+            && self.invalid == self.records
+            && self.truncated == 0
+        {
+            let sizes = match self.invalid_sizes {
+                Some((min, max)) if min == max => format!("{} bytes", min),
+                Some((min, max)) => format!("{} to {} bytes", min, max),
+                None => unreachable!("invalid records have a size"),
             };
+            eprintln!(
+                "Warning: none of the {} records ({} each) held a quote or trade packet. The \
+                 capture may encapsulate its packets differently from what the payload offset and \
+                 size assume; --scan-marker looks for quotes anywhere in a record and \
+                 --payload-size changes the size expected",
+                self.records, sizes
+            );
+        }
+        if self.truncated > 0 {
+            eprintln!(
+                "Skipped {} records truncated during capture",
+                self.truncated
+            );
+        }
+        if self.short_quotes > 0 {
+            eprintln!(
+                "Skipped {} records captured shorter than a quote packet",
+                self.short_quotes
+            );
+        }
+        if self.malformed > 0 {
+            eprintln!("Skipped {} malformed packets", self.malformed);
+        }
+        if self.framing > 0 {
+            eprintln!(
+                "{} of them didn't end with the end-of-message byte, so the capture may be of \
+                 another feed variant or corrupted",
+                self.framing
+            );
+        }
+        if self.crossed > 0 {
+            eprintln!("Dropped {} crossed or locked quotes", self.crossed);
+        }
+        if self.duplicates > 0 {
+            eprintln!("Dropped {} duplicate packets", self.duplicates);
+        }
+        if self.discarded > 0 {
+            eprintln!("Discarded {} bytes while resynchronizing", self.discarded);
+        }
+        if self.trailing > 0 {
+            eprintln!(
+                "Skipped {} bytes trailing the quote messages of datagrams",
+                self.trailing
+            );
+        }
+        if self.interrupted {
+            eprintln!("Interrupted: flushed {} buffered packets", self.flushed);
+        }
+        if let Some((high_water, left)) = self.reorder_buffer {
+            eprintln!(
+                "Reorder buffer peaked at {} packets, {} left at the end",
+                high_water, left
+            );
+        }
+        if options.dry_run {
+            if self.malformed > 0 || self.discarded > 0 {
+                return Err(format!(
+                    "Dry run failed: {} of {} records malformed, {} bytes discarded",
+                    grouped(self.malformed),
+                    grouped(self.records),
+                    grouped(self.discarded)
+                )
+                .into());
+            }
+            eprintln!(
+                "Dry run passed: {} records, {} quotes, {} trades, {} skipped",
+                grouped(self.records),
+                grouped(self.quotes),
+                grouped(self.trades),
+                grouped(self.invalid)
+            );
         }
+        Ok(())
+    }
+}
+
+fn parse_magic(magic: &str) -> Result<[u8; 5], String> {
+    magic
+        .as_bytes()
+        .try_into()
+        .ok()
+        .filter(|magic: &[u8; 5]| magic.iter().all(u8::is_ascii_graphic))
+        .ok_or_else(|| "must be exactly 5 ASCII characters".to_string())
+}
+
+fn parse_accept_tz(accept_tz: &str) -> Result<i64, String> {
+    let invalid = || "must be KST, UTC or an offset like +09:00".to_string();
+    let (sign, offset) = match accept_tz {
+        "KST" => return Ok(KST_OFFSET),
+        "UTC" => return Ok(0),
+        _ if accept_tz.starts_with('+') => (1, &accept_tz[1..]),
+        _ if accept_tz.starts_with('-') => (-1, &accept_tz[1..]),
+        _ => return Err(invalid()),
     };
-    let date = NaiveDateTime::from_timestamp_opt(seconds, read_u32(file, end)? * precision as u32)
-        .ok_or(INVALID_TIMESTAMP)?;
-    let packet_size = i64::from(read_u32(file, end)?) + 4;
-    if packet_size != QUOTE_PACKET_SIZE + QUOTE_PACKET_OFFSET {
-        file.seek(SeekFrom::Current(packet_size))?;
-        return Ok(Invalid);
-    }
-    file.seek(SeekFrom::Current(QUOTE_PACKET_OFFSET))?;
-    let mut buf = [0; 5];
-    file.read_exact(&mut buf)?;
-    if !buf.eq(QUOTE_PACKET_HEADER) {
-        file.seek(SeekFrom::Current(QUOTE_PACKET_SIZE - 5))?;
-        return Ok(Invalid);
-    }
-    let mut quote_packet: QuotePacket = QuotePacket {
-        time_stamp: date,
-        quote_accept_time: date,
-        issue_code: Default::default(),
-        bids: Default::default(),
-        asks: Default::default(),
+    let (hours, minutes) = offset.split_once(':').unwrap_or((offset, "00"));
+    match (hours.parse::<i64>(), minutes.parse::<i64>()) {
+        (Ok(hours), Ok(minutes)) if (0..=14).contains(&hours) && (0..60).contains(&minutes) => {
+            Ok(sign * (hours * 3_600 + minutes * 60))
+        }
+        _ => Err(invalid()),
+    }
+}
+
+fn parse_market_hours(market_hours: &str) -> Result<(NaiveTime, NaiveTime), String> {
+    let parse = |time| NaiveTime::parse_from_str(time, "%H:%M").ok();
+    match market_hours.split_once('-') {
+        Some((open, close)) => match (parse(open), parse(close)) {
+            (Some(open), Some(close)) if open < close => Ok((open, close)),
+            _ => Err("must be an opening and closing time like 09:00-15:00".to_string()),
+        },
+        None => Err("must be an opening and closing time like 09:00-15:00".to_string()),
+    }
+}
+
+fn parse_duration(duration: &str) -> Result<Duration, String> {
+    let invalid = || "must be a number with a unit of ms, s, m or h, like 500ms or 1s".to_string();
+    let split = duration
+        .find(|c: char| !c.is_ascii_digit())
+        .ok_or_else(invalid)?;
+    let count: i64 = duration[..split].parse().map_err(|_| invalid())?;
+    let duration = match &duration[split..] {
+        "ms" => Duration::try_milliseconds(count),
+        "s" => Duration::try_seconds(count),
+        "m" => Duration::try_minutes(count),
+        "h" => Duration::try_hours(count),
+        _ => return Err(invalid()),
     };
-    file.read_exact(&mut quote_packet.issue_code)?;
-    // Check that the issue code is valid UTF-8 for when we print it later.
-    str::from_utf8(&quote_packet.issue_code)?;
-    file.seek(SeekFrom::Current(BIDS_OFFSET))?;
-    parse_bids_or_asks(file, &mut quote_packet.bids)?;
-    file.seek(SeekFrom::Current(QUANTITY_OFFSET as i64))?;
-    parse_bids_or_asks(file, &mut quote_packet.asks)?;
-    file.seek(SeekFrom::Current(QUOTE_ACCEPT_OFFSET as i64))?;
-    quote_packet.quote_accept_time = parse_quote_accept_time(file, seconds)?;
-    file.seek(SeekFrom::Current(1))?;
-    Ok(Valid(quote_packet))
-}
-
-fn parse_file(path: &str) -> Result<(), Box<dyn Error>> {
-    let file = &mut File::open(path)?;
-    let (end, precision, this_zone) = parse_header(file)?;
-    let stdout = io::stdout();
-    let mut handle = stdout.lock();
-    loop {
-        match parse_packet(file, end, precision, this_zone)? {
-            Valid(quote_packet) => writeln!(handle, "{}", quote_packet)?,
-            EOF => break,
-            Invalid => continue,
+    // Intervals are counted in nanoseconds, which only go up to about 292 years.
+    let duration = duration.filter(|duration| duration.num_nanoseconds().is_some());
+    match duration {
+        Some(duration) if duration > Duration::zero() => Ok(duration),
+        Some(_) => Err("must be more than 0".to_string()),
+        None => Err("is too long".to_string()),
+    }
+}
+
+fn parse_alpha(alpha: &str) -> Result<f64, String> {
+    match alpha.parse() {
+        Ok(alpha) if alpha > 0.0 && alpha <= 1.0 => Ok(alpha),
+        Ok(_) => Err("must be more than 0 and at most 1".to_string()),
+        Err(e) => Err(format!("{}", e)),
+    }
+}
+
+fn parse_payload_size(payload_size: &str) -> Result<i64, String> {
+    match payload_size.parse() {
+        Ok(payload_size) if payload_size >= QUOTE_PACKET_SIZE => Ok(payload_size),
+        Ok(_) => Err(format!("must be at least {}", QUOTE_PACKET_SIZE)),
+        Err(e) => Err(format!("{}", e)),
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, ValueEnum)]
+enum Only {
+    Quotes,
+    Trades,
+    All,
+}
+
+#[derive(Copy, Clone, PartialEq, ValueEnum)]
+enum OutputFormat {
+    Text,
+    #[cfg(feature = "parquet")]
+    Parquet,
+}
+
+#[derive(Copy, Clone, PartialEq, ValueEnum)]
+enum Color {
+    Auto,
+    Always,
+    Never,
+}
+
+#[derive(Copy, Clone, ValueEnum)]
+enum ErrorFormat {
+    Text,
+    Json,
+}
+
+#[derive(Copy, Clone, ValueEnum)]
+enum ByteOrder {
+    Le,
+    Be,
+}
+
+#[derive(Copy, Clone, ValueEnum)]
+enum TimestampUnit {
+    Us,
+    Ns,
+}
+
+#[derive(clap::Subcommand)]
+enum Command {
+    /// Print a shell completion script to stdout
+    Completions { shell: Shell },
+}
+
+/// Parses KRX B6034 quote packets out of a pcap capture and prints them one per line.
+#[derive(clap::Parser)]
+#[command(
+    version,
+    args_conflicts_with_subcommands = true,
+    subcommand_negates_reqs = true
+)]
+struct Options {
+    #[command(subcommand)]
+    command: Option<Command>,
+    /// Reorder packets by quote accept time
+    ///
+    /// Packets are buffered for the few seconds it takes for their accept times to become final,
+    /// so memory use stays bounded by the trading activity in that window.
+    #[arg(short, long)]
+    reorder: bool,
+    /// What -r orders packets by
+    ///
+    /// accept is the accept or trade time, packet the capture timestamp and issue-accept the issue
+    /// code and then the accept time. Ties are broken by timestamp and then by the order packets
+    /// were captured in. Since any later packet could belong to an issue that comes first,
+    /// issue-accept holds on to the whole capture.
+    #[arg(long, value_name = "KEY", value_enum, default_value_t = SortKey::Accept, conflicts_with_all = ["merge", "bench"])]
+    sort_key: SortKey,
+    /// Make room for N packets in the -r buffer up front
+    #[arg(long, value_name = "N", default_value_t = 200_000)]
+    reorder_capacity: usize,
+    /// Abort when -r has to buffer more than N packets
+    ///
+    /// The buffer only grows that large when accept times stop becoming final, for instance because
+    /// they're garbage, so this keeps such a capture from using up all the memory.
+    #[arg(long, value_name = "N")]
+    max_buffer: Option<usize>,
+    /// Sort all packets by accept time, however out of order their timestamps are
+    ///
+    /// Unlike -r, this doesn't count on the capture being in timestamp order, which merged
+    /// captures often aren't. Packets are sorted in runs of --sort-run-size, which are written to
+    /// temporary files in --tmpdir and merged once the whole capture has been read, so memory use
+    /// stays bounded. Nothing is printed before the end of the capture.
+    #[arg(
+        long,
+        conflicts_with_all = ["reorder", "tape", "merge", "merge_sort_files", "check_monotonic", "aggregate_by_symbol", "quote_rate", "volume_profile", "bench"]
+    )]
+    full_sort: bool,
+    /// Sort --full-sort runs of N packets in memory before writing them out
+    #[arg(
+        long,
+        value_name = "N",
+        default_value_t = 1_000_000,
+        value_parser = clap::value_parser!(u64).range(1..),
+        requires = "full_sort"
+    )]
+    sort_run_size: u64,
+    /// Write the temporary files of --full-sort to DIR instead of the system's temporary directory
+    #[arg(long, value_name = "DIR", requires = "full_sort")]
+    tmpdir: Option<String>,
+    /// Print quotes and trades as one tape ordered by accept and trade time
+    ///
+    /// This is shorthand for `--reorder --only all`.
+    #[arg(long, conflicts_with = "only")]
+    tape: bool,
+    /// Mark every trade on the tape as buy, sell or - by comparing its price with the best ask and
+    /// bid of the latest quote for the issue
+    #[arg(long, requires = "tape")]
+    aggressor: bool,
+    /// Warn about packets whose timestamp goes backward, or with -r whose accept time is printed out
+    /// of order
+    ///
+    /// Unlike --check-monotonic, quotes are printed as usual and the warnings go to stderr. Under
+    /// --strict the first such packet is an error instead.
+    #[arg(long)]
+    check_monotone: bool,
+    /// Report packets whose timestamp goes backward and the largest gap, instead of printing quotes
+    ///
+    /// Unlike --check-monotone, only the report is printed, to stdout, and a backward timestamp is
+    /// never an error.
+    #[arg(long, conflicts_with_all = ["reorder", "tape", "head", "tail", "count"])]
+    check_monotonic: bool,
+    /// Report gaps of more than SECONDS (30 by default) between consecutive accept times of an
+    /// issue to stderr
+    #[arg(
+        long,
+        value_name = "SECONDS",
+        num_args = 0..=1,
+        default_missing_value = "30",
+        conflicts_with = "check_monotonic"
+    )]
+    gap_report: Option<u64>,
+    /// Warn on stderr when an issue gets more than N quotes within 100ms of accept time
+    ///
+    /// Such bursts are typical of quote stuffing. The warning is printed once when an issue goes
+    /// over the limit, and again only after its rate has dropped back to N or less.
+    #[arg(
+        long,
+        value_name = "N",
+        value_parser = clap::value_parser!(u64).range(1..),
+        conflicts_with_all = ["check_monotonic", "quote_rate", "bench"]
+    )]
+    quote_stuffing_threshold: Option<u64>,
+    /// Warn on stderr about every quote whose best ask is below the best bid, and count them per
+    /// issue at the end
+    ///
+    /// Locked quotes, with the best bid at the best ask, aren't counted.
+    #[arg(
+        long,
+        conflicts_with_all = ["check_monotonic", "quote_rate", "bench", "drop_crossed"]
+    )]
+    alert_crossed_book: bool,
+    /// Count the quotes of every issue in windows of SECONDS by accept time, instead of printing
+    /// them
+    ///
+    /// Prints one line per window and issue, with zero for windows in which an issue already seen
+    /// had no quotes.
+    #[arg(
+        long,
+        value_name = "SECONDS",
+        value_parser = clap::value_parser!(i64).range(1..),
+        conflicts_with_all = ["reorder", "tape", "check_monotonic", "aggregate_by_symbol", "bench", "head", "tail", "count"]
+    )]
+    quote_rate: Option<i64>,
+    /// Sum up the bid and ask quantities quoted at every price of each issue over the capture,
+    /// instead of printing quotes
+    ///
+    /// Prints the issue code, price and total quantity of one price per line, issues in the order
+    /// they first appear and prices ascending.
+    #[arg(
+        long,
+        visible_alias = "intraday-volume-profile",
+        conflicts_with_all = ["reorder", "tape", "check_monotonic", "aggregate_by_symbol", "quote_rate", "bench", "head", "tail", "count"]
+    )]
+    volume_profile: bool,
+    /// Add up the prices of --volume-profile in bins of N, each printed as its lowest price
+    #[arg(
+        long,
+        value_name = "N",
+        default_value_t = 1,
+        value_parser = clap::value_parser!(u32).range(1..),
+        requires = "volume_profile"
+    )]
+    volume_profile_resolution: u32,
+    /// Print the correlations between the mid price returns of every pair of issues as a CSV
+    /// matrix, instead of printing quotes
+    ///
+    /// Returns are taken over intervals of --correlation-interval by accept time, each from the
+    /// last mid price of an issue up to the end of one interval to that up to the end of the next,
+    /// so an interval without quotes carries the price before it forward. Pairs are correlated
+    /// over the intervals where both issues have a return, and NaN is printed when there are
+    /// fewer than two of those or one of the issues' returns never change.
+    #[arg(
+        long,
+        conflicts_with_all = ["reorder", "tape", "check_monotonic", "aggregate_by_symbol", "quote_rate", "volume_profile", "bench", "head", "tail", "count"]
+    )]
+    correlation_matrix: bool,
+    /// Print the minimum, median, 99th percentile and maximum of how long after its accept time
+    /// every quote was captured, instead of printing quotes
+    ///
+    /// The latency of a quote is its timestamp minus its accept time, printed in milliseconds. The
+    /// median and 99th percentile are taken from a histogram, to within 1 part in 128, while the
+    /// minimum and maximum are exact.
+    #[arg(
+        long,
+        conflicts_with_all = ["reorder", "tape", "check_monotonic", "aggregate_by_symbol", "quote_rate", "volume_profile", "correlation_matrix", "bench", "head", "tail", "count"]
+    )]
+    latency_stats: bool,
+    /// Take the returns of --correlation-matrix over intervals of SECONDS
+    #[arg(
+        long,
+        value_name = "SECONDS",
+        default_value_t = 60,
+        value_parser = clap::value_parser!(i64).range(1..),
+        requires = "correlation_matrix"
+    )]
+    correlation_interval: i64,
+    /// Only correlate the first N issues of the capture, warning about the rest
+    ///
+    /// Every issue takes memory for each interval, so this bounds the memory use.
+    #[arg(
+        long,
+        value_name = "N",
+        default_value_t = 50,
+        value_parser = clap::value_parser!(u64).range(1..),
+        requires = "correlation_matrix"
+    )]
+    max_symbols: u64,
+    /// Only report the part of gaps that falls within the trading hours of the day they start,
+    /// given as HH:MM-HH:MM in the accept time zone
+    #[arg(long, value_name = "HOURS", value_parser = parse_market_hours, requires = "gap_report")]
+    market_hours: Option<(NaiveTime, NaiveTime)>,
+    /// Write the gap report to FILE instead of stderr
+    #[arg(long, value_name = "FILE", requires = "gap_report")]
+    gap_output: Option<String>,
+    /// Write the packets of every issue to DIR/<issue code>.txt instead of stdout
+    ///
+    /// The directory is created if needed, and files from an earlier run are overwritten. Only
+    /// --max-open-files of them are kept open at once: when a capture has more issues than that,
+    /// the file of the issue written to least recently is closed and reopened for appending when
+    /// its issue comes up again, which is slower but never runs out of file descriptors.
+    /// Characters other than letters, digits, - and _ in issue codes are replaced by _ in the file
+    /// names.
+    #[arg(
+        long,
+        value_name = "DIR",
+        conflicts_with_all = ["check_monotonic", "quote_rate", "bench"]
+    )]
+    split_by_issue: Option<String>,
+    /// Keep at most N files open under --split-by-issue
+    #[arg(long, value_name = "N", default_value_t = 256, value_parser = clap::value_parser!(u64).range(1..), requires = "split_by_issue")]
+    max_open_files: u64,
+    /// Print all packets of one issue before moving on to the next, each sorted by accept time
+    ///
+    /// Issues come in the order they first appear in the capture. The whole capture is buffered,
+    /// so memory use grows with its size.
+    #[arg(long, conflicts_with_all = ["reorder", "tape", "check_monotonic"])]
+    aggregate_by_symbol: bool,
+    /// Order issues alphabetically under --aggregate-by-symbol
+    #[arg(long, requires = "aggregate_by_symbol")]
+    sort_symbols: bool,
+    /// Give up when --aggregate-by-symbol would buffer more than MB megabytes of packets
+    #[arg(long, value_name = "MB", requires = "aggregate_by_symbol")]
+    max_memory: Option<u64>,
+    /// Scan forward past records with implausible framing instead of misparsing them
+    ///
+    /// A record is implausible when its length exceeds the snaplen, its timestamp fraction is out
+    /// of range, or its timestamp jumps more than a day away from the previous record. A capture
+    /// header that can't be parsed is skipped too, assuming microsecond timestamps in whichever
+    /// byte order makes sense of the first record.
+    #[arg(long)]
+    resync: bool,
+    /// Read the capture as little-endian (le) or big-endian (be), whatever its magic number says
+    ///
+    /// The magic number isn't checked then, which recovers captures where only it is damaged or
+    /// nonstandard. It's a deliberate override: forcing the wrong byte order turns every record
+    /// into garbage. Without --precision, the precision is the one the magic number stands for,
+    /// or microseconds if it's not a magic number at all.
+    #[arg(long, value_name = "ORDER", value_enum)]
+    endian: Option<ByteOrder>,
+    /// Read the timestamps of the capture as microseconds (us) or nanoseconds (ns), whatever its
+    /// magic number says
+    ///
+    /// Like --endian, this skips checking the magic number and makes a wrong choice produce
+    /// garbage. Without --endian, the byte order is the one the magic number stands for, or
+    /// little-endian if it's not a magic number at all.
+    #[arg(long, value_name = "UNIT", value_enum)]
+    precision: Option<TimestampUnit>,
+    /// Look for the quote marker anywhere in a record when it isn't at the usual offset
+    ///
+    /// This rescues captures with nonstandard encapsulations, but is slower and could match
+    /// spuriously.
+    #[arg(long)]
+    scan_marker: bool,
+    /// Look for payloads N bytes into every packet instead of after the headers of its link type
+    ///
+    /// This reads captures with encapsulations that aren't understood, such as GRE or ERSPAN
+    /// mirrors. Packets aren't checked for being UDP then.
+    #[arg(long, value_name = "N")]
+    payload_offset: Option<u32>,
+    /// Find the payload offset in the first record with a quote marker and a whole quote after it,
+    /// and read the rest of the capture like with --payload-offset
+    ///
+    /// The offset found is printed to stderr. A later record with its quote marker elsewhere is an
+    /// error.
+    #[arg(long, conflicts_with_all = ["payload_offset", "scan_marker"])]
+    auto_offset: bool,
+    /// Accept quote packets with this message type instead of B6034 (repeatable)
+    ///
+    /// Once given, the matched message type is printed in front of every packet.
+    #[arg(long, value_name = "TYPE", value_parser = parse_magic)]
+    magic: Vec<[u8; 5]>,
+    /// Accept trade packets with this message type instead of A3034 (repeatable)
+    #[arg(long, value_name = "TYPE", value_parser = parse_magic)]
+    trade_magic: Vec<[u8; 5]>,
+    /// Which records to print
+    ///
+    /// Unless only quotes are printed, every line starts with Q for a quote or T for a trade.
+    #[arg(long, value_enum, default_value_t = Only::Quotes)]
+    only: Only,
+    /// Skip quotes and trades of the issue with this code (repeatable)
+    ///
+    /// Codes are compared without the spaces padding them to 12 characters.
+    #[arg(long, value_name = "CODE")]
+    exclude_symbol: Vec<String>,
+    /// Only print every Nth quote, starting with the first
+    ///
+    /// Quotes are counted after the ones skipped by --exclude-symbol, --drop-crossed and the like,
+    /// and before --head and --tail. Trades are never skipped.
+    #[arg(
+        long,
+        value_name = "N",
+        default_value_t = 1,
+        value_parser = clap::value_parser!(u64).range(1..),
+        conflicts_with_all = ["check_monotonic", "quote_rate", "bench"]
+    )]
+    sample: u64,
+    /// Only print the latest quote of every issue at the end of each DURATION of timestamps
+    ///
+    /// DURATION is a number with a unit of ms, s, m or h, like 500ms or 1s. At the end of each
+    /// interval, the last quote of every issue quoted in it is printed, in the order of the issue
+    /// codes, so an issue without quotes in an interval keeps the book printed for it before. This
+    /// happens before --sample, --head and --tail.
+    #[arg(
+        long,
+        value_name = "DURATION",
+        value_parser = parse_duration,
+        conflicts_with_all = ["tape", "only", "check_monotonic", "quote_rate", "bench"]
+    )]
+    every: Option<Duration>,
+    /// Expect quote payloads of N bytes instead of 215
+    ///
+    /// Only the leading 215 bytes are parsed, anything after them is ignored. A datagram longer
+    /// than that holds several messages of N bytes back to back, each a packet of its own.
+    #[arg(long, value_name = "N", value_parser = parse_payload_size, default_value_t = QUOTE_PACKET_SIZE)]
+    payload_size: i64,
+    /// Print the information type, market type, issue sequence number and market status of every
+    /// packet too
+    #[arg(long)]
+    verbose: bool,
+    /// Time zone the accept and trade times are in, as KST, UTC or an offset like -05:00
+    #[arg(
+        long,
+        value_name = "ZONE",
+        value_parser = parse_accept_tz,
+        default_value = "KST",
+        allow_hyphen_values = true
+    )]
+    accept_tz: i64,
+    /// Round accept and trade times to the nearest second instead of keeping their fraction
+    ///
+    /// The fraction is a single digit, so from 5 on it rounds up.
+    #[arg(long)]
+    round_accept: bool,
+    /// Strip the spaces padding issue codes to 12 characters
+    #[arg(long)]
+    normalize_issue_code: bool,
+    /// How to print book levels without a price or quantity
+    ///
+    /// Omitting them drops the empty levels at the deep end of each side, so lines may have fewer
+    /// fields.
+    #[arg(long, value_name = "STYLE", value_enum, default_value_t = EmptyAs::Zero)]
+    empty_as: EmptyAs,
+    /// Order in which the book levels are printed
+    ///
+    /// book lists every level by ascending price, from the deepest bid to the deepest ask, and
+    /// descending is the reverse of that. best-first lists the bids and then the asks, each from
+    /// the best level down, and asks-first does the same starting with the asks.
+    #[arg(long, value_name = "ORDER", value_enum, default_value_t = FieldOrder::Book)]
+    field_order: FieldOrder,
+    /// Only print the Nth best bid and ask level, counting from 1
+    ///
+    /// Levels 6 to 10 are only there in extended quotes, and nothing is printed for them otherwise.
+    /// Under --mdi only the imbalance at that level is printed.
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u8).range(1..=10))]
+    price_level: Option<u8>,
+    /// Only print these bid and ask levels, counting from 1 at the best, like 1,3,5
+    #[arg(
+        long,
+        value_name = "N,...",
+        value_delimiter = ',',
+        value_parser = clap::value_parser!(u8).range(1..=10),
+        conflicts_with = "price_level"
+    )]
+    price_levels: Vec<u8>,
+    /// Print timestamps as Unix epoch counts in this unit instead of formatted dates
+    #[arg(long, value_name = "UNIT", value_enum)]
+    epoch: Option<Epoch>,
+    /// Print the accept time, issue code and depth imbalance at each level of every quote instead
+    ///
+    /// The imbalance at a level is (bid quantity - ask quantity) / (bid quantity + ask quantity),
+    /// printed multiplied by 10000 and rounded toward zero, so 10000 means only bids and -10000
+    /// only asks. A level without either is 0.
+    #[arg(long, visible_alias = "market-depth-imbalance", conflicts_with_all = ["tape", "only"])]
+    mdi: bool,
+    /// Print the accept time, issue code, mid price and its exponential moving average with weight
+    /// ALPHA of every quote whose mid price changed instead
+    ///
+    /// The average of an issue starts at its first mid price and becomes ALPHA * mid price +
+    /// (1 - ALPHA) * previous average with every quote after it. It's printed rounded to the
+    /// nearest integer. Quotes without a bid or an ask have no mid price and are skipped.
+    #[arg(long, value_name = "ALPHA", value_parser = parse_alpha, conflicts_with_all = ["tape", "only", "mdi"])]
+    ema: Option<f64>,
+    /// Print the accept time, issue code, best bid and best ask price of every quote instead
+    ///
+    /// These are the touch prices, at which a sell and a buy would execute right away. A side
+    /// without any level is printed as 0.
+    #[arg(long, visible_alias = "touching-price", conflicts_with_all = ["tape", "only", "mdi", "ema"])]
+    touch: bool,
+    /// Print the accept time, issue code, mid price rounded down and its parity of every quote
+    /// instead
+    ///
+    /// The mid price is (best bid + best ask) / 2, and the parity (best bid + best ask) % 2, so
+    /// 1 means the mid price lies halfway between two prices. Quotes without a bid or an ask have
+    /// no mid price and are skipped.
+    #[arg(long, conflicts_with_all = ["tape", "only", "mdi", "ema", "touch"])]
+    touch_mid: bool,
+    /// Print every packet as a YAML document of its fields instead, starting with ---
+    ///
+    /// Book levels are lists of their price and quantity, best first. Timestamps are printed like
+    /// in lines, so --epoch makes them numbers.
+    #[arg(long, conflicts_with_all = ["mdi", "ema", "touch", "touch_mid"])]
+    yaml: bool,
+    /// Like --yaml, but print each document on one line in flow style, which is JSON
+    #[arg(long, conflicts_with_all = ["mdi", "ema", "touch", "touch_mid"])]
+    yaml_flow: bool,
+    /// Write every packet as a MessagePack map of the same fields as --yaml instead, each one
+    /// after its length as 4 big-endian bytes
+    #[arg(long, conflicts_with_all = ["mdi", "ema", "touch", "touch_mid", "yaml", "yaml_flow"])]
+    msgpack: bool,
+    /// Print every quote under --ema, even when its mid price didn't change
+    #[arg(long, requires = "ema")]
+    ema_output_all: bool,
+    /// Print the total quantity at each level and the better ones on its side instead of the
+    /// quantity at the level alone, marked with a C in front
+    #[arg(long, conflicts_with = "mdi")]
+    cumulative_depth: bool,
+    /// Follow the book of every quote with levels=B/A, the number of bid and ask levels that
+    /// aren't empty
+    #[arg(long)]
+    levels: bool,
+    /// Mark quotes whose best bid is at or above the best ask with LOCKED or CROSSED
+    #[arg(long)]
+    flag_crossed: bool,
+    /// Skip quotes whose best bid is at or above the best ask
+    #[arg(long)]
+    drop_crossed: bool,
+    /// Print the offset, size and first payload bytes of every record that isn't a quote to stderr
+    #[arg(long)]
+    print_invalid_offsets: bool,
+    /// Retry reads that fail with a transient error, or resume a download that broke off, up to N
+    /// times in a row before giving up
+    #[arg(long, value_name = "N", default_value_t = 3)]
+    read_retries: u32,
+    /// Keep reading a capture that's still being written, like one from `tcpdump -w`, instead of
+    /// stopping at its end
+    ///
+    /// At the end of the capture, or of a record not yet written in full, the capture is checked
+    /// for more every 100ms. Once it hasn't grown for --watch-timeout, it's taken to have ended.
+    #[arg(long)]
+    watch: bool,
+    /// Stop watching a capture after it hasn't grown for SECONDS
+    #[arg(long, value_name = "SECONDS", default_value_t = 10, requires = "watch")]
+    watch_timeout: u64,
+    /// Flush the output after every packet, so that whatever reads it sees each one as soon as
+    /// it's parsed, which --watch implies
+    ///
+    /// Output to a pipe or file is otherwise written in large blocks. Flushing makes a system call
+    /// for every line, which costs a good part of the throughput on a large capture.
+    #[arg(long)]
+    line_buffered: bool,
+    /// Parse everything but don't print any packets, then report whether the capture parsed cleanly
+    ///
+    /// Exits with a nonzero status if any packet was malformed or any bytes had to be discarded
+    /// while resynchronizing, so that it can check archived captures. Records that aren't quote or
+    /// trade packets are skipped as usual.
+    #[arg(long)]
+    dry_run: bool,
+    /// How to write packets
+    ///
+    /// Parquet, which is only there when built with the parquet feature, writes quotes as rows
+    /// of a Parquet file given with --output. The columns are described in src/parquet_output.rs.
+    #[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+    format: OutputFormat,
+    /// When to color the best bid green and the best ask red
+    ///
+    /// Auto colors them when printing to a terminal, unless the NO_COLOR environment variable is
+    /// set to anything but an empty string.
+    #[arg(long, value_name = "WHEN", value_enum, default_value_t = Color::Auto)]
+    color: Color,
+    /// Write packets to FILE instead of stdout
+    #[arg(short, long, value_name = "FILE", required_if_eq("format", "parquet"))]
+    output: Option<String>,
+    /// Parse the capture without printing anything and report how fast that went
+    ///
+    /// Reports the wall time, the throughput in bytes, records and packets per second, and how the
+    /// time splits between reading, parsing and with -r reordering. Built with the
+    /// count-allocations feature, it also reports how many allocations the packet loop made.
+    #[arg(
+        long,
+        conflicts_with_all = ["check_monotonic", "aggregate_by_symbol", "gap_report", "head", "tail", "count"]
+    )]
+    bench: bool,
+    /// Run --bench N times and report the best and median pass
+    #[arg(long, value_name = "N", default_value_t = 1, requires = "bench")]
+    repeat: usize,
+    /// Print the number of records found at the end
+    #[arg(long)]
+    count: bool,
+    /// Print how many records were quote or trade packets and how many were skipped to stderr at
+    /// the end, even when interrupted
+    ///
+    /// Packets left out by --exclude-symbol, --drop-crossed and the like count as valid.
+    #[arg(long)]
+    print_skipped_ratio: bool,
+    /// Abort on the first malformed packet and check every field of the quote format
+    ///
+    /// On top of what is always checked, like the end-of-message byte every message ends with,
+    /// this verifies the record length of quote packets, that numeric fields are digits only and
+    /// that the accept time is in range.
+    #[arg(long, conflicts_with = "warn")]
+    strict: bool,
+    /// Print a warning for every malformed or truncated packet that is skipped
+    #[arg(long)]
+    warn: bool,
+    /// Log the decision taken for every record, or with -vv also the field offsets
+    #[arg(short, action = ArgAction::Count)]
+    verbosity: u8,
+    /// Stop printing warnings and log messages after N of them
+    #[arg(long, value_name = "N")]
+    max_warnings: Option<u64>,
+    /// Hex dump the start of the first N records that aren't quote packets
+    #[arg(long, value_name = "N", num_args = 0..=1, default_missing_value = "10")]
+    dump_invalid: Option<u64>,
+    /// Format of error messages and warnings
+    #[arg(long, value_enum, default_value_t = ErrorFormat::Text)]
+    error_format: ErrorFormat,
+    /// Prefix every packet with its 1-based number
+    #[arg(long)]
+    packet_number: bool,
+    /// Count records that aren't quote packets when numbering, to match the record index
+    #[arg(long, requires = "packet_number")]
+    count_invalid: bool,
+    /// Start parsing packets at byte offset N of the file
+    #[arg(long, value_name = "N", default_value_t = 0)]
+    skip_bytes: u64,
+    /// Only print the first N packets
+    #[arg(long, value_name = "N")]
+    head: Option<u64>,
+    /// Only print the last N packets
+    #[arg(long, value_name = "N")]
+    tail: Option<usize>,
+    /// Prefix every packet with the hex offset of its record in the file
+    #[arg(long)]
+    with_offset: bool,
+    /// Prefix every packet with the decimal offset of its record in the file
+    #[arg(long, conflicts_with = "with_offset")]
+    decimal_offset: bool,
+    /// Print the version, build profile, target and commit this binary was built from
+    #[arg(long, exclusive = true)]
+    build_info: bool,
+    /// Print what the global header of the capture says about it instead of parsing its packets
+    #[arg(long, conflicts_with_all = ["merge", "merge_sort_files", "bench"])]
+    info: bool,
+    /// Merge several captures of the same feed into one stream ordered by accept time
+    ///
+    /// Implies -r. The captures are read side by side in timestamp order, each with the byte order
+    /// and precision of its own header, and one that ends early leaves the others to carry on. A
+    /// packet already printed from another capture with the same accept time is dropped as a
+    /// duplicate, so copies from the capture listed first are kept. Offsets and packet numbers
+    /// refer to the capture each packet came from.
+    #[arg(
+        long,
+        conflicts_with_all = ["check_monotonic", "aggregate_by_symbol", "quote_rate", "bench", "skip_bytes"]
+    )]
+    merge: bool,
+    /// Read several captures, each in timestamp order, as one stream ordered by accept time
+    ///
+    /// Implies -r. Like concatenating the captures and reading them with -r, except that the
+    /// captures are read side by side, always taking the earliest of their next packets, so they
+    /// may overlap in time. Unlike --merge, nothing is dropped as a duplicate. Offsets and packet
+    /// numbers refer to the capture each packet came from.
+    #[arg(
+        long,
+        conflicts_with_all = ["merge", "check_monotonic", "aggregate_by_symbol", "quote_rate", "volume_profile", "correlation_matrix", "bench", "skip_bytes"]
+    )]
+    merge_sort_files: bool,
+    /// Also look for captures in the subdirectories of a FILENAME that is a directory
+    #[arg(long)]
+    recursive: bool,
+    /// The pcap capture to parse, or with --merge or --merge-sort-files the captures to merge
+    ///
+    /// A directory stands for the *.pcap, *.pcapng and *.pcap.gz files inside it, and a glob
+    /// pattern such as captures/2011-02-16_*.pcap for the files it matches, both in lexicographic
+    /// order. With the http feature, it can also be an http:// or https:// URL to download the
+    /// capture from. The whole capture is downloaded to a temporary file before any of it is
+    /// parsed, so nothing is printed until the download has finished.
+    #[arg(value_name = "FILENAME", required_unless_present = "build_info")]
+    paths: Vec<String>,
+}
+
+impl Options {
+    fn path(&self) -> &str {
+        self.paths.first().expect("clap requires a path")
+    }
+
+    fn is_magic(&self, bytes: &[u8]) -> bool {
+        if self.magic.is_empty() {
+            bytes == QUOTE_MESSAGE_TYPE
+        } else {
+            self.magic.iter().any(|magic| bytes == magic)
+        }
+    }
+
+    // Which of the book levels on each side get printed, best first, counting those only extended
+    // quotes have.
+    fn printed_levels(&self) -> [bool; QUOTE_EXT_LEVELS] {
+        let mut printed =
+            [self.price_level.is_none() && self.price_levels.is_empty(); QUOTE_EXT_LEVELS];
+        for &level in self.price_level.iter().chain(&self.price_levels) {
+            printed[usize::from(level) - 1] = true;
+        }
+        printed
+    }
+
+    fn selects(&self, record: &Record) -> bool {
+        let wanted = match record {
+            Record::Quote(_) => self.only != Only::Trades,
+            Record::Trade(_) => self.only != Only::Quotes,
+        };
+        wanted
+            && !self
+                .exclude_symbol
+                .iter()
+                .any(|code| code.trim_end_matches(' ') == record.issue_code_str())
+    }
+
+    fn is_trade_magic(&self, bytes: &[u8]) -> bool {
+        if self.trade_magic.is_empty() {
+            bytes == TRADE_MESSAGE_TYPE
+        } else {
+            self.trade_magic.iter().any(|magic| bytes == magic)
         }
     }
-    Ok(())
 }
 
-fn parse_reorder(path: &str) -> Result<(), Box<dyn Error>> {
-    let mut min_heap: BinaryHeap<QuotePacket> = BinaryHeap::new();
-    let file = &mut File::open(path)?;
-    let (end, precision, this_zone) = parse_header(file)?;
-    let stdout = io::stdout();
-    let mut handle = stdout.lock();
-    loop {
-        match parse_packet(file, end, precision, this_zone)? {
-            Valid(quote_packet) => {
-                // Instead of filling up the heap with all the quote packets before printing them
-                // for a possibly expensive O(n) space and O(n*log(n)) time complexity where
-                // n = number of quote packets, we only keep track of the last 3 seconds of trading
-                // since our quote packets are already sorted by ascending order of timestamps
-                // and the difference between the latest timestamp and the earliest quote accept
-                // time can never exceed 3 seconds. This gives us O(k) space and O(n*log(k)) time
-                // complexity where k = number of quote packets that arrived in the last 3 seconds.
-                while min_heap.peek().map_or(false, |top| {
-                    quote_packet.time_stamp.timestamp_nanos()
-                        - top.quote_accept_time.timestamp_nanos()
-                        > MAX_DIFF * 1_000_000_000
-                }) {
-                    writeln!(handle, "{}", min_heap.pop().unwrap())?;
+// A capture being read, with what its header says about it and where we are in it.
+struct Input<R = File> {
+    file: R,
+    state: ParserState,
+    snaplen: u32,
+    link_type: LinkType,
+    // Where --payload-offset or --auto-offset put the payload in the record data, if they did.
+    payload_offset: Option<i64>,
+    packet_number: u64,
+    last_seconds: Option<i64>,
+    // The timestamps `--check-monotone` checks.
+    time_stamps: MonotoneCheck,
+    scratch: PacketScratch,
+}
+
+impl<R: Read + Seek> Input<R> {
+    fn plausible_record(&self, buf: &[u8; RECORD_HEADER_SIZE as usize + 4]) -> bool {
+        let field = |i: usize| self.state.decode_u32(buf, i);
+        let seconds = i64::from(field(0)) + self.state.this_zone;
+        let captured_length = field(8);
+        let max_length = match self.snaplen {
+            0 => MAX_RECORD_SIZE,
+            snaplen => snaplen.min(MAX_RECORD_SIZE),
+        };
+        field(4) < 1_000_000_000 / self.state.precision.nanoseconds()
+            && captured_length <= max_length
+            && captured_length <= field(12)
+            && self
+                .last_seconds
+                .is_none_or(|last| (seconds - last).abs() <= MAX_TIME_JUMP)
+    }
+
+    // Waits until the record at the current offset has been written in full, or the capture has
+    // stopped growing for --watch-timeout. Seeking back to the offset after every look at the end
+    // makes the next read see whatever has been appended since the last one hit the end.
+    fn wait_for_record(&mut self, options: &Options) -> Result<(), Box<dyn Error>> {
+        let timeout = time::Duration::from_secs(options.watch_timeout);
+        let mut waited = time::Duration::ZERO;
+        let mut last_end = None;
+        loop {
+            let end = self.file.seek(SeekFrom::End(0))?;
+            self.file.seek(SeekFrom::Start(self.state.offset))?;
+            let available = end.saturating_sub(self.state.offset);
+            if available >= RECORD_HEADER_SIZE + 4 {
+                let mut buf = [0; RECORD_HEADER_SIZE as usize];
+                read_exact(&mut self.file, &mut buf, self.state.offset)?;
+                self.file.seek(SeekFrom::Start(self.state.offset))?;
+                let captured_length = u64::from(self.state.decode_u32(&buf, 8));
+                if available >= RECORD_HEADER_SIZE + 4 + captured_length {
+                    return Ok(());
                 }
-                min_heap.push(quote_packet);
             }
-            EOF => break,
-            Invalid => continue,
+            if last_end != Some(end) {
+                last_end = Some(end);
+                waited = time::Duration::ZERO;
+            }
+            if waited >= timeout || INTERRUPTED.load(atomic::Ordering::SeqCst) {
+                return Ok(());
+            }
+            thread::sleep(WATCH_INTERVAL);
+            waited += WATCH_INTERVAL;
         }
     }
-    for quote_packet in min_heap.into_sorted_vec().iter().rev() {
-        writeln!(handle, "{}", quote_packet)?;
-    }
-    Ok(())
-}
 
-fn main() {
-    let mut args = env::args().skip(1);
-    match (
-        args.next().as_ref().map(String::as_str),
-        args.next().as_ref(),
-    ) {
-        (Some(path), None) => parse_file(path),
-        (Some("-r"), Some(path)) => parse_reorder(path),
-        _ => {
-            eprintln!("Usage: parse-quote [-r] <filename>");
-            process::exit(1);
+    // Checks that the record at the current offset looks sane, and if it doesn't, scans forward a
+    // byte at a time until it finds one that does, so that a damaged stretch of the capture only
+    // costs us the records inside it.
+    fn resync(&mut self, summary: &mut Summary) -> Result<(), Box<dyn Error>> {
+        let start = self.state.offset;
+        let mut buf = [0; RECORD_HEADER_SIZE as usize + 4];
+        loop {
+            match read_exact(&mut self.file, &mut buf, self.state.offset) {
+                Ok(()) if self.plausible_record(&buf) => {
+                    let seconds = self.state.decode_u32(&buf, 0);
+                    self.last_seconds = Some(i64::from(seconds) + self.state.this_zone);
+                    self.file.seek(SeekFrom::Start(self.state.offset))?;
+                    break;
+                }
+                Ok(()) => {
+                    self.state.offset += 1;
+                    self.file.seek(SeekFrom::Start(self.state.offset))?;
+                }
+                // Whatever is left is too short to be a record, so it's discarded as well.
+                Err(e) if e.kind() == ErrorKind::UnexpectedEof => {
+                    self.state.offset = self.file.seek(SeekFrom::End(0))?;
+                    break;
+                }
+                Err(e) => return Err(e.into()),
+            }
+        }
+        if self.state.offset > start {
+            eprintln!(
+                "Warning: resynchronized at offset {:#x} after discarding {} bytes",
+                self.state.offset,
+                self.state.offset - start
+            );
+            summary.discarded += self.state.offset - start;
         }
+        Ok(())
+    }
+
+    fn next_packet(
+        &mut self,
+        options: &Options,
+        summary: &mut Summary,
+    ) -> Result<Option<Record>, Box<dyn Error>> {
+        loop {
+            if INTERRUPTED.load(atomic::Ordering::SeqCst) {
+                summary.interrupted = true;
+                return Ok(None);
+            }
+            // The rest of a datagram comes from a record that's been read already.
+            let continued = self.scratch.in_datagram();
+            if options.watch && !continued {
+                self.wait_for_record(options)?;
+            }
+            if options.resync && !continued {
+                self.resync(summary)?;
+            }
+            let parsed = parse_packet(self, options)?;
+            summary.trailing += mem::take(&mut self.scratch.trailing);
+            match parsed {
+                Valid(record) if !options.selects(&record) => {}
+                Valid(Record::Quote(quote_packet))
+                    if options.drop_crossed && quote_packet.crossed().is_some() =>
+                {
+                    summary.crossed += 1;
+                }
+                Valid(mut record) => {
+                    if options.check_monotone {
+                        let checked = self.time_stamps.check(
+                            "Timestamp",
+                            record.time_stamp(),
+                            record.offset(),
+                        );
+                        if let Err(e) = checked {
+                            non_monotone(options, e)?;
+                        }
+                    }
+                    match record {
+                        Record::Quote(_) => summary.quotes += 1,
+                        Record::Trade(_) => summary.trades += 1,
+                    }
+                    summary.records += 1;
+                    self.packet_number += 1;
+                    *record.number_mut() = self.packet_number;
+                    return Ok(Some(record));
+                }
+                Malformed(packet_offset, malformation) => {
+                    if malformation.is_framing() {
+                        summary.framing += 1;
+                    }
+                    let data = &self.scratch.record;
+                    let e = || malformation.into_error(packet_offset, data);
+                    summary.malformed(packet_offset, e, options)?
+                }
+                Invalid {
+                    offset,
+                    size,
+                    header,
+                } => {
+                    summary.invalid += 1;
+                    let captured = size - 4;
+                    summary.invalid_sizes = Some(match summary.invalid_sizes {
+                        Some((min, max)) => (min.min(captured), max.max(captured)),
+                        None => (captured, captured),
+                    });
+                    // A record whose original length is longer than what was captured was
+                    // truncated, and one the snaplen cut off before the end of a quote payload
+                    // was long enough for a quote on the wire.
+                    let original = self.scratch.original;
+                    if original > captured {
+                        summary.truncated += 1;
+                        let needed = captured_size(self.payload_offset(), options.payload_size);
+                        if i64::from(captured) < needed && i64::from(original) >= needed {
+                            summary.short_quotes += 1;
+                        }
+                        if options.warn && within_message_limit(options) {
+                            let e = ParseError::Truncated {
+                                offset,
+                                captured,
+                                original,
+                            };
+                            match options.error_format {
+                                ErrorFormat::Text => {
+                                    eprintln!("Warning: skipped truncated packet: {}", e)
+                                }
+                                ErrorFormat::Json => eprintln!("{}", error_json(&e, Some(offset))),
+                            }
+                        }
+                    }
+                    if options.print_invalid_offsets {
+                        eprintln!(
+                            "SKIP byte_offset={} packet_size={} header_bytes={:02x} {:02x} {:02x} {:02x} {:02x}",
+                            offset, size, header[0], header[1], header[2], header[3], header[4]
+                        );
+                    }
+                }
+                Eof => return Ok(None),
+            }
+            summary.records += 1;
+            // Skipped records only take up a number when asked to, which makes the packet number
+            // match the raw record index in the file.
+            if options.count_invalid {
+                self.packet_number += 1;
+            }
+        }
+    }
+}
+
+// Picks the byte order under which the first record after a damaged header has sane lengths, or
+// little endian, the more common one, if neither or both do.
+fn guess_endianness(file: &mut File) -> Result<Endianness, io::Error> {
+    let mut buf = [0; RECORD_HEADER_SIZE as usize + 4];
+    file.seek(SeekFrom::Start(HEADER_SIZE))?;
+    let read = read_full(file, &mut buf, HEADER_SIZE)?;
+    file.seek(SeekFrom::Start(HEADER_SIZE))?;
+    let plausible = |end| {
+        let field = |i: usize| decode_u32([buf[i], buf[i + 1], buf[i + 2], buf[i + 3]], end);
+        read == buf.len() && field(8) <= MAX_RECORD_SIZE && field(8) <= field(12)
+    };
+    Ok(match (plausible(LittleEndian), plausible(BigEndian)) {
+        (false, true) => BigEndian,
+        _ => LittleEndian,
+    })
+}
+
+fn is_url(path: &str) -> bool {
+    path.starts_with("http://") || path.starts_with("https://")
+}
+
+#[cfg(feature = "http")]
+fn open_url(options: &Options, url: &str) -> Result<File, Box<dyn Error>> {
+    http_input::download(url, options.read_retries)
+}
+
+#[cfg(not(feature = "http"))]
+fn open_url(_: &Options, _: &str) -> Result<File, Box<dyn Error>> {
+    Err("Reading captures from URLs needs parse-quote built with the http feature".into())
+}
+
+fn open_file(options: &Options, path: &str) -> Result<Input, Box<dyn Error>> {
+    let mut file = if is_url(path) {
+        open_url(options, path)?
+    } else {
+        File::open(path)?
+    };
+    let Header {
+        end,
+        precision,
+        this_zone,
+        snaplen,
+        link_type,
+    } = match parse_header(&mut file, options) {
+        Ok(header) => header,
+        // The packets after a damaged header can still be found by resynchronizing on them.
+        Err(e) if options.resync => {
+            let end = guess_endianness(&mut file)?;
+            eprintln!(
+                "Warning: could not parse the capture header ({}), assuming a {} microsecond \
+                 capture of Ethernet frames without a time zone offset or snaplen",
+                e,
+                match end {
+                    LittleEndian => "little-endian",
+                    BigEndian => "big-endian",
+                }
+            );
+            Header {
+                end,
+                precision: Microsecond,
+                this_zone: 0,
+                snaplen: 0,
+                link_type: LinkType::Ethernet,
+            }
+        }
+        Err(e) => return Err(e),
+    };
+    // Writers almost always leave this at zero. Otherwise it shifts every timestamp, and with them
+    // the dates the KST accept times are matched against, which is rarely what was intended.
+    if this_zone != 0 {
+        eprintln!(
+            "Warning: the capture header has a time zone offset of {} seconds, which is added to \
+             every packet timestamp to get UTC",
+            this_zone
+        );
+    }
+    // A capture taken with a small snaplen, like `tcpdump -s 96`, has every quote cut short, so
+    // that none of them would parse without any hint as to why.
+    let payload_offset = options.payload_offset.map(record_data_offset);
+    let needed = captured_size(
+        payload_offset.unwrap_or_else(|| link_payload_offset(link_type)),
+        options.payload_size,
+    );
+    if snaplen != 0 && i64::from(snaplen) < needed {
+        eprintln!(
+            "Warning: the capture header has a snaplen of {} bytes, but a whole quote packet \
+             takes at least {}, so every quote in the capture is truncated and skipped",
+            snaplen, needed
+        );
+    }
+    // The header is always read from the start of the file, so resuming from an offset inside it
+    // would only re-read it as packet data.
+    let offset = if options.skip_bytes > HEADER_SIZE {
+        file.seek(SeekFrom::Start(options.skip_bytes))?
+    } else {
+        HEADER_SIZE
+    };
+    Ok(Input {
+        file,
+        state: ParserState {
+            offset,
+            end,
+            precision,
+            this_zone,
+        },
+        snaplen,
+        link_type,
+        payload_offset,
+        packet_number: 0,
+        last_seconds: None,
+        time_stamps: MonotoneCheck::default(),
+        scratch: PacketScratch::default(),
+    })
+}
+
+fn is_capture(path: &Path) -> bool {
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    [".pcap", ".pcapng", ".pcap.gz"]
+        .iter()
+        .any(|extension| name.ends_with(extension))
+}
+
+fn find_captures(dir: &Path, recursive: bool, paths: &mut Vec<PathBuf>) -> Result<(), io::Error> {
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            if recursive {
+                find_captures(&path, recursive, paths)?;
+            }
+        } else if is_capture(&path) {
+            paths.push(path);
+        }
+    }
+    Ok(())
+}
+
+// Replaces a directory by the captures inside it and a glob pattern by the files it matches, for
+// shells that don't expand patterns themselves. Returns `None` for a plain file.
+fn expand_path(path: &str, recursive: bool) -> Result<Option<Vec<String>>, String> {
+    let mut paths = Vec::new();
+    if is_url(path) {
+        return Ok(None);
+    } else if Path::new(path).is_dir() {
+        find_captures(Path::new(path), recursive, &mut paths)
+            .map_err(|e| format!("could not list '{}': {}", path, e))?;
+    } else if glob::Pattern::escape(path) != path && !Path::new(path).exists() {
+        let matches = glob::glob(path).map_err(|e| format!("invalid pattern '{}': {}", path, e))?;
+        for entry in matches {
+            let entry = entry.map_err(|e| e.to_string())?;
+            if entry.is_file() {
+                paths.push(entry);
+            }
+        }
+    } else {
+        return Ok(None);
+    }
+    if paths.is_empty() {
+        return Err(format!("no captures found in '{}'", path));
+    }
+    paths.sort();
+    paths
+        .into_iter()
+        .map(|path| {
+            path.into_os_string()
+                .into_string()
+                .map_err(|path| format!("'{}' is not valid UTF-8", Path::new(&path).display()))
+        })
+        .collect::<Result<_, _>>()
+        .map(Some)
+}
+
+fn printed_issue_code<'r>(record: &'r Record, options: &Options) -> &'r str {
+    if options.normalize_issue_code {
+        record.issue_code_str()
+    } else {
+        str::from_utf8(record.issue_code()).unwrap_or_default()
+    }
+}
+
+fn push_issue_code(line: &mut Vec<u8>, record: &Record, options: &Options) {
+    if options.normalize_issue_code {
+        line.extend_from_slice(record.issue_code_str().as_bytes());
+    } else {
+        line.extend_from_slice(record.issue_code());
+    }
+}
+
+fn write_record(
+    handle: &mut impl Write,
+    buffer: &mut LineBuffer,
+    record: &Record,
+    options: &Options,
+) -> Result<(), io::Error> {
+    if options.dry_run {
+        return Ok(());
+    }
+    let (number, offset, message_type, issue_sequence, market_status) = match record {
+        Record::Quote(quote_packet) => (
+            quote_packet.number,
+            quote_packet.offset,
+            &quote_packet.message_type,
+            &quote_packet.issue_sequence,
+            &quote_packet.market_status,
+        ),
+        Record::Trade(trade_packet) => (
+            trade_packet.number,
+            trade_packet.offset,
+            &trade_packet.message_type,
+            &trade_packet.issue_sequence,
+            &trade_packet.board_id,
+        ),
+    };
+    buffer.line.clear();
+    if options.msgpack {
+        // The length goes in front once the value has been written after room for it.
+        buffer.line.extend_from_slice(&[0; 4]);
+        rmp_serde::encode::write_named(&mut buffer.line, &record_json(record, options))
+            .map_err(io::Error::other)?;
+        let length = buffer.line.len() as u32 - 4;
+        buffer.line[..4].copy_from_slice(&length.to_be_bytes());
+        return handle.write_all(&buffer.line);
+    }
+    if options.yaml {
+        let value = record_json(record, options);
+        if options.yaml_flow {
+            buffer.line.extend_from_slice(b"--- ");
+            serde_json::to_writer(&mut buffer.line, &value)?;
+            buffer.line.push(b'\n');
+        } else {
+            buffer.line.extend_from_slice(b"---\n");
+            serde_yaml::to_writer(&mut buffer.line, &value).map_err(io::Error::other)?;
+        }
+        return handle.write_all(&buffer.line);
+    }
+    if options.mdi {
+        if let Record::Quote(quote_packet) = record {
+            buffer.push_time(quote_packet.quote_accept_time, options.epoch);
+            buffer.line.push(b' ');
+            push_issue_code(&mut buffer.line, record, options);
+            let levels = quote_packet.bids().iter().zip(quote_packet.asks());
+            let printed = options.printed_levels();
+            for (_, (&(bid, _), &(ask, _))) in levels.enumerate().filter(|&(i, _)| printed[i]) {
+                let (bid, ask) = (i64::from(bid), i64::from(ask));
+                let imbalance = match bid + ask {
+                    0 => 0,
+                    total => (bid - ask) * 10_000 / total,
+                };
+                buffer.line.push(b' ');
+                push_signed(&mut buffer.line, imbalance);
+            }
+            buffer.line.push(b'\n');
+            handle.write_all(&buffer.line)?;
+        }
+        return Ok(());
+    }
+    if options.touch {
+        if let Record::Quote(quote_packet) = record {
+            buffer.push_time(quote_packet.quote_accept_time, options.epoch);
+            buffer.line.push(b' ');
+            push_issue_code(&mut buffer.line, record, options);
+            buffer.line.push(b' ');
+            push_decimal(&mut buffer.line, u64::from(quote_packet.bids[0].1));
+            buffer.line.push(b' ');
+            push_decimal(&mut buffer.line, u64::from(quote_packet.asks[0].1));
+            buffer.line.push(b'\n');
+            handle.write_all(&buffer.line)?;
+        }
+        return Ok(());
+    }
+    if options.touch_mid {
+        if let Record::Quote(quote_packet) = record {
+            let double_mid = match quote_packet.double_mid_price() {
+                Some(double_mid) => double_mid,
+                None => return Ok(()),
+            };
+            buffer.push_time(quote_packet.quote_accept_time, options.epoch);
+            buffer.line.push(b' ');
+            push_issue_code(&mut buffer.line, record, options);
+            buffer.line.push(b' ');
+            push_decimal(&mut buffer.line, double_mid / 2);
+            buffer.line.push(b' ');
+            push_decimal(&mut buffer.line, double_mid % 2);
+            buffer.line.push(b'\n');
+            handle.write_all(&buffer.line)?;
+        }
+        return Ok(());
+    }
+    if options.ema.is_some() {
+        if let Record::Quote(quote_packet) = record {
+            let (double_mid, ema) = match (quote_packet.double_mid_price(), quote_packet.ema) {
+                (Some(double_mid), Some(ema)) => (double_mid, ema),
+                _ => return Ok(()),
+            };
+            buffer.push_time(quote_packet.quote_accept_time, options.epoch);
+            buffer.line.push(b' ');
+            push_issue_code(&mut buffer.line, record, options);
+            buffer.line.push(b' ');
+            push_decimal(&mut buffer.line, double_mid / 2);
+            if double_mid % 2 == 1 {
+                buffer.line.extend_from_slice(b".5");
+            }
+            buffer.line.push(b' ');
+            push_signed(&mut buffer.line, ema);
+            buffer.line.push(b'\n');
+            handle.write_all(&buffer.line)?;
+        }
+        return Ok(());
+    }
+    let line = &mut buffer.line;
+    if options.packet_number {
+        push_decimal(line, number);
+        line.push(b' ');
+    }
+    if options.with_offset {
+        write!(line, "{:#x} ", offset)?;
+    } else if options.decimal_offset {
+        push_decimal(line, offset);
+        line.push(b' ');
+    }
+    if options.only != Only::Quotes {
+        line.extend_from_slice(match record {
+            Record::Quote(_) => b"Q ",
+            Record::Trade(_) => b"T ",
+        });
+    }
+    if !options.magic.is_empty() || !options.trade_magic.is_empty() {
+        line.extend_from_slice(message_type);
+        line.push(b' ');
+    }
+    if options.verbose {
+        // The message type is the data type, then the information type and the market type.
+        for field in &[
+            &message_type[2..4],
+            &message_type[4..],
+            issue_sequence,
+            market_status,
+        ] {
+            line.extend_from_slice(field);
+            line.push(b' ');
+        }
+    }
+    buffer.push_time(record.time_stamp(), options.epoch);
+    buffer.line.push(b' ');
+    buffer.push_time(record.event_time(), options.epoch);
+    buffer.line.push(b' ');
+    let line = &mut buffer.line;
+    push_issue_code(line, record, options);
+    match record {
+        Record::Quote(quote_packet) => {
+            quote_packet.write_levels(
+                line,
+                options.empty_as,
+                options.field_order,
+                options.cumulative_depth,
+                options.printed_levels(),
+                options.color == Color::Always,
+            );
+            if options.levels {
+                let (bids, asks) = quote_packet.populated_levels();
+                line.extend_from_slice(b" levels=");
+                push_decimal(line, bids as u64);
+                line.push(b'/');
+                push_decimal(line, asks as u64);
+            }
+            if let Some(marker) = quote_packet.crossed().filter(|_| options.flag_crossed) {
+                line.push(b' ');
+                line.extend_from_slice(marker.as_bytes());
+            }
+        }
+        Record::Trade(trade_packet) => trade_packet.write_fill(line),
+    }
+    line.push(b'\n');
+    handle.write_all(line)
+}
+
+// The fields of a record for --yaml and --msgpack, which is built as JSON since every JSON value is
+// one in YAML and MessagePack as well.
+fn record_json(record: &Record, options: &Options) -> serde_json::Value {
+    let time = |time: NaiveDateTime| match options.epoch {
+        None => json!(time.to_string()),
+        Some(Epoch::S) => json!(time.and_utc().timestamp()),
+        Some(Epoch::Ms) => json!(time.and_utc().timestamp_millis()),
+        Some(Epoch::Us) => json!(timestamp_nanos(time) / 1_000),
+        Some(Epoch::Ns) => json!(timestamp_nanos(time)),
+    };
+    let text = |bytes: &[u8]| String::from_utf8_lossy(bytes).into_owned();
+    let issue_code = printed_issue_code(record, options);
+    match record {
+        Record::Quote(quote_packet) => {
+            let side = |levels: &[(u32, u32)]| {
+                levels
+                    .iter()
+                    .map(|&(quantity, price)| json!({ "price": price, "quantity": quantity }))
+                    .collect::<Vec<_>>()
+            };
+            json!({
+                "type": "quote",
+                "number": quote_packet.number,
+                "offset": quote_packet.offset,
+                "message_type": text(&quote_packet.message_type),
+                "time_stamp": time(quote_packet.time_stamp),
+                "accept_time": time(quote_packet.quote_accept_time),
+                "issue_code": issue_code,
+                "issue_sequence": text(&quote_packet.issue_sequence),
+                "market_status": text(&quote_packet.market_status),
+                "bids": side(quote_packet.bids()),
+                "asks": side(quote_packet.asks()),
+            })
+        }
+        Record::Trade(trade_packet) => json!({
+            "type": "trade",
+            "number": trade_packet.number,
+            "offset": trade_packet.offset,
+            "message_type": text(&trade_packet.message_type),
+            "time_stamp": time(trade_packet.time_stamp),
+            "trade_time": time(trade_packet.trade_time),
+            "issue_code": issue_code,
+            "issue_sequence": text(&trade_packet.issue_sequence),
+            "board_id": text(&trade_packet.board_id),
+            "price": trade_packet.price,
+            "quantity": trade_packet.quantity,
+            "aggressor": trade_packet.aggressor.map(|aggressor| text(aggressor.as_bytes())),
+        }),
+    }
+}
+
+// Where packets are printed: the --output file, or stdout, which is only buffered when it isn't a
+// terminal.
+fn output_handle(options: &Options) -> Result<Box<dyn Write>, io::Error> {
+    if let (Some(path), OutputFormat::Text) = (&options.output, options.format) {
+        return Ok(Box::new(io::BufWriter::new(File::create(path)?)));
+    }
+    let stdout = io::stdout();
+    Ok(if stdout.is_terminal() {
+        Box::new(stdout.lock())
+    } else {
+        Box::new(io::BufWriter::new(stdout.lock()))
+    })
+}
+
+// The files `--split-by-issue` writes to, of which at most `max_open` are kept open. Each one is
+// created on the first packet of its issue, and reopened for appending if it had to be closed to
+// make room for another.
+struct IssueFiles<'a> {
+    dir: &'a Path,
+    max_open: usize,
+    // The open files, with when they were last written to.
+    open: HashMap<[u8; 12], (io::BufWriter<File>, u64)>,
+    created: HashSet<[u8; 12]>,
+    clock: u64,
+}
+
+impl<'a> IssueFiles<'a> {
+    fn new(dir: &'a str, max_open: usize) -> Result<Self, io::Error> {
+        fs::create_dir_all(dir)?;
+        Ok(IssueFiles {
+            dir: Path::new(dir),
+            max_open,
+            open: HashMap::new(),
+            created: HashSet::new(),
+            clock: 0,
+        })
+    }
+
+    fn get(&mut self, record: &Record) -> Result<&mut io::BufWriter<File>, io::Error> {
+        self.clock += 1;
+        let issue_code = *record.issue_code();
+        if !self.open.contains_key(&issue_code) {
+            if self.open.len() == self.max_open {
+                let least_recent = self
+                    .open
+                    .iter()
+                    .min_by_key(|(_, (_, last_used))| *last_used)
+                    .map(|(issue_code, _)| *issue_code);
+                if let Some((mut file, _)) = least_recent.and_then(|code| self.open.remove(&code)) {
+                    file.flush()?;
+                }
+            }
+            let name: String = record
+                .issue_code_str()
+                .chars()
+                .map(|c| match c {
+                    'A'..='Z' | 'a'..='z' | '0'..='9' | '-' | '_' => c,
+                    _ => '_',
+                })
+                .collect();
+            let path = self.dir.join(format!("{}.txt", name));
+            let file = if self.created.insert(issue_code) {
+                File::create(path)?
+            } else {
+                OpenOptions::new().append(true).open(path)?
+            };
+            self.open
+                .insert(issue_code, (io::BufWriter::new(file), self.clock));
+        }
+        let (file, last_used) = self.open.get_mut(&issue_code).unwrap();
+        *last_used = self.clock;
+        Ok(file)
+    }
+
+    fn flush(&mut self) -> Result<(), io::Error> {
+        for (file, _) in self.open.values_mut() {
+            file.flush()?;
+        }
+        Ok(())
+    }
+}
+
+// Where records end up once parsed, applying the `--head`/`--tail` selection. The tail is kept in
+// a ring buffer and only written out by `finish`, once we know which records are last.
+struct Output<'a, W: Write> {
+    handle: W,
+    options: &'a Options,
+    written: u64,
+    // How many quotes `--sample` has seen, printed or not.
+    sampled: u64,
+    tail: VecDeque<Record>,
+    // The best bid and ask prices of the latest quote written for each issue, under `--aggressor`.
+    books: HashMap<[u8; 12], (u32, u32)>,
+    // The last mid price, doubled, and its moving average for each issue, under `--ema`.
+    emas: HashMap<[u8; 12], (u64, f64)>,
+    // The accept times `--check-monotone` checks once reordered.
+    event_times: MonotoneCheck,
+    // Where `--gap-report` goes, and the latest accept time seen for each issue.
+    gaps: Option<Box<dyn Write>>,
+    last_accept_times: HashMap<[u8; 12], NaiveDateTime>,
+    // The accept times of the quotes of each issue in the last 100ms under
+    // `--quote-stuffing-threshold`, and whether it's been reported as over the limit.
+    stuffing: HashMap<[u8; 12], (VecDeque<NaiveDateTime>, bool)>,
+    // How many crossed quotes each issue had under `--alert-crossed-book`.
+    crossings: HashMap<String, u64>,
+    // The interval of `--every` being collected, and the latest quote of each issue in it.
+    interval: Option<i128>,
+    snapshot: BTreeMap<[u8; 12], Record>,
+    buffer: LineBuffer,
+    issue_files: Option<IssueFiles<'a>>,
+    #[cfg(feature = "parquet")]
+    parquet: Option<parquet_output::ParquetOutput>,
+}
+
+impl<'a, W: Write> Output<'a, W> {
+    fn new(handle: W, options: &'a Options) -> Result<Self, io::Error> {
+        let gaps: Option<Box<dyn Write>> = match (options.gap_report, &options.gap_output) {
+            (None, _) => None,
+            (Some(_), None) => Some(Box::new(io::stderr())),
+            (Some(_), Some(path)) => Some(Box::new(io::BufWriter::new(File::create(path)?))),
+        };
+        Ok(Output {
+            handle,
+            options,
+            written: 0,
+            sampled: 0,
+            tail: VecDeque::with_capacity(options.tail.unwrap_or(0)),
+            books: HashMap::new(),
+            emas: HashMap::new(),
+            event_times: MonotoneCheck::default(),
+            gaps,
+            last_accept_times: HashMap::new(),
+            stuffing: HashMap::new(),
+            crossings: HashMap::new(),
+            interval: None,
+            snapshot: BTreeMap::new(),
+            buffer: LineBuffer::default(),
+            issue_files: match &options.split_by_issue {
+                Some(dir) => Some(IssueFiles::new(dir, options.max_open_files as usize)?),
+                None => None,
+            },
+            #[cfg(feature = "parquet")]
+            parquet: match (options.format, &options.output) {
+                (OutputFormat::Parquet, Some(path)) => {
+                    Some(parquet_output::ParquetOutput::create(path)?)
+                }
+                _ => None,
+            },
+        })
+    }
+
+    fn done(&self) -> bool {
+        self.options.head.is_some_and(|head| self.written >= head)
+    }
+
+    fn report_gap(&mut self, record: &Record) -> Result<(), io::Error> {
+        let (gaps, threshold) = match (&mut self.gaps, self.options.gap_report, record) {
+            (Some(gaps), Some(threshold), Record::Quote(_)) => (gaps, threshold),
+            _ => return Ok(()),
+        };
+        let current = record.event_time();
+        // Without -r accept times of an issue can go back a little, so measure from the latest.
+        let previous = match self.last_accept_times.get_mut(record.issue_code()) {
+            Some(previous) if current > *previous => mem::replace(previous, current),
+            Some(_) => return Ok(()),
+            None => {
+                self.last_accept_times.insert(*record.issue_code(), current);
+                return Ok(());
+            }
+        };
+        let (mut from, mut to) = (previous, current);
+        if let Some((open, close)) = self.options.market_hours {
+            let accept_tz = Duration::seconds(self.options.accept_tz);
+            let date = (from + accept_tz).date();
+            from = from.max(date.and_time(open) - accept_tz);
+            to = to.min(date.and_time(close) - accept_tz);
+        }
+        let duration = to.signed_duration_since(from);
+        if duration <= Duration::seconds(threshold as i64) {
+            return Ok(());
+        }
+        writeln!(
+            gaps,
+            "GAP: {} from={} to={} duration={}s",
+            printed_issue_code(record, self.options),
+            Time(from, self.options.epoch),
+            Time(to, self.options.epoch),
+            duration.num_milliseconds() as f64 / 1_000.0
+        )
+    }
+
+    fn report_stuffing(&mut self, record: &Record) {
+        let threshold = match (self.options.quote_stuffing_threshold, record) {
+            (Some(threshold), Record::Quote(_)) => threshold as usize,
+            _ => return,
+        };
+        let current = record.event_time();
+        let (window, alerted) = self.stuffing.entry(*record.issue_code()).or_default();
+        // Without -r accept times can go back a little, which only makes the window a bit longer.
+        let start = current - Duration::milliseconds(STUFFING_WINDOW_MS);
+        while window.front().is_some_and(|&time| time <= start) {
+            window.pop_front();
+        }
+        window.push_back(current);
+        if window.len() <= threshold {
+            *alerted = false;
+        } else if !*alerted {
+            *alerted = true;
+            eprintln!(
+                "STUFFING_ALERT: {} rate={} updates/sec at {}",
+                printed_issue_code(record, self.options),
+                window.len() as i64 * 1_000 / STUFFING_WINDOW_MS,
+                Time(current, self.options.epoch)
+            );
+        }
+    }
+
+    fn report_crossed_book(&mut self, record: &Record) {
+        let quote_packet = match record {
+            Record::Quote(quote_packet) if self.options.alert_crossed_book => quote_packet,
+            _ => return,
+        };
+        if quote_packet.crossed() != Some("CROSSED") {
+            return;
+        }
+        let issue_code = printed_issue_code(record, self.options);
+        *self.crossings.entry(issue_code.to_owned()).or_default() += 1;
+        eprintln!(
+            "CROSSED: {} bid={} ask={} at {}",
+            issue_code,
+            quote_packet.bids[0].1,
+            quote_packet.asks[0].1,
+            Time(quote_packet.quote_accept_time, self.options.epoch)
+        );
+    }
+
+    fn write(&mut self, mut record: Record) -> Result<(), Box<dyn Error>> {
+        if self.done() {
+            return Ok(());
+        }
+        self.report_gap(&record)?;
+        self.report_stuffing(&record);
+        self.report_crossed_book(&record);
+        // Reordering is supposed to put records in event time order, so check that it did.
+        let reordered = self.options.reorder && self.options.sort_key == SortKey::Accept;
+        if self.options.check_monotone && (reordered || self.options.full_sort) {
+            let checked =
+                self.event_times
+                    .check("Accept time", record.event_time(), record.offset());
+            if let Err(e) = checked {
+                non_monotone(self.options, e)?;
+            }
+        }
+        if self.options.aggressor {
+            match &mut record {
+                Record::Quote(quote_packet) => {
+                    let best = (quote_packet.bids[0].1, quote_packet.asks[0].1);
+                    self.books.insert(quote_packet.issue_code, best);
+                }
+                Record::Trade(trade_packet) => {
+                    let best = self.books.get(&trade_packet.issue_code);
+                    trade_packet.aggressor = Some(match best {
+                        Some(&(_, ask)) if trade_packet.price == ask => Aggressor::Buyer,
+                        Some(&(bid, _)) if trade_packet.price == bid => Aggressor::Seller,
+                        _ => Aggressor::Unknown,
+                    });
+                }
+            }
+        }
+        if let (Some(alpha), Record::Quote(quote_packet)) = (self.options.ema, &mut record) {
+            let double_mid = match quote_packet.double_mid_price() {
+                Some(double_mid) => double_mid,
+                None => return Ok(()),
+            };
+            let mid = double_mid as f64 / 2.0;
+            let changed = match self.emas.get_mut(&quote_packet.issue_code) {
+                Some((last, ema)) => {
+                    *ema = alpha * mid + (1.0 - alpha) * *ema;
+                    mem::replace(last, double_mid) != double_mid
+                }
+                None => {
+                    self.emas.insert(quote_packet.issue_code, (double_mid, mid));
+                    true
+                }
+            };
+            if !changed && !self.options.ema_output_all {
+                return Ok(());
+            }
+            quote_packet.ema = Some(self.emas[&quote_packet.issue_code].1.round() as i64);
+        }
+        if let (Some(every), Record::Quote(quote_packet)) = (self.options.every, &record) {
+            let time = quote_packet.time_stamp.and_utc();
+            let nanos = i128::from(time.timestamp()) * 1_000_000_000
+                + i128::from(time.timestamp_subsec_nanos());
+            let every = every
+                .num_nanoseconds()
+                .expect("--every fits in nanoseconds");
+            let interval = nanos.div_euclid(i128::from(every));
+            // Under -r the timestamps can go back a little, and those quotes count toward the
+            // interval already being collected.
+            if self.interval.is_some_and(|current| interval > current) {
+                self.write_snapshot()?;
+            }
+            self.interval = Some(
+                self.interval
+                    .map_or(interval, |current| current.max(interval)),
+            );
+            self.snapshot.insert(quote_packet.issue_code, record);
+            return Ok(());
+        }
+        Ok(self.emit(record)?)
+    }
+
+    // Prints the quotes `--every` collected for the interval that just ended.
+    fn write_snapshot(&mut self) -> Result<(), io::Error> {
+        for (_, record) in mem::take(&mut self.snapshot) {
+            if self.done() {
+                break;
+            }
+            self.emit(record)?;
+        }
+        Ok(())
+    }
+
+    // Passes a record on to `--sample`, `--head` and `--tail` and then prints it.
+    fn emit(&mut self, record: Record) -> Result<(), io::Error> {
+        if let Record::Quote(_) = record {
+            self.sampled += 1;
+            if !(self.sampled - 1).is_multiple_of(self.options.sample) {
+                return Ok(());
+            }
+        }
+        self.written += 1;
+        match self.options.tail {
+            Some(tail) => {
+                if self.tail.len() == tail {
+                    self.tail.pop_front();
+                }
+                if tail > 0 {
+                    self.tail.push_back(record);
+                }
+                Ok(())
+            }
+            None => self.write_record(&record),
+        }
+    }
+
+    // Writes out a record that's been selected, to the file of its issue under `--split-by-issue`.
+    fn write_record(&mut self, record: &Record) -> Result<(), io::Error> {
+        #[cfg(feature = "parquet")]
+        if let Some(parquet) = &mut self.parquet {
+            return match record {
+                Record::Quote(_) if self.options.dry_run => Ok(()),
+                Record::Quote(quote_packet) => {
+                    parquet.write(quote_packet, printed_issue_code(record, self.options))
+                }
+                Record::Trade(_) => Ok(()),
+            };
+        }
+        let line_buffered = self.options.line_buffered;
+        match &mut self.issue_files {
+            Some(issue_files) => {
+                let handle = issue_files.get(record)?;
+                write_record(handle, &mut self.buffer, record, self.options)?;
+                if line_buffered {
+                    handle.flush()?;
+                }
+            }
+            None => {
+                write_record(&mut self.handle, &mut self.buffer, record, self.options)?;
+                if line_buffered {
+                    self.handle.flush()?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self, summary: &Summary) -> Result<(), io::Error> {
+        self.write_snapshot()?;
+        while let Some(record) = self.tail.pop_front() {
+            self.write_record(&record)?;
+        }
+        if self.options.count {
+            writeln!(self.handle, "{}", summary.quotes + summary.trades)?;
+        }
+        if let Some(gaps) = &mut self.gaps {
+            gaps.flush()?;
+        }
+        let mut crossings: Vec<_> = self.crossings.iter().collect();
+        crossings.sort();
+        for (issue_code, count) in crossings {
+            eprintln!("{}: {} crossed-book events", issue_code, count);
+        }
+        if let Some(issue_files) = &mut self.issue_files {
+            issue_files.flush()?;
+        }
+        #[cfg(feature = "parquet")]
+        if let Some(parquet) = &mut self.parquet {
+            parquet.finish()?;
+        }
+        self.handle.flush()
+    }
+}
+
+fn parse_file(options: &Options) -> Result<(), Box<dyn Error>> {
+    let mut input = open_file(options, options.path())?;
+    let mut summary = Summary::default();
+    let mut output = Output::new(output_handle(options)?, options)?;
+    while !output.done() {
+        match input.next_packet(options, &mut summary)? {
+            Some(record) => output.write(record)?,
+            None => break,
+        }
+    }
+    output.finish(&summary)?;
+    summary.report(options)
+}
+
+fn aggregate_by_symbol(options: &Options) -> Result<(), Box<dyn Error>> {
+    let mut groups: HashMap<[u8; 12], Vec<Record>> = HashMap::new();
+    let mut symbols = Vec::new();
+    let max_records = options
+        .max_memory
+        .map(|max_memory| max_memory * 1024 * 1024 / mem::size_of::<Record>() as u64);
+    let mut buffered = 0;
+    let mut input = open_file(options, options.path())?;
+    let mut summary = Summary::default();
+    while let Some(record) = input.next_packet(options, &mut summary)? {
+        buffered += 1;
+        if max_records.is_some_and(|max_records| buffered > max_records) {
+            return Err(format!(
+                "Buffering packets for --aggregate-by-symbol needs more than --max-memory {} MB, \
+                 stopped at offset {:#x}",
+                options.max_memory.unwrap_or_default(),
+                record.offset()
+            )
+            .into());
+        }
+        let issue_code = *record.issue_code();
+        groups
+            .entry(issue_code)
+            .or_insert_with(|| {
+                symbols.push(issue_code);
+                Vec::new()
+            })
+            .push(record);
+    }
+    if options.sort_symbols {
+        symbols.sort_unstable();
+    }
+    summary.flushed = buffered as usize;
+    let mut output = Output::new(output_handle(options)?, options)?;
+    for symbol in symbols {
+        let mut records = groups.remove(&symbol).unwrap_or_default();
+        // The sort is stable, so packets accepted at the same time keep their capture order.
+        records.sort_by_key(Record::event_time);
+        for record in records {
+            output.write(record)?;
+        }
+    }
+    output.finish(&summary)?;
+    summary.report(options)
+}
+
+// The packets -r holds back until their accept times are final, kept in the order they come out
+// in, which for `Record` is the greatest first. Packets mostly arrive in that order already, so they
+// usually just go on the back, and the rest are inserted after everything they don't come before.
+// The queue is sized up front and never shrinks, so a capture only pays for growing it once it
+// outgrows `--reorder-capacity`.
+struct ReorderBuffer<T> {
+    queue: VecDeque<T>,
+    high_water: usize,
+    max: Option<usize>,
+}
+
+impl<T: Ord> ReorderBuffer<T> {
+    fn new(options: &Options) -> Self {
+        ReorderBuffer {
+            // `--bench` has one without -r too, which never holds anything.
+            queue: VecDeque::with_capacity(if options.reorder {
+                options.reorder_capacity
+            } else {
+                0
+            }),
+            high_water: 0,
+            max: options.max_buffer,
+        }
+    }
+
+    fn peek(&self) -> Option<&T> {
+        self.queue.front()
+    }
+
+    fn pop(&mut self) -> Option<T> {
+        self.queue.pop_front()
+    }
+
+    // Buffers the packet at `offset`, unless that would take more than `--max-buffer` packets.
+    fn push(&mut self, item: T, offset: u64) -> Result<(), ParseError> {
+        if let Some(max) = self.max.filter(|&max| self.queue.len() >= max) {
+            return Err(ParseError::ReorderBuffer { offset, max });
+        }
+        if self.queue.back().is_none_or(|last| *last >= item) {
+            self.queue.push_back(item);
+        } else {
+            let position = self.queue.partition_point(|queued| *queued >= item);
+            self.queue.insert(position, item);
+        }
+        self.high_water = self.high_water.max(self.queue.len());
+        Ok(())
+    }
+
+    // Takes the packets left at the end in order, recording how many there were.
+    fn finish(&mut self, summary: &mut Summary) -> impl Iterator<Item = T> + '_ {
+        summary.flushed = self.queue.len();
+        summary.reorder_buffer = Some((self.high_water, self.queue.len()));
+        self.queue.drain(..)
+    }
+}
+
+// Whether no packet captured from `record` on can have an earlier event time than `top` anymore.
+fn is_final(top: &Record, record: &Record) -> bool {
+    // A malformed accept time far away from the timestamp mustn't overflow the difference.
+    record
+        .time_stamp()
+        .signed_duration_since(top.event_time())
+        .num_nanoseconds()
+        .unwrap_or(i64::MAX)
+        > MAX_DIFF * 1_000_000_000
+}
+
+// What -r orders packets by under `--sort-key`, earliest first, taken from a packet once when it's
+// buffered. The issue code is left blank unless packets are grouped by it.
+#[derive(PartialEq, Eq, PartialOrd, Ord)]
+struct ReorderKey {
+    issue_code: [u8; 12],
+    time: NaiveDateTime,
+    time_stamp: NaiveDateTime,
+    number: u64,
+}
+
+impl SortKey {
+    fn key(self, record: &Record) -> ReorderKey {
+        ReorderKey {
+            issue_code: match self {
+                SortKey::IssueAccept => *record.issue_code(),
+                SortKey::Accept | SortKey::Packet => [0; 12],
+            },
+            time: match self {
+                SortKey::Accept | SortKey::IssueAccept => record.event_time(),
+                SortKey::Packet => record.time_stamp(),
+            },
+            time_stamp: record.time_stamp(),
+            number: record.number(),
+        }
+    }
+
+    // Like `is_final`, but for packets ordered by this key.
+    fn is_final(self, top: &Record, record: &Record) -> bool {
+        match self {
+            SortKey::Accept => is_final(top, record),
+            SortKey::Packet => record.time_stamp() - top.time_stamp() > Duration::seconds(MAX_DIFF),
+            SortKey::IssueAccept => false,
+        }
+    }
+}
+
+// The captures -r reads, which are several with `--merge-sort-files` and `--merge`. Each is in
+// timestamp order, so always taking the earliest of their next packets keeps the stream as a whole
+// in timestamp order.
+// A capture is only read from again once its last packet has been taken, so with a single one
+// this is the same as reading it directly.
+struct SortedCaptures {
+    inputs: Vec<Input>,
+    // The next packet of every capture that has one, earliest first, and the capture it came from.
+    heads: BinaryHeap<(Reverse<NaiveDateTime>, Reverse<usize>, Record)>,
+    // The capture whose packet was taken last, and which still has to be read from again.
+    taken: Option<usize>,
+    started: bool,
+}
+
+impl SortedCaptures {
+    fn open(options: &Options) -> Result<Self, Box<dyn Error>> {
+        let mut inputs = Vec::with_capacity(options.paths.len());
+        for path in &options.paths {
+            inputs.push(open_file(options, path)?);
+        }
+        Ok(SortedCaptures {
+            inputs,
+            heads: BinaryHeap::new(),
+            taken: None,
+            started: false,
+        })
+    }
+
+    fn read(
+        &mut self,
+        source: usize,
+        options: &Options,
+        summary: &mut Summary,
+    ) -> Result<(), Box<dyn Error>> {
+        if let Some(record) = self.inputs[source].next_packet(options, summary)? {
+            self.heads
+                .push((Reverse(record.time_stamp()), Reverse(source), record));
+        }
+        Ok(())
+    }
+
+    // Returns the earliest packet left with the capture it came from, as an index into the paths.
+    fn next_packet(
+        &mut self,
+        options: &Options,
+        summary: &mut Summary,
+    ) -> Result<Option<(Record, usize)>, Box<dyn Error>> {
+        if !self.started {
+            self.started = true;
+            for source in 0..self.inputs.len() {
+                self.read(source, options, summary)?;
+            }
+        } else if let Some(source) = self.taken.take() {
+            self.read(source, options, summary)?;
+        }
+        if summary.interrupted {
+            return Ok(None);
+        }
+        Ok(self.heads.pop().map(|(_, Reverse(source), record)| {
+            self.taken = Some(source);
+            (record, source)
+        }))
+    }
+}
+
+fn parse_reorder(options: &Options) -> Result<(), Box<dyn Error>> {
+    let sort_key = options.sort_key;
+    // Greater keys come out first, like with `Record`.
+    let mut buffer: ReorderBuffer<(Reverse<ReorderKey>, Record)> = ReorderBuffer::new(options);
+    let mut input = SortedCaptures::open(options)?;
+    let mut summary = Summary::default();
+    let mut output = Output::new(output_handle(options)?, options)?;
+    while !output.done() {
+        let record = match input.next_packet(options, &mut summary)? {
+            Some((record, _)) => record,
+            None => break,
+        };
+        // Instead of buffering all the quote packets before printing them for a possibly
+        // expensive O(n) space and O(n*log(n)) time complexity where n = number of quote
+        // packets, we only keep track of the last 3 seconds of trading since our quote packets
+        // are already sorted by ascending order of timestamps and the difference between the
+        // latest timestamp and the earliest quote accept time can never exceed 3 seconds. This
+        // gives us O(k) space where k = number of quote packets that arrived in the last
+        // 3 seconds, and since most packets arrive in order, close to O(n) time.
+        while buffer
+            .peek()
+            .is_some_and(|(_, top)| sort_key.is_final(top, &record))
+        {
+            output.write(buffer.pop().unwrap().1)?;
+        }
+        let offset = record.offset();
+        buffer.push((Reverse(sort_key.key(&record)), record), offset)?;
+    }
+    // On interrupt we stop reading but still flush whatever is buffered.
+    for (_, record) in buffer.finish(&mut summary) {
+        output.write(record)?;
+    }
+    output.finish(&summary)?;
+    summary.report(options)
+}
+
+fn full_sort(options: &Options) -> Result<(), Box<dyn Error>> {
+    let dir = match &options.tmpdir {
+        Some(dir) => PathBuf::from(dir),
+        None => env::temp_dir(),
+    };
+    let mut sort = full_sort::ExternalSort::new(&dir, options.sort_run_size as usize);
+    let mut input = open_file(options, options.path())?;
+    let mut summary = Summary::default();
+    while let Some(record) = input.next_packet(options, &mut summary)? {
+        sort.push(record)?;
+    }
+    sort.finish()?;
+    let mut output = Output::new(output_handle(options)?, options)?;
+    while !output.done() {
+        match sort.next()? {
+            Some(record) => output.write(record)?,
+            None => break,
+        }
+    }
+    output.finish(&summary)?;
+    summary.report(options)
+}
+
+// Writes a packet of `--merge` unless the same message was already written from another capture.
+// Duplicates have the same event time, so only the packets written at the latest one are kept.
+fn write_merged<W: Write>(
+    output: &mut Output<W>,
+    written: &mut Vec<(Record, usize)>,
+    summary: &mut Summary,
+    (record, source): (Record, usize),
+) -> Result<(), Box<dyn Error>> {
+    if written
+        .first()
+        .is_some_and(|(last, _)| last.event_time() != record.event_time())
+    {
+        written.clear();
+    }
+    let duplicate = written
+        .iter()
+        .any(|(last, last_source)| *last_source != source && last.same_message(&record));
+    if duplicate {
+        summary.duplicates += 1;
+        return Ok(());
+    }
+    written.push((record.clone(), source));
+    output.write(record)
+}
+
+fn merge(options: &Options) -> Result<(), Box<dyn Error>> {
+    let mut input = SortedCaptures::open(options)?;
+    let mut summary = Summary::default();
+    // Among packets with the same event time, the one from the capture listed first comes out first.
+    // Packet numbers are counted per capture, so they can't tell packets of different ones apart.
+    let mut buffer: ReorderBuffer<(Reverse<NaiveDateTime>, Reverse<usize>, Record)> =
+        ReorderBuffer::new(options);
+    // The packets last written, all with the same event time, and the capture they came from.
+    let mut written: Vec<(Record, usize)> = Vec::new();
+    let mut output = Output::new(output_handle(options)?, options)?;
+    while !output.done() {
+        let (record, source) = match input.next_packet(options, &mut summary)? {
+            Some(next) => next,
+            None => break,
+        };
+        while buffer
+            .peek()
+            .is_some_and(|(_, _, top)| is_final(top, &record))
+        {
+            let (_, Reverse(top_source), top) = buffer.pop().unwrap();
+            write_merged(&mut output, &mut written, &mut summary, (top, top_source))?;
+        }
+        let offset = record.offset();
+        let event_time = Reverse(record.event_time());
+        buffer.push((event_time, Reverse(source), record), offset)?;
+    }
+    for (_, Reverse(source), record) in buffer.finish(&mut summary) {
+        write_merged(&mut output, &mut written, &mut summary, (record, source))?;
+    }
+    output.finish(&summary)?;
+    summary.report(options)
+}
+
+fn info(options: &Options) -> Result<(), Box<dyn Error>> {
+    let path = options.path();
+    let mut file = if is_url(path) {
+        open_url(options, path)?
+    } else {
+        File::open(path)?
+    };
+    let header = read_pcap_header(&mut file, options)?;
+    let stdout = io::stdout();
+    let mut handle = stdout.lock();
+    writeln!(
+        handle,
+        "Byte order: {}",
+        match header.endianness {
+            LittleEndian => "little-endian",
+            BigEndian => "big-endian",
+        }
+    )?;
+    writeln!(
+        handle,
+        "Timestamps: {}",
+        match header.precision {
+            Microsecond => "microseconds",
+            Nanosecond => "nanoseconds",
+        }
+    )?;
+    writeln!(
+        handle,
+        "Version: {}.{}",
+        header.version_major, header.version_minor
+    )?;
+    writeln!(handle, "Time zone offset: {} seconds", header.this_zone)?;
+    writeln!(handle, "Snaplen: {}", header.snaplen)?;
+    writeln!(
+        handle,
+        "Link type: {} ({})",
+        header.link_type,
+        LinkType::from_header(header.link_type).map_or("unsupported", LinkType::name)
+    )?;
+    Ok(())
+}
+
+fn check_monotonic(options: &Options) -> Result<(), Box<dyn Error>> {
+    let mut input = open_file(options, options.path())?;
+    let mut summary = Summary::default();
+    let stdout = io::stdout();
+    let mut handle = stdout.lock();
+    let mut time_stamps = MonotoneCheck::default();
+    let mut largest_gap: Option<(Duration, u64, u64)> = None;
+    let mut backward = 0;
+    while let Some(record) = input.next_packet(options, &mut summary)? {
+        let time_stamp = record.time_stamp();
+        match time_stamps.check("Timestamp", time_stamp, record.offset()) {
+            Err(e) => {
+                backward += 1;
+                writeln!(handle, "{}", e)?;
+            }
+            Ok(Some((previous, previous_offset))) => {
+                let gap = time_stamp.signed_duration_since(previous);
+                if largest_gap.is_none_or(|(largest, _, _)| gap > largest) {
+                    largest_gap = Some((gap, previous_offset, record.offset()));
+                }
+            }
+            Ok(None) => {}
+        }
+    }
+    writeln!(handle, "{} backward timestamps found", backward)?;
+    if let Some((gap, from, to)) = largest_gap {
+        let nanoseconds = gap.num_nanoseconds().unwrap_or(i64::MAX);
+        writeln!(
+            handle,
+            "Largest gap: {}.{:09}s between offsets {:#x} and {:#x}",
+            nanoseconds / 1_000_000_000,
+            nanoseconds % 1_000_000_000,
+            from,
+            to
+        )?;
+    }
+    summary.report(options)
+}
+
+fn quote_rate(options: &Options, window: i64) -> Result<(), Box<dyn Error>> {
+    let mut bins: HashMap<([u8; 12], i64), u64> = HashMap::new();
+    // Issues in the order they first appear, with the window they first appear in.
+    let mut symbols: Vec<([u8; 12], i64)> = Vec::new();
+    let mut windows: Option<(i64, i64)> = None;
+    let mut input = open_file(options, options.path())?;
+    let mut summary = Summary::default();
+    while let Some(record) = input.next_packet(options, &mut summary)? {
+        if let Record::Quote(quote_packet) = record {
+            let bin = quote_packet
+                .quote_accept_time
+                .and_utc()
+                .timestamp()
+                .div_euclid(window);
+            let issue_code = quote_packet.issue_code;
+            match symbols.iter_mut().find(|(symbol, _)| *symbol == issue_code) {
+                Some((_, first)) => *first = bin.min(*first),
+                None => symbols.push((issue_code, bin)),
+            }
+            *bins.entry((issue_code, bin)).or_insert(0) += 1;
+            windows =
+                Some(windows.map_or((bin, bin), |(first, last)| (first.min(bin), last.max(bin))));
+        }
+    }
+    let stdout = io::stdout();
+    let mut handle = stdout.lock();
+    if let Some((first, last)) = windows {
+        for bin in first..=last {
+            let window_start = naive_from_timestamp(bin * window, 0).ok_or(INVALID_TIMESTAMP)?;
+            for (issue_code, _) in symbols.iter().filter(|&&(_, first)| first <= bin) {
+                let issue_code_str = str::from_utf8(issue_code).unwrap_or_default();
+                writeln!(
+                    handle,
+                    "{} {} {}",
+                    Time(window_start, options.epoch),
+                    if options.normalize_issue_code {
+                        issue_code_str.trim_end_matches(' ')
+                    } else {
+                        issue_code_str
+                    },
+                    bins.get(&(*issue_code, bin)).unwrap_or(&0)
+                )?;
+            }
+        }
+    }
+    summary.report(options)
+}
+
+fn volume_profile(options: &Options) -> Result<(), Box<dyn Error>> {
+    let resolution = options.volume_profile_resolution;
+    let mut volumes: HashMap<([u8; 12], u32), u64> = HashMap::new();
+    // The position of every issue in the order they first appear.
+    let mut symbols: HashMap<[u8; 12], usize> = HashMap::new();
+    let mut input = open_file(options, options.path())?;
+    let mut summary = Summary::default();
+    while let Some(record) = input.next_packet(options, &mut summary)? {
+        if let Record::Quote(quote_packet) = record {
+            let issue_code = quote_packet.issue_code;
+            let next = symbols.len();
+            symbols.entry(issue_code).or_insert(next);
+            for &(quantity, price) in quote_packet.bids().iter().chain(quote_packet.asks()) {
+                // Empty levels have no price to put their quantity at.
+                if price == 0 {
+                    continue;
+                }
+                let bin = price / resolution * resolution;
+                *volumes.entry((issue_code, bin)).or_insert(0) += u64::from(quantity);
+            }
+        }
+    }
+    let mut levels: Vec<_> = volumes.into_iter().collect();
+    levels.sort_unstable_by_key(|&((issue_code, price), _)| (symbols[&issue_code], price));
+    let stdout = io::stdout();
+    let mut handle = stdout.lock();
+    for ((issue_code, price), quantity) in levels {
+        let issue_code_str = str::from_utf8(&issue_code).unwrap_or_default();
+        writeln!(
+            handle,
+            "{} {} {}",
+            if options.normalize_issue_code {
+                issue_code_str.trim_end_matches(' ')
+            } else {
+                issue_code_str
+            },
+            price,
+            quantity
+        )?;
+    }
+    summary.report(options)
+}
+
+fn latency_stats(options: &Options) -> Result<(), Box<dyn Error>> {
+    let mut histogram = latency::Histogram::default();
+    let mut input = open_file(options, options.path())?;
+    let mut summary = Summary::default();
+    while let Some(record) = input.next_packet(options, &mut summary)? {
+        if let Record::Quote(quote_packet) = record {
+            histogram.record(
+                timestamp_nanos(quote_packet.time_stamp)
+                    - timestamp_nanos(quote_packet.quote_accept_time),
+            );
+        }
+    }
+    let milliseconds = |nanoseconds: Option<i64>| match nanoseconds {
+        Some(nanoseconds) => format!("{:.3}", nanoseconds as f64 / 1e6),
+        None => "-".to_string(),
+    };
+    println!(
+        "quotes={} min={} median={} p99={} max={}",
+        histogram.count(),
+        milliseconds(histogram.min()),
+        milliseconds(histogram.quantile(0.5)),
+        milliseconds(histogram.quantile(0.99)),
+        milliseconds(histogram.max())
+    );
+    summary.report(options)
+}
+
+// The Pearson correlation of two series over the points where both have a value.
+fn correlation(a: &[Option<f64>], b: &[Option<f64>]) -> Option<f64> {
+    let pairs: Vec<(f64, f64)> = a
+        .iter()
+        .zip(b)
+        .filter_map(|(&a, &b)| Some((a?, b?)))
+        .collect();
+    if pairs.len() < 2 {
+        return None;
+    }
+    let n = pairs.len() as f64;
+    let mean_a = pairs.iter().map(|&(a, _)| a).sum::<f64>() / n;
+    let mean_b = pairs.iter().map(|&(_, b)| b).sum::<f64>() / n;
+    let (mut covariance, mut variance_a, mut variance_b) = (0.0, 0.0, 0.0);
+    for &(a, b) in &pairs {
+        covariance += (a - mean_a) * (b - mean_b);
+        variance_a += (a - mean_a) * (a - mean_a);
+        variance_b += (b - mean_b) * (b - mean_b);
+    }
+    if variance_a == 0.0 || variance_b == 0.0 {
+        return None;
+    }
+    Some(covariance / (variance_a * variance_b).sqrt())
+}
+
+fn correlation_matrix(options: &Options) -> Result<(), Box<dyn Error>> {
+    let interval = options.correlation_interval;
+    // Issues in the order they first appear, and the last mid price of each in every interval
+    // it was quoted in, with the accept time of the quote it's from.
+    let mut symbols: Vec<[u8; 12]> = Vec::new();
+    let mut mids: HashMap<[u8; 12], HashMap<i64, (NaiveDateTime, f64)>> = HashMap::new();
+    let mut intervals: Option<(i64, i64)> = None;
+    let mut warned = false;
+    let mut input = open_file(options, options.path())?;
+    let mut summary = Summary::default();
+    while let Some(record) = input.next_packet(options, &mut summary)? {
+        let quote_packet = match record {
+            Record::Quote(quote_packet) => quote_packet,
+            Record::Trade(_) => continue,
+        };
+        let mid = match quote_packet.double_mid_price() {
+            Some(double_mid) => double_mid as f64 / 2.0,
+            None => continue,
+        };
+        let issue_code = quote_packet.issue_code;
+        if !mids.contains_key(&issue_code) {
+            if symbols.len() as u64 == options.max_symbols {
+                if !warned {
+                    eprintln!(
+                        "Warning: more than {} issues, only correlating the first of them",
+                        options.max_symbols
+                    );
+                    warned = true;
+                }
+                continue;
+            }
+            symbols.push(issue_code);
+        }
+        let accept_time = quote_packet.quote_accept_time;
+        let bin = accept_time.and_utc().timestamp().div_euclid(interval);
+        let last = mids
+            .entry(issue_code)
+            .or_default()
+            .entry(bin)
+            .or_insert((accept_time, mid));
+        // Without -r accept times can go back a little, so keep the latest quote's.
+        if accept_time >= last.0 {
+            *last = (accept_time, mid);
+        }
+        intervals =
+            Some(intervals.map_or((bin, bin), |(first, last)| (first.min(bin), last.max(bin))));
+    }
+    let (first, last) = intervals.unwrap_or((0, -1));
+    let returns: Vec<Vec<Option<f64>>> = symbols
+        .iter()
+        .map(|issue_code| {
+            let mids = &mids[issue_code];
+            let mut price = None;
+            let mut returns = Vec::new();
+            for bin in first..=last {
+                let previous = price;
+                if let Some(&(_, mid)) = mids.get(&bin) {
+                    price = Some(mid);
+                }
+                if bin > first {
+                    returns.push(match (previous, price) {
+                        (Some(previous), Some(price)) => Some((price - previous) / previous),
+                        _ => None,
+                    });
+                }
+            }
+            returns
+        })
+        .collect();
+    let issue_codes: Vec<&str> = symbols
+        .iter()
+        .map(|issue_code| {
+            let issue_code_str = str::from_utf8(issue_code).unwrap_or_default();
+            if options.normalize_issue_code {
+                issue_code_str.trim_end_matches(' ')
+            } else {
+                issue_code_str
+            }
+        })
+        .collect();
+    let stdout = io::stdout();
+    let mut handle = stdout.lock();
+    for issue_code in &issue_codes {
+        write!(handle, ",{}", issue_code)?;
+    }
+    writeln!(handle)?;
+    for (issue_code, a) in issue_codes.iter().zip(&returns) {
+        write!(handle, "{}", issue_code)?;
+        for b in &returns {
+            match correlation(a, b) {
+                Some(correlation) => write!(handle, ",{:.4}", correlation)?,
+                None => write!(handle, ",NaN")?,
+            }
+        }
+        writeln!(handle)?;
+    }
+    summary.report(options)
+}
+
+// One timed pass of `--bench` over the capture.
+struct Pass {
+    elapsed: time::Duration,
+    reading: time::Duration,
+    parsing: time::Duration,
+    bytes: u64,
+    records: u64,
+    packets: u64,
+    // What the packet loop allocated, if allocations are counted.
+    allocations: Option<u64>,
+    checksum: u64,
+}
+
+// Mixes the fields of a record into a checksum, so the compiler can't skip producing them.
+fn fold_checksum(checksum: u64, record: &Record) -> u64 {
+    let mut checksum = checksum.rotate_left(5) ^ record.offset();
+    checksum = checksum.rotate_left(5) ^ timestamp_nanos(record.event_time()) as u64;
+    for &byte in record.issue_code() {
+        checksum = checksum.rotate_left(5) ^ u64::from(byte);
+    }
+    match record {
+        Record::Quote(quote_packet) => {
+            for &(quantity, price) in quote_packet.bids().iter().chain(quote_packet.asks()) {
+                checksum = checksum.rotate_left(5) ^ u64::from(quantity) ^ u64::from(price) << 32;
+            }
+        }
+        Record::Trade(trade_packet) => {
+            checksum = checksum.rotate_left(5)
+                ^ u64::from(trade_packet.quantity)
+                ^ u64::from(trade_packet.price) << 32;
+        }
+    }
+    checksum
+}
+
+fn bench_pass(
+    options: &Options,
+    buffer: &mut ReorderBuffer<Record>,
+    summary: &mut Summary,
+) -> Result<Pass, Box<dyn Error>> {
+    READ_NANOS.store(0, atomic::Ordering::Relaxed);
+    let started = time::Instant::now();
+    let mut parsing = time::Duration::default();
+    let mut checksum = 0;
+    let mut input = open_file(options, options.path())?;
+    let start_offset = input.state.offset;
+    let allocated = allocations();
+    loop {
+        let parse_started = time::Instant::now();
+        let record = input.next_packet(options, summary)?;
+        parsing += parse_started.elapsed();
+        let record = match record {
+            Some(record) => record,
+            None => break,
+        };
+        if !options.reorder {
+            checksum = fold_checksum(checksum, &record);
+            continue;
+        }
+        while buffer.peek().is_some_and(|top| is_final(top, &record)) {
+            checksum = fold_checksum(checksum, &buffer.pop().unwrap());
+        }
+        let offset = record.offset();
+        buffer.push(record, offset)?;
+    }
+    let allocations = allocations()
+        .zip(allocated)
+        .map(|(after, before)| after - before);
+    if options.reorder {
+        summary.reorder_buffer = Some((buffer.high_water, buffer.queue.len()));
+    }
+    // Popping keeps the queue's capacity for the next pass.
+    while let Some(record) = buffer.pop() {
+        checksum = fold_checksum(checksum, &record);
+    }
+    let reading = time::Duration::from_nanos(READ_NANOS.load(atomic::Ordering::Relaxed));
+    Ok(Pass {
+        elapsed: started.elapsed(),
+        reading,
+        parsing: parsing.saturating_sub(reading),
+        bytes: input.state.offset - start_offset,
+        records: summary.records,
+        packets: summary.quotes + summary.trades,
+        allocations,
+        checksum: std::hint::black_box(checksum),
+    })
+}
+
+// How many allocations `--bench` has counted so far, when built to count them.
+#[cfg(feature = "count-allocations")]
+fn allocations() -> Option<u64> {
+    Some(allocations::counted())
+}
+
+#[cfg(not(feature = "count-allocations"))]
+fn allocations() -> Option<u64> {
+    None
+}
+
+fn bench(options: &Options) -> Result<(), Box<dyn Error>> {
+    TIME_READS.store(true, atomic::Ordering::Relaxed);
+    #[cfg(feature = "count-allocations")]
+    allocations::count_this_thread();
+    let stdout = io::stdout();
+    let mut handle = stdout.lock();
+    let mut passes = Vec::with_capacity(options.repeat);
+    let mut summary = Summary::default();
+    let mut buffer = ReorderBuffer::new(options);
+    for number in 1..=options.repeat.max(1) {
+        if INTERRUPTED.load(atomic::Ordering::SeqCst) {
+            break;
+        }
+        summary = Summary::default();
+        let pass = bench_pass(options, &mut buffer, &mut summary)?;
+        if options.repeat > 1 {
+            writeln!(
+                handle,
+                "Pass {}: {:.6}s",
+                number,
+                pass.elapsed.as_secs_f64()
+            )?;
+        }
+        passes.push(pass);
+    }
+    passes.sort_by_key(|pass| pass.elapsed);
+    let (best, median) = match (passes.first(), passes.get(passes.len() / 2)) {
+        (Some(best), Some(median)) => (best, median),
+        _ => return Ok(()),
+    };
+    let seconds = best.elapsed.as_secs_f64();
+    let share = |phase: time::Duration| 100.0 * phase.as_secs_f64() / seconds;
+    let reordering = best.elapsed.saturating_sub(best.reading + best.parsing);
+    if passes.len() > 1 {
+        writeln!(
+            handle,
+            "Elapsed: best {:.6}s, median {:.6}s over {} passes",
+            seconds,
+            median.elapsed.as_secs_f64(),
+            passes.len()
+        )?;
+    } else {
+        writeln!(handle, "Elapsed: {:.6}s", seconds)?;
+    }
+    writeln!(
+        handle,
+        "Throughput: {:.1} MB/s, {:.0} records/s, {:.0} packets/s",
+        best.bytes as f64 / seconds / 1_000_000.0,
+        best.records as f64 / seconds,
+        best.packets as f64 / seconds
+    )?;
+    write!(
+        handle,
+        "Time spent: reading {:.1}%, parsing {:.1}%",
+        share(best.reading),
+        share(best.parsing)
+    )?;
+    if options.reorder {
+        write!(handle, ", reordering {:.1}%", share(reordering))?;
+    }
+    writeln!(handle)?;
+    writeln!(
+        handle,
+        "Parsed {} bytes, {} records, {} packets, checksum {:016x}",
+        best.bytes, best.records, best.packets, best.checksum
+    )?;
+    if let Some(allocations) = best.allocations {
+        writeln!(handle, "Allocations in the packet loop: {}", allocations)?;
+    }
+    summary.report(options)
+}
+
+fn run(options: &Options) -> Result<(), Box<dyn Error>> {
+    READ_RETRIES.store(options.read_retries, atomic::Ordering::SeqCst);
+    ctrlc::set_handler(|| INTERRUPTED.store(true, atomic::Ordering::SeqCst))?;
+    if options.info {
+        info(options)
+    } else if options.bench {
+        bench(options)
+    } else if options.check_monotonic {
+        check_monotonic(options)
+    } else if let Some(window) = options.quote_rate {
+        quote_rate(options, window)
+    } else if options.volume_profile {
+        volume_profile(options)
+    } else if options.correlation_matrix {
+        correlation_matrix(options)
+    } else if options.latency_stats {
+        latency_stats(options)
+    } else if options.merge {
+        merge(options)
+    } else if options.aggregate_by_symbol {
+        aggregate_by_symbol(options)
+    } else if options.full_sort {
+        full_sort(options)
+    } else if options.reorder {
+        parse_reorder(options)
+    } else {
+        parse_file(options)
+    }
+}
+
+fn main() {
+    let mut options = Options::parse();
+    if options.tape {
+        options.reorder = true;
+        options.only = Only::All;
+    }
+    if options.yaml_flow {
+        options.yaml = true;
+    }
+    if options.watch {
+        options.line_buffered = true;
+    }
+    #[cfg(feature = "parquet")]
+    if options.format == OutputFormat::Parquet
+        && (options.only != Only::Quotes
+            || options.mdi
+            || options.touch
+            || options.touch_mid
+            || options.yaml
+            || options.msgpack
+            || options.ema.is_some()
+            || options.split_by_issue.is_some())
+    {
+        Options::command()
+            .error(
+                clap::error::ErrorKind::ArgumentConflict,
+                "--format parquet only writes quotes, one row each",
+            )
+            .exit();
+    }
+    if options.color == Color::Auto {
+        let no_color = env::var_os("NO_COLOR").is_some_and(|no_color| !no_color.is_empty());
+        let terminal = options.output.is_none()
+            && options.split_by_issue.is_none()
+            && io::stdout().is_terminal();
+        options.color = if terminal && !no_color {
+            Color::Always
+        } else {
+            Color::Never
+        };
+    }
+    // Checked by hand since --tape turns on -r too.
+    if options.sort_key != SortKey::Accept && !options.reorder {
+        Options::command()
+            .error(
+                clap::error::ErrorKind::MissingRequiredArgument,
+                "--sort-key only applies to -r and --tape",
+            )
+            .exit();
+    }
+    let mut expanded = false;
+    let mut paths = Vec::with_capacity(options.paths.len());
+    for path in &options.paths {
+        match expand_path(path, options.recursive) {
+            Ok(Some(captures)) => {
+                expanded = true;
+                paths.extend(captures);
+            }
+            Ok(None) => paths.push(path.clone()),
+            Err(message) => Options::command()
+                .error(clap::error::ErrorKind::ValueValidation, message)
+                .exit(),
+        }
+    }
+    options.paths = paths;
+    if options.merge || options.merge_sort_files {
+        options.reorder = true;
+    } else if options.paths.len() > 1 && expanded {
+        Options::command()
+            .error(
+                clap::error::ErrorKind::TooManyValues,
+                format!(
+                    "FILENAME matched {} captures, which can only be read together with --merge or \
+                     --merge-sort-files",
+                    options.paths.len()
+                ),
+            )
+            .exit();
+    } else if options.paths.len() > 1 {
+        Options::command()
+            .error(
+                clap::error::ErrorKind::TooManyValues,
+                "more than one FILENAME is only allowed with --merge or --merge-sort-files",
+            )
+            .exit();
+    }
+    if let Some(Command::Completions { shell }) = options.command {
+        // `generate` panics when it can't write, like into a closed pipe, so the script is written
+        // out here instead.
+        let mut script = Vec::new();
+        clap_complete::generate(shell, &mut Options::command(), "parse-quote", &mut script);
+        if let Err(e) = io::stdout().write_all(&script) {
+            exit_with_error(&options, e.into());
+        }
+        return;
+    }
+    if options.build_info {
+        println!("parse-quote {}", env!("CARGO_PKG_VERSION"));
+        println!("profile: {}", env!("BUILD_PROFILE"));
+        println!("target: {}", env!("BUILD_TARGET"));
+        println!("commit: {}", env!("BUILD_GIT_COMMIT"));
+        return;
+    }
+    env_logger::Builder::new()
+        .filter_level(match options.verbosity {
+            0 => LevelFilter::Warn,
+            1 => LevelFilter::Debug,
+            _ => LevelFilter::Trace,
+        })
+        .format_timestamp(None)
+        .format_target(false)
+        .init();
+    if expanded {
+        for path in &options.paths {
+            debug!("Reading {}", path);
+        }
+    }
+    run(&options).unwrap_or_else(|e| exit_with_error(&options, e));
+}
+
+// Prints the error that stopped the run in the `--error-format` asked for and exits with status 1.
+fn exit_with_error(options: &Options, e: Box<dyn Error>) -> ! {
+    match options.error_format {
+        ErrorFormat::Text => eprintln!("Error: {}", e),
+        ErrorFormat::Json => match e.downcast_ref::<PacketError>() {
+            Some(e) => eprintln!("{}", error_json(&*e.source, Some(e.offset))),
+            None => eprintln!("{}", error_json(&*e, None)),
+        },
     }
-    .unwrap_or_else(|e| {
-        eprintln!("Error: {}", e);
-        process::exit(1);
-    });
+    process::exit(1);
 }
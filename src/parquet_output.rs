@@ -0,0 +1,148 @@
+//! `--format parquet`, which writes quotes as the rows of a Parquet file for Arrow, DuckDB and the
+//! like, instead of lines of text.
+//!
+//! The schema is stable. Columns are never renamed, retyped or reordered, and new ones only ever
+//! go at the end:
+//!
+//! | Column                                    | Type               | Contents                    |
+//! |-------------------------------------------|--------------------|-----------------------------|
+//! | `number`                                  | UInt64             | Packet number, from 1       |
+//! | `offset`                                  | UInt64             | File offset of the record   |
+//! | `time_stamp`                              | Timestamp(ns, UTC) | Capture time                |
+//! | `accept_time`                             | Timestamp(ns, UTC) | Quote accept time           |
+//! | `issue_code`                              | Utf8               | Issue code, as printed      |
+//! | `bid_price_1`, `bid_quantity_1`, ... `5`  | UInt32             | Bid levels, best first      |
+//! | `ask_price_1`, `ask_quantity_1`, ... `5`  | UInt32             | Ask levels, best first      |
+//!
+//! An empty level has a price and quantity of 0. Extended quotes only have their best five levels
+//! on each side written, since the schema never changes. Rows are written in batches of `BATCH_ROWS`,
+//! which the writer gathers into row groups.
+
+use crate::QuotePacket;
+use arrow_array::builder::{
+    ArrayBuilder, StringBuilder, TimestampNanosecondBuilder, UInt32Builder, UInt64Builder,
+};
+use arrow_array::{ArrayRef, RecordBatch};
+use arrow_schema::{DataType, Field, Schema, SchemaRef, TimeUnit};
+use parquet::arrow::ArrowWriter;
+use parse_quote::payload::{QuoteData, QUOTE_LEVELS};
+use std::fs::File;
+use std::io;
+use std::sync::Arc;
+
+const BATCH_ROWS: usize = 64 * 1024;
+
+fn timestamp_field(name: &str) -> Field {
+    let data_type = DataType::Timestamp(TimeUnit::Nanosecond, Some("UTC".into()));
+    Field::new(name, data_type, false)
+}
+
+fn schema() -> Schema {
+    let mut fields = vec![
+        Field::new("number", DataType::UInt64, false),
+        Field::new("offset", DataType::UInt64, false),
+        timestamp_field("time_stamp"),
+        timestamp_field("accept_time"),
+        Field::new("issue_code", DataType::Utf8, false),
+    ];
+    for side in &["bid", "ask"] {
+        for level in 1..=5 {
+            for column in &["price", "quantity"] {
+                let name = format!("{}_{}_{}", side, column, level);
+                fields.push(Field::new(name, DataType::UInt32, false));
+            }
+        }
+    }
+    Schema::new(fields)
+}
+
+fn timestamp_builder() -> TimestampNanosecondBuilder {
+    TimestampNanosecondBuilder::with_capacity(BATCH_ROWS).with_timezone("UTC")
+}
+
+// Parquet and Arrow errors are all about the file being written, so they're reported like I/O ones.
+fn io_error(e: impl std::error::Error + Send + Sync + 'static) -> io::Error {
+    io::Error::other(e)
+}
+
+pub(crate) struct ParquetOutput {
+    writer: ArrowWriter<File>,
+    schema: SchemaRef,
+    number: UInt64Builder,
+    offset: UInt64Builder,
+    time_stamp: TimestampNanosecondBuilder,
+    accept_time: TimestampNanosecondBuilder,
+    issue_code: StringBuilder,
+    // The price and quantity of every level, in the order of their columns.
+    levels: Vec<UInt32Builder>,
+}
+
+impl ParquetOutput {
+    pub(crate) fn create(path: &str) -> Result<Self, io::Error> {
+        let schema = Arc::new(schema());
+        let writer =
+            ArrowWriter::try_new(File::create(path)?, schema.clone(), None).map_err(io_error)?;
+        Ok(ParquetOutput {
+            writer,
+            schema,
+            number: UInt64Builder::with_capacity(BATCH_ROWS),
+            offset: UInt64Builder::with_capacity(BATCH_ROWS),
+            time_stamp: timestamp_builder(),
+            accept_time: timestamp_builder(),
+            issue_code: StringBuilder::with_capacity(BATCH_ROWS, 12 * BATCH_ROWS),
+            levels: (0..20)
+                .map(|_| UInt32Builder::with_capacity(BATCH_ROWS))
+                .collect(),
+        })
+    }
+
+    pub(crate) fn write(
+        &mut self,
+        quote_packet: &QuotePacket,
+        issue_code: &str,
+    ) -> Result<(), io::Error> {
+        self.number.append_value(quote_packet.number);
+        self.offset.append_value(quote_packet.offset);
+        self.time_stamp
+            .append_value(crate::timestamp_nanos(quote_packet.time_stamp));
+        self.accept_time
+            .append_value(crate::timestamp_nanos(quote_packet.quote_accept_time));
+        self.issue_code.append_value(issue_code);
+        let levels = quote_packet.bids()[..QUOTE_LEVELS]
+            .iter()
+            .chain(&quote_packet.asks()[..QUOTE_LEVELS]);
+        for (columns, &(quantity, price)) in self.levels.chunks_mut(2).zip(levels) {
+            columns[0].append_value(price);
+            columns[1].append_value(quantity);
+        }
+        if self.number.len() == BATCH_ROWS {
+            self.write_batch()?;
+        }
+        Ok(())
+    }
+
+    fn write_batch(&mut self) -> Result<(), io::Error> {
+        if self.number.is_empty() {
+            return Ok(());
+        }
+        let mut columns: Vec<ArrayRef> = vec![
+            Arc::new(self.number.finish()),
+            Arc::new(self.offset.finish()),
+            Arc::new(self.time_stamp.finish()),
+            Arc::new(self.accept_time.finish()),
+            Arc::new(self.issue_code.finish()),
+        ];
+        for level in &mut self.levels {
+            columns.push(Arc::new(level.finish()));
+        }
+        let batch = RecordBatch::try_new(self.schema.clone(), columns).map_err(io_error)?;
+        self.writer.write(&batch).map_err(io_error)
+    }
+
+    // Writes the rows still batched up and the file footer, without which the file can't be read.
+    pub(crate) fn finish(&mut self) -> Result<(), io::Error> {
+        self.write_batch()?;
+        self.writer.finish().map_err(io_error)?;
+        Ok(())
+    }
+}
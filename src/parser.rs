@@ -0,0 +1,1083 @@
+//! Reading the records of a pcap capture and parsing the quote and trade packets in them, from
+//! anything that implements `Read`. Only `--resync` and `--skip-bytes` need to seek, which `Input`
+//! takes care of.
+
+use crate::{
+    naive_from_timestamp, payload_error, within_message_limit, ByteOrder, Input, Only, Options,
+    ParseError, QuotePacket, Record, TimestampUnit, TradePacket, DUMPED, DUMP_SIZE,
+    INVALID_TIMESTAMP, MAX_DIFF, MAX_RECORD_SIZE, QUOTE_EXT_PACKET_SIZE, READ_NANOS, READ_RETRIES,
+    RETRY_BACKOFF, SCRATCH_SIZE, SECONDS_IN_A_DAY, TIME_READS, TRADE_PACKET_SIZE,
+};
+use chrono::NaiveDateTime;
+use log::{debug, log_enabled, trace, Level};
+use parse_quote::payload::{
+    self, ExchangeTime, PayloadError, ASKS_START, BIDS_START, EXCHANGE_TIME_STEP, ISSUE_CODE_START,
+    MESSAGE_TYPE_SIZE, QUOTE_ACCEPT_START, QUOTE_EXT_ACCEPT_START, QUOTE_EXT_ASKS_START,
+    QUOTE_EXT_PAYLOAD_SIZE, QUOTE_MESSAGE_TYPE, QUOTE_PAYLOAD_SIZE, TRADE_PAYLOAD_SIZE,
+};
+use parse_quote::pcap::{self, Endianness, LinkType, PcapHeader, Precision, UDP_PROTOCOL};
+use std::convert::TryInto;
+use std::error::Error;
+use std::fmt::{self, Write as _};
+use std::io::{self, ErrorKind, Read};
+use std::sync::atomic;
+use std::{thread, time};
+
+use Endianness::*;
+use Parser::*;
+
+pub(crate) const HEADER_SIZE: u64 = 24;
+pub(crate) const RECORD_HEADER_SIZE: u64 = 12;
+// The record data starts with the original length of the packet, which isn't part of the header
+// as we read it.
+const ORIGINAL_LENGTH_SIZE: i64 = 4;
+
+// Where something `offset` bytes into the captured packet is in the record data.
+pub(crate) fn record_data_offset(offset: u32) -> i64 {
+    ORIGINAL_LENGTH_SIZE + i64::from(offset)
+}
+
+// Where the UDP payload, and with it the quote marker, starts in the record data of a capture
+// framed as `link_type`.
+pub(crate) fn link_payload_offset(link_type: LinkType) -> i64 {
+    ORIGINAL_LENGTH_SIZE + link_type.payload_offset() as i64
+}
+
+// The fewest bytes a record has to capture to hold a payload of `payload_size` bytes at
+// `payload_offset` in its data.
+pub(crate) fn captured_size(payload_offset: i64, payload_size: i64) -> i64 {
+    payload_offset - ORIGINAL_LENGTH_SIZE + payload_size
+}
+
+impl<R> Input<R> {
+    // Where the payload starts in the record data.
+    pub(crate) fn payload_offset(&self) -> i64 {
+        self.payload_offset
+            .unwrap_or_else(|| link_payload_offset(self.link_type))
+    }
+}
+
+pub(crate) fn decode_u32(buf: [u8; 4], end: Endianness) -> u32 {
+    match end {
+        LittleEndian => u32::from_le_bytes(buf),
+        BigEndian => u32::from_be_bytes(buf),
+    }
+}
+
+fn unexpected_eof() -> io::Error {
+    io::Error::new(ErrorKind::UnexpectedEof, "failed to fill whole buffer")
+}
+
+// Reads exactly `buf.len()` bytes like `Read::read_exact`, but also rides out the transient errors
+// that pipes and network filesystems produce, retrying them up to `--read-retries` times with a
+// growing backoff. Running out of input is still reported as `UnexpectedEof`. `offset` is where in
+// the capture the read starts, for the error message.
+pub(crate) fn read_exact<R: Read>(
+    reader: &mut R,
+    buf: &mut [u8],
+    offset: u64,
+) -> Result<(), io::Error> {
+    if read_full(reader, buf, offset)? < buf.len() {
+        return Err(unexpected_eof());
+    }
+    Ok(())
+}
+
+// Like `read_exact`, but stops early at the end of the input, returning how much it read.
+pub(crate) fn read_full<R: Read>(
+    reader: &mut R,
+    buf: &mut [u8],
+    offset: u64,
+) -> Result<usize, io::Error> {
+    let mut filled = 0;
+    let mut retries = 0;
+    while filled < buf.len() {
+        let started = TIME_READS
+            .load(atomic::Ordering::Relaxed)
+            .then(time::Instant::now);
+        let read = reader.read(&mut buf[filled..]);
+        if let Some(started) = started {
+            let nanos = started.elapsed().as_nanos() as u64;
+            READ_NANOS.fetch_add(nanos, atomic::Ordering::Relaxed);
+        }
+        match read {
+            Ok(0) => break,
+            Ok(read) => {
+                filled += read;
+                retries = 0;
+            }
+            Err(e) if e.kind() == ErrorKind::Interrupted => {}
+            Err(e)
+                if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut)
+                    && retries < READ_RETRIES.load(atomic::Ordering::SeqCst) =>
+            {
+                retries += 1;
+                thread::sleep(RETRY_BACKOFF * retries);
+            }
+            Err(e) => {
+                let offset = offset + filled as u64;
+                let message = format!("{} (reading at offset {:#x})", e, offset);
+                return Err(io::Error::new(e.kind(), message));
+            }
+        }
+    }
+    Ok(filled)
+}
+
+// Where we are in a capture and how to read its records. Everything that reads from or seeks in
+// the capture moves `offset` along by the bytes it went past, so that diagnostics can always tell
+// where in the file they are.
+pub(crate) struct ParserState {
+    pub(crate) offset: u64,
+    pub(crate) end: Endianness,
+    pub(crate) precision: Precision,
+    pub(crate) this_zone: i64,
+}
+
+impl ParserState {
+    // Like `read_full`, starting at the current offset and moving it past what was read.
+    pub(crate) fn read_full<R: Read>(
+        &mut self,
+        reader: &mut R,
+        buf: &mut [u8],
+    ) -> Result<usize, io::Error> {
+        let read = read_full(reader, buf, self.offset)?;
+        self.offset += read as u64;
+        Ok(read)
+    }
+
+    // Reads past the next `bytes` bytes rather than seeking, so that any reader will do.
+    fn skip<R: Read>(&mut self, reader: &mut R, bytes: u64) -> Result<(), io::Error> {
+        self.offset += io::copy(&mut reader.by_ref().take(bytes), &mut io::sink())?;
+        Ok(())
+    }
+
+    // Decodes the field at `i` of a record header in `buf`.
+    pub(crate) fn decode_u32(&self, buf: &[u8], i: usize) -> u32 {
+        decode_u32([buf[i], buf[i + 1], buf[i + 2], buf[i + 3]], self.end)
+    }
+}
+
+// The fields of the global header that tell how to read the records after it.
+pub(crate) struct Header {
+    pub(crate) end: Endianness,
+    pub(crate) precision: Precision,
+    pub(crate) this_zone: i64,
+    pub(crate) snaplen: u32,
+    pub(crate) link_type: LinkType,
+}
+
+// Reads the whole global header in one go, taking the byte order and precision from `--endian`
+// and `--precision` rather than the magic number when they're given.
+pub(crate) fn read_pcap_header<R: Read>(
+    reader: &mut R,
+    options: &Options,
+) -> Result<PcapHeader, Box<dyn Error>> {
+    let mut header = [0; HEADER_SIZE as usize];
+    let read = read_full(reader, &mut header, 0)?;
+    if read < 4 {
+        return Err(unexpected_eof().into());
+    }
+    let header = &header[..read];
+    let parsed = match (options.endian, options.precision) {
+        (None, None) => PcapHeader::parse(header),
+        (endian, precision) => {
+            // What isn't forced comes from the magic number if it is one, or is the common choice.
+            let (detected_end, detected_precision) =
+                PcapHeader::magic_format(header).unwrap_or((LittleEndian, Precision::Microsecond));
+            let end = match endian {
+                Some(ByteOrder::Le) => LittleEndian,
+                Some(ByteOrder::Be) => BigEndian,
+                None => detected_end,
+            };
+            let precision = match precision {
+                Some(TimestampUnit::Us) => Precision::Microsecond,
+                Some(TimestampUnit::Ns) => Precision::Nanosecond,
+                None => detected_precision,
+            };
+            PcapHeader::parse_as(header, end, precision)
+        }
+    };
+    match parsed {
+        Ok(header) => Ok(header),
+        Err(e) if e.kind() == ErrorKind::UnexpectedEof => Err(unexpected_eof().into()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+// Reads the global header and picks the fields we need out of it.
+pub(crate) fn parse_header<R: Read>(
+    reader: &mut R,
+    options: &Options,
+) -> Result<Header, Box<dyn Error>> {
+    let header = read_pcap_header(reader, options)?;
+    Ok(Header {
+        end: header.endianness,
+        precision: header.precision,
+        this_zone: i64::from(header.this_zone),
+        snaplen: header.snaplen,
+        link_type: LinkType::from_header(header.link_type)?,
+    })
+}
+
+// Puts an exchange time, such as the quote accept time or the trade time, on the UTC date that
+// brings it closest to `time_stamp`.
+fn resolve_exchange_time(
+    time_stamp: i64,
+    exchange_time: ExchangeTime,
+    options: &Options,
+) -> Option<NaiveDateTime> {
+    let ExchangeTime {
+        mut seconds,
+        mut nanoseconds,
+    } = exchange_time;
+    // The fraction is a single digit, so rounding takes 5 and up to the next second. That can carry
+    // into the next day, which the date logic below deals with like with any other time.
+    if options.round_accept {
+        if nanoseconds >= 5 * EXCHANGE_TIME_STEP {
+            seconds += 1;
+        }
+        nanoseconds = 0;
+    }
+    // We converted the timestamp to UTC, while the market feed data is in KST (or whatever
+    // `--accept-tz` says). We'll also convert it to UTC and calculate the date accounting for the
+    // subtle difference in time that leads to a few edge cases when for instance the quote accept
+    // time is 2011-02-16 8:59:59 and the timestamp is 2011-02-16 0:00:00 leading to the date
+    // warping to 2011-02-15 23:59:59.
+    let remainder = time_stamp % SECONDS_IN_A_DAY;
+    let difference = (seconds - options.accept_tz).rem_euclid(SECONDS_IN_A_DAY) - remainder;
+    // A difference of exactly MAX_DIFF either way is still a genuine gap within the same day, e.g.
+    // an accept time of 09:00:00 KST stamped at 00:00:03 UTC, so only larger ones mean we wrapped.
+    naive_from_timestamp(
+        if difference.abs() > MAX_DIFF {
+            if difference < 0 {
+                time_stamp + difference + SECONDS_IN_A_DAY
+            } else {
+                time_stamp + difference - SECONDS_IN_A_DAY
+            }
+        } else {
+            time_stamp + difference
+        },
+        nanoseconds,
+    )
+}
+
+pub(crate) enum Parser {
+    Valid(Record),
+    Invalid {
+        offset: u64,
+        size: u32,
+        header: [u8; 5],
+    },
+    Malformed(u64, Malformation),
+    Eof,
+}
+
+// Why a record with a quote or trade marker couldn't be parsed. It's plain data so that skipping
+// the record allocates nothing, and only becomes an error once it's going to be reported.
+#[derive(Clone)]
+pub(crate) enum Malformation {
+    RecordLength { length: i64, expected: i64 },
+    // `start` is where the payload begins in the record data.
+    Payload { start: usize, error: PayloadError },
+    Timestamp,
+}
+
+impl Malformation {
+    // Whether the payload didn't end with the end-of-message byte, which means its fields have most
+    // likely been read from the wrong places.
+    pub(crate) fn is_framing(&self) -> bool {
+        matches!(
+            self,
+            Malformation::Payload {
+                error: PayloadError::EndOfMessage { .. },
+                ..
+            }
+        )
+    }
+
+    // Builds the error for the record at `offset`, whose data `data` still holds.
+    pub(crate) fn into_error(self, offset: u64, data: &[u8]) -> Box<dyn Error> {
+        match self {
+            Malformation::RecordLength { length, expected } => ParseError::RecordLength {
+                offset,
+                length,
+                expected,
+            }
+            .into(),
+            Malformation::Payload { start, error } => {
+                let payload_offset = offset + RECORD_HEADER_SIZE + start as u64;
+                payload_error(payload_offset, &data[start..], error)
+            }
+            Malformation::Timestamp => INVALID_TIMESTAMP.into(),
+        }
+    }
+}
+
+// The buffers `parse_packet` works in, owned by the loop calling it so that they're reused from one
+// record to the next. The record buffer holds everything captured after the record header, of which
+// the first `captured` bytes belong to the current record, and only grows past its initial size
+// for the rare record that doesn't fit.
+pub(crate) struct PacketScratch {
+    pub(crate) record: Vec<u8>,
+    pub(crate) captured: usize,
+    // The length of the packet on the wire, from the start of the record data, or 0 when even
+    // that wasn't captured.
+    pub(crate) original: u32,
+    // The messages left in the current record, when it's a datagram of several.
+    datagram: Option<Datagram>,
+    // Bytes after the last message of datagrams that weren't a whole message of their own, for the
+    // caller to take.
+    pub(crate) trailing: u64,
+    pub(crate) text: String,
+}
+
+impl Default for PacketScratch {
+    fn default() -> Self {
+        PacketScratch {
+            record: vec![0; SCRATCH_SIZE],
+            captured: 0,
+            original: 0,
+            datagram: None,
+            trailing: 0,
+            text: String::new(),
+        }
+    }
+}
+
+impl PacketScratch {
+    // Whether the next packet comes from the same record as the last one, the rest of a datagram
+    // of several messages.
+    pub(crate) fn in_datagram(&self) -> bool {
+        self.datagram.is_some()
+    }
+}
+
+// The record a quote message was found in, which the messages of a datagram share.
+#[derive(Clone, Copy)]
+struct QuoteRecord {
+    offset: u64,
+    date: NaiveDateTime,
+    seconds: i64,
+    packet_size: i64,
+}
+
+// A record holding several quote messages back to back, with where the next one starts in the
+// record data and where its payload ends.
+#[derive(Clone, Copy)]
+struct Datagram {
+    record: QuoteRecord,
+    next: usize,
+    end: usize,
+}
+
+// Parses a quote payload, which is an extended one if it's as long as those are.
+fn parse_quote_body(
+    quote_packet: &mut QuotePacket,
+    payload: &[u8],
+    start: usize,
+    seconds: i64,
+    options: &Options,
+) -> Result<(), Malformation> {
+    let malformation = |error| Malformation::Payload { start, error };
+    let accept_time = if let Ok(payload) = payload.try_into() {
+        let quote = payload::parse_quote_ext(payload, options.strict).map_err(malformation)?;
+        quote_packet.issue_code = quote.issue_code;
+        quote_packet.issue_sequence = quote.issue_sequence;
+        quote_packet.market_status = quote.market_status;
+        quote_packet.set_book(&quote);
+        quote.accept_time
+    } else if let Ok(payload) = payload.try_into() {
+        let quote = payload::parse_quote(payload, options.strict).map_err(malformation)?;
+        quote_packet.issue_code = quote.issue_code;
+        quote_packet.issue_sequence = quote.issue_sequence;
+        quote_packet.market_status = quote.market_status;
+        quote_packet.set_book(&quote);
+        quote.accept_time
+    } else {
+        return Err(Malformation::RecordLength {
+            length: payload.len() as i64,
+            expected: QUOTE_PAYLOAD_SIZE as i64,
+        });
+    };
+    quote_packet.quote_accept_time =
+        resolve_exchange_time(seconds, accept_time, options).ok_or(Malformation::Timestamp)?;
+    Ok(())
+}
+
+fn parse_trade_body(
+    trade_packet: &mut TradePacket,
+    payload: &[u8; TRADE_PAYLOAD_SIZE],
+    start: usize,
+    seconds: i64,
+    options: &Options,
+) -> Result<(), Malformation> {
+    let trade = payload::parse_trade(payload, options.strict)
+        .map_err(|error| Malformation::Payload { start, error })?;
+    trade_packet.issue_code = trade.issue_code;
+    trade_packet.issue_sequence = trade.issue_sequence;
+    trade_packet.board_id = trade.board_id;
+    trade_packet.price = trade.price;
+    trade_packet.quantity = trade.quantity;
+    trade_packet.trade_time =
+        resolve_exchange_time(seconds, trade.trade_time, options).ok_or(Malformation::Timestamp)?;
+    Ok(())
+}
+
+// Returns `len` bytes of the record data from `start`, which are only missing when the capture
+// ends in the middle of the record.
+fn record_bytes(data: &[u8], start: usize, len: usize) -> Result<&[u8], io::Error> {
+    data.get(start..start + len).ok_or_else(unexpected_eof)
+}
+
+// Returns where in the record data the first quote marker with a whole payload after it starts.
+fn scan_marker(
+    data: &[u8],
+    packet_size: i64,
+    options: &Options,
+) -> Result<Option<usize>, io::Error> {
+    if packet_size > i64::from(MAX_RECORD_SIZE) {
+        return Ok(None);
+    }
+    let data = record_bytes(data, 0, packet_size as usize)?;
+    Ok(data
+        .windows(QUOTE_MESSAGE_TYPE.len())
+        .take((packet_size - options.payload_size + 1).max(0) as usize)
+        .position(|window| options.is_magic(window)))
+}
+
+fn log_record(
+    options: &Options,
+    offset: u64,
+    date: NaiveDateTime,
+    packet_size: i64,
+    decision: fmt::Arguments,
+) {
+    if log_enabled!(Level::Debug) && within_message_limit(options) {
+        debug!(
+            "record at {:#x} ({}, {} bytes captured): {}",
+            offset,
+            date,
+            packet_size - 4,
+            decision
+        );
+    }
+}
+
+fn hex_dump(offset: u64, bytes: &[u8], text: &mut String) {
+    for (i, line) in bytes.chunks(16).enumerate() {
+        text.clear();
+        let _ = write!(text, "{:08x} ", offset + 16 * i as u64);
+        for (j, byte) in line.iter().enumerate() {
+            text.push_str(if j == 8 { "  " } else { " " });
+            let _ = write!(text, "{:02x}", byte);
+        }
+        // The hex column is as wide as a full line of sixteen bytes.
+        while text.len() < 9 + 49 {
+            text.push(' ');
+        }
+        text.push_str("  |");
+        for &c in line {
+            text.push(if (0x20..=0x7E).contains(&c) {
+                c as char
+            } else {
+                '.'
+            });
+        }
+        text.push('|');
+        eprintln!("{}", text);
+    }
+}
+
+// Skips a record that isn't a quote packet, logging why and dumping the start of its captured
+// data under `--dump-invalid`.
+fn reject(
+    options: &Options,
+    offset: u64,
+    date: NaiveDateTime,
+    packet_size: i64,
+    scratch: &mut PacketScratch,
+    payload_offset: i64,
+    decision: fmt::Arguments,
+) -> Parser {
+    log_record(options, offset, date, packet_size, decision);
+    let data = &scratch.record[..scratch.captured];
+    if options
+        .dump_invalid
+        .is_some_and(|max| DUMPED.fetch_add(1, atomic::Ordering::SeqCst) < max)
+    {
+        let data_offset = offset + RECORD_HEADER_SIZE + 4;
+        let end = data.len().min(4 + DUMP_SIZE as usize);
+        eprintln!(
+            "Invalid record at offset {:#x} ({} bytes captured, {}):",
+            offset,
+            packet_size - 4,
+            decision
+        );
+        hex_dump(
+            data_offset,
+            data.get(4..end).unwrap_or(&[]),
+            &mut scratch.text,
+        );
+    }
+    // The bytes where the quote marker would be tell which message type the record carries.
+    let mut header = [0; 5];
+    if options.print_invalid_offsets {
+        let marker = data.get(payload_offset as usize..).unwrap_or(&[]);
+        let available = marker.len().min(header.len());
+        header[..available].copy_from_slice(&marker[..available]);
+    }
+    Invalid {
+        offset,
+        size: packet_size as u32,
+        header,
+    }
+}
+
+// Skips a record that has a marker but couldn't be parsed, logging why.
+fn malformed(
+    options: &Options,
+    offset: u64,
+    date: NaiveDateTime,
+    packet_size: i64,
+    data: &[u8],
+    malformation: Malformation,
+) -> Parser {
+    if log_enabled!(Level::Debug) {
+        let e = malformation.clone().into_error(offset, data);
+        log_record(options, offset, date, packet_size, format_args!("{}", e));
+    }
+    Malformed(offset, malformation)
+}
+
+// Returns the IP protocol of the packet in the record data, if it's long enough to be IPv4 at all.
+// The market feed is all UDP, so anything else can be skipped no matter what its payload looks
+// like.
+fn ip_protocol(
+    data: &[u8],
+    packet_size: i64,
+    link_type: LinkType,
+) -> Result<Option<u8>, io::Error> {
+    let len = packet_size.min(link_payload_offset(link_type)) as usize;
+    let packet = record_bytes(data, 0, len)?;
+    Ok(link_type.ip_protocol(&packet[ORIGINAL_LENGTH_SIZE as usize..]))
+}
+
+// Parses the trade record at `offset`, whose data is in `scratch`, returning it as invalid if it
+// doesn't carry a trade marker.
+fn parse_trade(
+    seconds: i64,
+    date: NaiveDateTime,
+    offset: u64,
+    packet_size: i64,
+    scratch: &mut PacketScratch,
+    payload_offset: i64,
+    options: &Options,
+) -> Result<Parser, Box<dyn Error>> {
+    let start = payload_offset as usize;
+    let data = &scratch.record[..scratch.captured];
+    let message_type: [u8; 5] = record_bytes(data, start, 5)?.try_into().unwrap();
+    if !options.is_trade_magic(&message_type) {
+        let decision = format_args!("trade marker mismatch, found {:02x?}", message_type);
+        return Ok(reject(
+            options,
+            offset,
+            date,
+            packet_size,
+            scratch,
+            payload_offset,
+            decision,
+        ));
+    }
+    let payload = record_bytes(data, start, TRADE_PAYLOAD_SIZE)?;
+    let mut trade_packet = TradePacket {
+        offset,
+        number: 0,
+        message_type,
+        time_stamp: date,
+        trade_time: date,
+        issue_code: Default::default(),
+        issue_sequence: Default::default(),
+        board_id: Default::default(),
+        price: 0,
+        quantity: 0,
+        aggressor: None,
+    };
+    let payload = payload.try_into().unwrap();
+    match parse_trade_body(&mut trade_packet, payload, start, seconds, options) {
+        Ok(()) => {
+            log_record(
+                options,
+                offset,
+                date,
+                packet_size,
+                format_args!("trade parsed"),
+            );
+            Ok(Valid(Record::Trade(trade_packet)))
+        }
+        Err(malformation) => Ok(malformed(
+            options,
+            offset,
+            date,
+            packet_size,
+            data,
+            malformation,
+        )),
+    }
+}
+
+// Reads the record at the current offset of `input` into its scratch buffers and parses it,
+// leaving the reader at the start of the next record.
+pub(crate) fn parse_packet<R: Read>(
+    input: &mut Input<R>,
+    options: &Options,
+) -> Result<Parser, Box<dyn Error>> {
+    let link_type_offset = link_payload_offset(input.link_type);
+    let Input {
+        file,
+        state,
+        link_type,
+        payload_offset: found_offset,
+        scratch,
+        ..
+    } = input;
+    let link_type = *link_type;
+    if scratch.datagram.is_some() {
+        return Ok(datagram_message(options, scratch)?);
+    }
+    let offset = state.offset;
+    let mut record_header = [0; RECORD_HEADER_SIZE as usize];
+    match state.read_full(file, &mut record_header)? {
+        // Not even the timestamp is there, so the capture just ends here.
+        read if read < 4 => return Ok(Eof),
+        read if read < record_header.len() => return Err(unexpected_eof().into()),
+        _ => {}
+    }
+    let field = |i: usize| state.decode_u32(&record_header, i);
+    // Converting the packet timestamp to UTC. The seconds can't leave the range chrono handles
+    // even with the most extreme time zone offset, but a corrupt fraction can be a second or more.
+    let seconds = i64::from(field(0)) + state.this_zone;
+    let nanoseconds = u64::from(field(4)) * u64::from(state.precision.nanoseconds());
+    let date = Some(nanoseconds)
+        .filter(|&nanoseconds| nanoseconds < 1_000_000_000)
+        .and_then(|nanoseconds| naive_from_timestamp(seconds, nanoseconds as u32))
+        .ok_or(ParseError::Timestamp {
+            offset,
+            seconds,
+            nanoseconds,
+        })?;
+    let packet_size = i64::from(field(8)) + 4;
+    // Nothing past MAX_RECORD_SIZE is ever looked at, so the rest of a longer record is skipped.
+    let wanted = packet_size.min(i64::from(MAX_RECORD_SIZE)) as usize;
+    if scratch.record.len() < wanted {
+        scratch.record.resize(wanted, 0);
+    }
+    let captured = state.read_full(file, &mut scratch.record[..wanted])?;
+    scratch.captured = captured;
+    if captured == wanted && wanted < packet_size as usize {
+        state.skip(file, packet_size as u64 - wanted as u64)?;
+    }
+    let data = &scratch.record[..captured];
+    scratch.original = match data.get(..4) {
+        Some(_) => state.decode_u32(data, 0),
+        None => 0,
+    };
+    // Only a record that was captured whole can be trusted to show where its payload starts.
+    if options.auto_offset && captured as i64 == packet_size {
+        if let Some(position) = scan_marker(data, packet_size, options)? {
+            let position = position as i64;
+            match *found_offset {
+                None => {
+                    eprintln!(
+                        "Found the quote payload {} bytes into the packet at offset {:#x}, \
+                         reading the capture with --payload-offset {}",
+                        position - ORIGINAL_LENGTH_SIZE,
+                        offset,
+                        position - ORIGINAL_LENGTH_SIZE
+                    );
+                    *found_offset = Some(position);
+                }
+                Some(found) if found != position => {
+                    return Err(format!(
+                        "The quote payload of the record at offset {:#x} is {} bytes into its \
+                         packet instead of the {} found by --auto-offset",
+                        offset,
+                        position - ORIGINAL_LENGTH_SIZE,
+                        found - ORIGINAL_LENGTH_SIZE
+                    )
+                    .into())
+                }
+                Some(_) => {}
+            }
+        }
+    }
+    let payload_offset = found_offset.unwrap_or(link_type_offset);
+    // Whatever the packet was, the capture only kept the start of it.
+    let original = scratch.original;
+    if i64::from(original) > packet_size - 4 {
+        let decision = format_args!(
+            "truncated, {} of {} bytes captured",
+            packet_size - 4,
+            original
+        );
+        return Ok(reject(
+            options,
+            offset,
+            date,
+            packet_size,
+            scratch,
+            payload_offset,
+            decision,
+        ));
+    }
+    // Where the payload was put by hand, the headers in front of it are anyone's guess.
+    let framed = found_offset.is_none() || payload_offset == link_type_offset;
+    if let Some(protocol) = ip_protocol(data, packet_size, link_type)?.filter(|_| framed) {
+        if protocol != UDP_PROTOCOL {
+            let decision = format_args!("not UDP, IP protocol {}", protocol);
+            return Ok(reject(
+                options,
+                offset,
+                date,
+                packet_size,
+                scratch,
+                payload_offset,
+                decision,
+            ));
+        }
+    }
+    if options.only != Only::Quotes && packet_size == TRADE_PACKET_SIZE + payload_offset {
+        return parse_trade(
+            seconds,
+            date,
+            offset,
+            packet_size,
+            scratch,
+            payload_offset,
+            options,
+        );
+    }
+    // Where the payload starts in the record data, at the quote marker.
+    let mut start = payload_offset as usize;
+    // Feeds sending extended quotes, with ten levels on each side, have records of their own size.
+    let extended = packet_size == QUOTE_EXT_PACKET_SIZE + payload_offset;
+    // The feed sometimes coalesces several quote messages into one datagram, one after the other.
+    let length = packet_size - payload_offset;
+    if length > options.payload_size
+        && !extended
+        && data
+            .get(start..start + 5)
+            .is_some_and(|marker| options.is_magic(marker))
+    {
+        let record = QuoteRecord {
+            offset,
+            date,
+            seconds,
+            packet_size,
+        };
+        scratch.datagram = Some(Datagram {
+            record,
+            next: start,
+            end: packet_size as usize,
+        });
+        return Ok(datagram_message(options, scratch)?);
+    }
+    if packet_size != options.payload_size + payload_offset && !extended {
+        if options.scan_marker {
+            match scan_marker(data, packet_size, options)? {
+                Some(position) => start = position,
+                None => {
+                    let decision = format_args!("no marker");
+                    return Ok(reject(
+                        options,
+                        offset,
+                        date,
+                        packet_size,
+                        scratch,
+                        payload_offset,
+                        decision,
+                    ));
+                }
+            }
+        } else {
+            // A quote marker in a record of the wrong size means the framing can't be trusted.
+            if options.strict
+                && packet_size >= payload_offset + 5
+                && options.is_magic(record_bytes(data, start, 5)?)
+            {
+                let malformation = Malformation::RecordLength {
+                    length: packet_size - 4,
+                    expected: payload_offset + options.payload_size - 4,
+                };
+                return Ok(Malformed(offset, malformation));
+            }
+            let decision = format_args!("wrong size");
+            return Ok(reject(
+                options,
+                offset,
+                date,
+                packet_size,
+                scratch,
+                payload_offset,
+                decision,
+            ));
+        }
+    } else {
+        let marker: [u8; 5] = record_bytes(data, start, 5)?.try_into().unwrap();
+        if !options.is_magic(&marker) {
+            let scanned = if options.scan_marker {
+                scan_marker(data, packet_size, options)?
+            } else {
+                None
+            };
+            match scanned {
+                Some(position) => start = position,
+                None => {
+                    let decision = format_args!("marker mismatch, found {:02x?}", marker);
+                    return Ok(reject(
+                        options,
+                        offset,
+                        date,
+                        packet_size,
+                        scratch,
+                        payload_offset,
+                        decision,
+                    ));
+                }
+            }
+        }
+    }
+    // A marker found elsewhere in the record is followed by a quote of the usual size.
+    let (payload_size, asks_start, accept_start) = if extended && start == payload_offset as usize {
+        (
+            QUOTE_EXT_PAYLOAD_SIZE,
+            QUOTE_EXT_ASKS_START,
+            QUOTE_EXT_ACCEPT_START,
+        )
+    } else {
+        (QUOTE_PAYLOAD_SIZE, ASKS_START, QUOTE_ACCEPT_START)
+    };
+    let record = QuoteRecord {
+        offset,
+        date,
+        seconds,
+        packet_size,
+    };
+    let payload = record_bytes(data, start, payload_size)?;
+    Ok(parse_quote(
+        options,
+        record,
+        data,
+        start,
+        payload,
+        asks_start,
+        accept_start,
+    ))
+}
+
+// Parses the next message of the datagram in `scratch`, and forgets about the datagram once no
+// whole message with a marker follows it, counting what's left as trailing bytes.
+fn datagram_message(options: &Options, scratch: &mut PacketScratch) -> Result<Parser, io::Error> {
+    let Datagram { record, next, end } = scratch.datagram.take().unwrap();
+    let stride = options.payload_size as usize;
+    let data = &scratch.record[..scratch.captured.min(end)];
+    let following = next + stride;
+    let more = pcap::datagram_message(data, following, stride, |marker| options.is_magic(marker))
+        .is_some();
+    if more {
+        scratch.datagram = Some(Datagram {
+            record,
+            next: following,
+            end,
+        });
+    } else {
+        scratch.trailing += (end - following) as u64;
+    }
+    // Messages padded out to a longer `--payload-size` only have a quote at their start.
+    let message = record_bytes(data, next, stride)?;
+    let payload = message.get(..QUOTE_PAYLOAD_SIZE).unwrap_or(message);
+    Ok(parse_quote(
+        options,
+        record,
+        data,
+        next,
+        payload,
+        ASKS_START,
+        QUOTE_ACCEPT_START,
+    ))
+}
+
+// Parses the quote `payload` starting at `start` in the record data, whose asks and accept time are
+// at `asks_start` and `accept_start` in it.
+fn parse_quote(
+    options: &Options,
+    record: QuoteRecord,
+    data: &[u8],
+    start: usize,
+    payload: &[u8],
+    asks_start: usize,
+    accept_start: usize,
+) -> Parser {
+    let QuoteRecord {
+        offset,
+        date,
+        seconds,
+        packet_size,
+    } = record;
+    let mut quote_packet: QuotePacket = QuotePacket {
+        offset,
+        number: 0,
+        message_type: payload[..MESSAGE_TYPE_SIZE].try_into().unwrap(),
+        time_stamp: date,
+        quote_accept_time: date,
+        issue_code: Default::default(),
+        issue_sequence: Default::default(),
+        market_status: Default::default(),
+        bids: Default::default(),
+        asks: Default::default(),
+        levels: 0,
+        ema: None,
+    };
+    match parse_quote_body(&mut quote_packet, payload, start, seconds, options) {
+        Ok(()) => {
+            log_record(
+                options,
+                offset,
+                date,
+                packet_size,
+                format_args!("quote parsed"),
+            );
+            if log_enabled!(Level::Trace) {
+                let payload_offset = offset + RECORD_HEADER_SIZE + start as u64;
+                let field = |start: usize| payload_offset + start as u64;
+                let issue_code = field(ISSUE_CODE_START);
+                let bids = field(BIDS_START);
+                let asks = field(asks_start);
+                let accept_time = field(accept_start);
+                trace!(
+                    "issue code at {:#x}, bids at {:#x}, asks at {:#x}, accept time at {:#x}",
+                    issue_code,
+                    bids,
+                    asks,
+                    accept_time
+                );
+            }
+            Valid(Record::Quote(quote_packet))
+        }
+        // A bad field only spoils this packet, and the whole record has been read already, so the
+        // next one is still where we expect it.
+        Err(malformation) => malformed(options, offset, date, packet_size, data, malformation),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser as _;
+    use std::io::Cursor;
+
+    fn input(capture: Vec<u8>, options: &Options) -> Input<Cursor<Vec<u8>>> {
+        let mut file = Cursor::new(capture);
+        let header = parse_header(&mut file, options).unwrap();
+        Input {
+            file,
+            state: ParserState {
+                offset: HEADER_SIZE,
+                end: header.end,
+                precision: header.precision,
+                this_zone: header.this_zone,
+            },
+            snaplen: header.snaplen,
+            link_type: header.link_type,
+            payload_offset: None,
+            packet_number: 0,
+            last_seconds: None,
+            time_stamps: crate::MonotoneCheck::default(),
+            scratch: PacketScratch::default(),
+        }
+    }
+
+    fn issue_codes(capture: Vec<u8>) -> Vec<String> {
+        let options = Options::parse_from(["parse-quote", "capture.pcap"]);
+        let mut input = input(capture, &options);
+        let mut codes = Vec::new();
+        loop {
+            match parse_packet(&mut input, &options).unwrap() {
+                Valid(Record::Quote(quote)) => {
+                    codes.push(String::from_utf8_lossy(&quote.issue_code).into_owned())
+                }
+                Eof => return codes,
+                _ => {}
+            }
+        }
+    }
+
+    fn capture() -> Vec<u8> {
+        include_bytes!("../tests/fixtures/quotes.pcap").to_vec()
+    }
+
+    #[test]
+    fn parses_quotes_from_memory() {
+        assert_eq!(
+            issue_codes(capture()),
+            [
+                "KR4201011009",
+                "KR0000000001",
+                "KR4201011009",
+                "KR7005930003",
+                "KR4201011009",
+            ]
+        );
+    }
+
+    #[test]
+    fn reads_past_what_it_skips() {
+        // A record past MAX_RECORD_SIZE in front of the quotes, whose excess can't be seeked over.
+        let mut capture = capture();
+        let size = MAX_RECORD_SIZE as usize + 100;
+        let mut record = vec![0; RECORD_HEADER_SIZE as usize];
+        record[8..12].copy_from_slice(&(size as u32 - 4).to_le_bytes());
+        record.resize(RECORD_HEADER_SIZE as usize + size, 0);
+        let at = HEADER_SIZE as usize;
+        capture.splice(at..at, record);
+        assert_eq!(issue_codes(capture).len(), 5);
+    }
+
+    #[test]
+    fn rounds_exchange_times_into_the_next_second() {
+        let truncate = Options::parse_from(["parse-quote", "capture.pcap"]);
+        let round = Options::parse_from(["parse-quote", "--round-accept", "capture.pcap"]);
+        // Stamped at 2011-02-16 00:00:00 UTC, accepted at 08:59:59 KST and a fraction.
+        let time_stamp = 1_297_814_400;
+        let accept_time = |digit: u32| ExchangeTime {
+            seconds: 8 * 3_600 + 59 * 60 + 59,
+            nanoseconds: digit * EXCHANGE_TIME_STEP,
+        };
+        let resolve = |digit, options| {
+            let time = resolve_exchange_time(time_stamp, accept_time(digit), options).unwrap();
+            time.format("%Y-%m-%d %H:%M:%S%.f").to_string()
+        };
+        assert_eq!(resolve(9, &truncate), "2011-02-15 23:59:59.009");
+        assert_eq!(resolve(9, &round), "2011-02-16 00:00:00");
+        assert_eq!(resolve(5, &round), "2011-02-16 00:00:00");
+        assert_eq!(resolve(4, &round), "2011-02-15 23:59:59");
+    }
+
+    #[test]
+    fn keeps_track_of_the_offset() {
+        let options = Options::parse_from(["parse-quote", "capture.pcap"]);
+        let capture = capture();
+        let length = capture.len() as u64;
+        let mut input = input(capture, &options);
+        let mut offsets = Vec::new();
+        loop {
+            match parse_packet(&mut input, &options).unwrap() {
+                Valid(record) => offsets.push(record.offset()),
+                Eof => break,
+                _ => {}
+            }
+        }
+        assert_eq!(offsets[0], HEADER_SIZE);
+        assert_eq!(input.state.offset, length);
+    }
+}
@@ -0,0 +1,448 @@
+//! The layout of KRX quote (B6034) and trade (A3034) payloads, and parsing them out of bytes.
+//!
+//! Quotes come with five levels on each side of the book, or ten in the extended variant some feeds
+//! send instead, which is laid out the same way apart from the longer sides. [`QuoteData`] gives
+//! the book of either.
+//!
+//! Nothing in here does any I/O or allocates: finding the payload in a capture is left to the
+//! caller, and exchange times are returned as a time of day for the caller to put on a date.
+
+use core::fmt;
+use core::str;
+
+/// The message type a quote payload starts with, unless the feed uses a variant.
+pub const QUOTE_MESSAGE_TYPE: &[u8; MESSAGE_TYPE_SIZE] = b"B6034";
+/// The message type a trade payload starts with, unless the feed uses a variant.
+pub const TRADE_MESSAGE_TYPE: &[u8; MESSAGE_TYPE_SIZE] = b"A3034";
+/// The byte both payloads end with.
+pub const END_OF_MESSAGE: u8 = 0xFF;
+
+pub const MESSAGE_TYPE_SIZE: usize = 5;
+pub const ISSUE_CODE_SIZE: usize = 12;
+pub const ISSUE_SEQUENCE_SIZE: usize = 3;
+pub const MARKET_STATUS_SIZE: usize = 2;
+pub const BOARD_ID_SIZE: usize = 2;
+pub const PRICE_SIZE: usize = 5;
+pub const QUANTITY_SIZE: usize = 7;
+pub const LEVEL_SIZE: usize = PRICE_SIZE + QUANTITY_SIZE;
+pub const EXCHANGE_TIME_SIZE: usize = 8;
+/// The nanoseconds one step of the single fraction digit of an exchange time stands for, so that
+/// the fraction never reaches a whole second.
+pub const EXCHANGE_TIME_STEP: u32 = 1_000_000;
+/// The number of levels on each side of the book in a quote payload.
+pub const QUOTE_LEVELS: usize = 5;
+/// The number of levels on each side of the book in an extended quote payload.
+pub const QUOTE_EXT_LEVELS: usize = 10;
+
+// Both payloads start with the message type, the issue code and its sequence number.
+pub const ISSUE_CODE_START: usize = MESSAGE_TYPE_SIZE;
+pub const ISSUE_SEQUENCE_START: usize = ISSUE_CODE_START + ISSUE_CODE_SIZE;
+
+pub const MARKET_STATUS_START: usize = ISSUE_SEQUENCE_START + ISSUE_SEQUENCE_SIZE;
+pub const BIDS_START: usize = MARKET_STATUS_START + MARKET_STATUS_SIZE + 7;
+pub const ASKS_START: usize = BIDS_START + QUOTE_LEVELS * LEVEL_SIZE + 7;
+pub const QUOTE_ACCEPT_START: usize = ASKS_START + QUOTE_LEVELS * LEVEL_SIZE + 50;
+/// The size of a quote payload, from the message type to the end-of-message byte.
+pub const QUOTE_PAYLOAD_SIZE: usize = QUOTE_ACCEPT_START + EXCHANGE_TIME_SIZE + 1;
+
+// The extended quote has the bids where the quote has them, and everything after them moved along.
+pub const QUOTE_EXT_ASKS_START: usize = BIDS_START + QUOTE_EXT_LEVELS * LEVEL_SIZE + 7;
+pub const QUOTE_EXT_ACCEPT_START: usize = QUOTE_EXT_ASKS_START + QUOTE_EXT_LEVELS * LEVEL_SIZE + 50;
+/// The size of an extended quote payload, from the message type to the end-of-message byte.
+pub const QUOTE_EXT_PAYLOAD_SIZE: usize = QUOTE_EXT_ACCEPT_START + EXCHANGE_TIME_SIZE + 1;
+
+pub const BOARD_ID_START: usize = ISSUE_SEQUENCE_START + ISSUE_SEQUENCE_SIZE;
+pub const TRADE_PRICE_START: usize = BOARD_ID_START + BOARD_ID_SIZE + 6;
+pub const TRADE_TIME_START: usize = TRADE_PRICE_START + LEVEL_SIZE + 34;
+/// The size of a trade payload, from the message type to the end-of-message byte.
+pub const TRADE_PAYLOAD_SIZE: usize = TRADE_TIME_START + EXCHANGE_TIME_SIZE + 1;
+
+/// An HHMMSSuu exchange time as the time of day in the exchange's time zone.
+///
+/// Only the last digit is taken as the fraction, in milliseconds.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct ExchangeTime {
+    pub seconds: i64,
+    pub nanoseconds: u32,
+}
+
+/// The fields of a quote payload. Book levels are `(quantity, price)`, best level first.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Quote {
+    pub message_type: [u8; MESSAGE_TYPE_SIZE],
+    pub issue_code: [u8; ISSUE_CODE_SIZE],
+    pub issue_sequence: [u8; ISSUE_SEQUENCE_SIZE],
+    pub market_status: [u8; MARKET_STATUS_SIZE],
+    pub bids: [(u32, u32); QUOTE_LEVELS],
+    pub asks: [(u32, u32); QUOTE_LEVELS],
+    pub accept_time: ExchangeTime,
+}
+
+/// The fields of an extended quote payload, which has ten book levels on each side.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct QuoteExt {
+    pub message_type: [u8; MESSAGE_TYPE_SIZE],
+    pub issue_code: [u8; ISSUE_CODE_SIZE],
+    pub issue_sequence: [u8; ISSUE_SEQUENCE_SIZE],
+    pub market_status: [u8; MARKET_STATUS_SIZE],
+    pub bids: [(u32, u32); QUOTE_EXT_LEVELS],
+    pub asks: [(u32, u32); QUOTE_EXT_LEVELS],
+    pub accept_time: ExchangeTime,
+}
+
+/// The book of a quote, however many levels it has.
+pub trait QuoteData {
+    /// The bid levels as `(quantity, price)`, best first.
+    fn bids(&self) -> &[(u32, u32)];
+    /// The ask levels as `(quantity, price)`, best first.
+    fn asks(&self) -> &[(u32, u32)];
+}
+
+impl QuoteData for Quote {
+    fn bids(&self) -> &[(u32, u32)] {
+        &self.bids
+    }
+
+    fn asks(&self) -> &[(u32, u32)] {
+        &self.asks
+    }
+}
+
+impl QuoteData for QuoteExt {
+    fn bids(&self) -> &[(u32, u32)] {
+        &self.bids
+    }
+
+    fn asks(&self) -> &[(u32, u32)] {
+        &self.asks
+    }
+}
+
+/// The fields of a trade payload.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Trade {
+    pub message_type: [u8; MESSAGE_TYPE_SIZE],
+    pub issue_code: [u8; ISSUE_CODE_SIZE],
+    pub issue_sequence: [u8; ISSUE_SEQUENCE_SIZE],
+    pub board_id: [u8; BOARD_ID_SIZE],
+    pub price: u32,
+    pub quantity: u32,
+    pub trade_time: ExchangeTime,
+}
+
+/// A numeric field, with book levels numbered from 1 for the best one.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Field {
+    BidPrice(usize),
+    BidQuantity(usize),
+    AskPrice(usize),
+    AskQuantity(usize),
+    TradePrice,
+    TradeQuantity,
+    QuoteAcceptTime,
+    TradeTime,
+}
+
+impl fmt::Display for Field {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Field::BidPrice(level) => write!(f, "bid {} price", level),
+            Field::BidQuantity(level) => write!(f, "bid {} quantity", level),
+            Field::AskPrice(level) => write!(f, "ask {} price", level),
+            Field::AskQuantity(level) => write!(f, "ask {} quantity", level),
+            Field::TradePrice => f.write_str("trade price"),
+            Field::TradeQuantity => f.write_str("trade quantity"),
+            Field::QuoteAcceptTime => f.write_str("quote accept time"),
+            Field::TradeTime => f.write_str("trade time"),
+        }
+    }
+}
+
+/// Why a payload couldn't be parsed. Offsets count from the start of the payload.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum PayloadError {
+    /// The hours, minutes, seconds or fraction of an exchange time aren't digits, which usually
+    /// means the payload has a different layout.
+    BadTime {
+        offset: usize,
+        field: Field,
+        bytes: [u8; EXCHANGE_TIME_SIZE],
+    },
+    /// A price or quantity isn't a number.
+    Level {
+        offset: usize,
+        len: usize,
+        field: Field,
+    },
+    /// A numeric field has something else than digits, which is only checked when strict.
+    NonDigit { offset: usize, field: Field },
+    /// The hours, minutes or seconds of an exchange time are out of range, which is only checked
+    /// when strict.
+    TimeRange {
+        offset: usize,
+        field: Field,
+        unit: &'static str,
+        value: u32,
+    },
+    /// The issue code isn't printable ASCII.
+    IssueCode {
+        offset: usize,
+        bytes: [u8; ISSUE_CODE_SIZE],
+    },
+    /// The payload doesn't end with [`END_OF_MESSAGE`], which means the fields before it can't be
+    /// trusted to be where they're expected.
+    EndOfMessage { offset: usize, byte: u8 },
+}
+
+impl fmt::Display for PayloadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PayloadError::BadTime {
+                offset,
+                field,
+                bytes,
+            } => write!(
+                f,
+                "Malformed {} at offset {} from {:02x?}",
+                field, offset, bytes
+            ),
+            PayloadError::Level { offset, field, .. } => {
+                write!(f, "Can't parse {} at offset {}", field, offset)
+            }
+            PayloadError::NonDigit { offset, field } => {
+                write!(f, "Non-digit byte in {} at offset {}", field, offset)
+            }
+            PayloadError::TimeRange {
+                offset,
+                field,
+                unit,
+                value,
+            } => write!(
+                f,
+                "{} {} {} at offset {} is out of range",
+                field, unit, value, offset
+            ),
+            PayloadError::IssueCode { offset, bytes } => write!(
+                f,
+                "Issue code at offset {} is not printable ASCII: {:02x?}",
+                offset, bytes
+            ),
+            PayloadError::EndOfMessage { offset, byte } => write!(
+                f,
+                "End-of-message byte at offset {} is {:#04x}",
+                offset, byte
+            ),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for PayloadError {}
+
+/// Parses a quote payload, checking that it ends with an end-of-message byte. When `strict`, also
+/// checks that numeric fields are digits only and that the accept time is in range.
+pub fn parse_quote(
+    payload: &[u8; QUOTE_PAYLOAD_SIZE],
+    strict: bool,
+) -> Result<Quote, PayloadError> {
+    let issue_code = parse_issue_code(payload)?;
+    let bids = parse_side(payload, BIDS_START, BID_FIELDS, strict)?;
+    let asks = parse_side(payload, ASKS_START, ASK_FIELDS, strict)?;
+    let accept_time =
+        parse_exchange_time(payload, QUOTE_ACCEPT_START, Field::QuoteAcceptTime, strict)?;
+    parse_end_of_message(payload)?;
+    Ok(Quote {
+        message_type: copy_field(payload, 0),
+        issue_code,
+        issue_sequence: copy_field(payload, ISSUE_SEQUENCE_START),
+        market_status: copy_field(payload, MARKET_STATUS_START),
+        bids,
+        asks,
+        accept_time,
+    })
+}
+
+/// Parses an extended quote payload, with the same checks as [`parse_quote`].
+pub fn parse_quote_ext(
+    payload: &[u8; QUOTE_EXT_PAYLOAD_SIZE],
+    strict: bool,
+) -> Result<QuoteExt, PayloadError> {
+    let issue_code = parse_issue_code(payload)?;
+    let bids = parse_side(payload, BIDS_START, BID_FIELDS, strict)?;
+    let asks = parse_side(payload, QUOTE_EXT_ASKS_START, ASK_FIELDS, strict)?;
+    let accept_time = parse_exchange_time(
+        payload,
+        QUOTE_EXT_ACCEPT_START,
+        Field::QuoteAcceptTime,
+        strict,
+    )?;
+    parse_end_of_message(payload)?;
+    Ok(QuoteExt {
+        message_type: copy_field(payload, 0),
+        issue_code,
+        issue_sequence: copy_field(payload, ISSUE_SEQUENCE_START),
+        market_status: copy_field(payload, MARKET_STATUS_START),
+        bids,
+        asks,
+        accept_time,
+    })
+}
+
+/// Parses a trade payload, with the same checks as [`parse_quote`].
+pub fn parse_trade(
+    payload: &[u8; TRADE_PAYLOAD_SIZE],
+    strict: bool,
+) -> Result<Trade, PayloadError> {
+    let issue_code = parse_issue_code(payload)?;
+    let fields = (Field::TradePrice, Field::TradeQuantity);
+    let (quantity, price) = parse_level(payload, TRADE_PRICE_START, fields, strict)?;
+    let trade_time = parse_exchange_time(payload, TRADE_TIME_START, Field::TradeTime, strict)?;
+    parse_end_of_message(payload)?;
+    Ok(Trade {
+        message_type: copy_field(payload, 0),
+        issue_code,
+        issue_sequence: copy_field(payload, ISSUE_SEQUENCE_START),
+        board_id: copy_field(payload, BOARD_ID_START),
+        price,
+        quantity,
+        trade_time,
+    })
+}
+
+fn copy_field<const N: usize>(payload: &[u8], start: usize) -> [u8; N] {
+    let mut field = [0; N];
+    field.copy_from_slice(&payload[start..start + N]);
+    field
+}
+
+fn check_digits(
+    payload: &[u8],
+    start: usize,
+    len: usize,
+    field: Field,
+) -> Result<(), PayloadError> {
+    match payload[start..start + len]
+        .iter()
+        .position(|byte| !byte.is_ascii_digit())
+    {
+        // The offset is that of the field rather than the offending byte, like everywhere else.
+        Some(_) => Err(PayloadError::NonDigit {
+            offset: start,
+            field,
+        }),
+        None => Ok(()),
+    }
+}
+
+// Issue codes are ISIN-style, so anything but printable ASCII means the payload is garbage. That
+// also makes them valid UTF-8, so they can be printed as they are.
+fn parse_issue_code(payload: &[u8]) -> Result<[u8; ISSUE_CODE_SIZE], PayloadError> {
+    let issue_code: [u8; ISSUE_CODE_SIZE] = copy_field(payload, ISSUE_CODE_START);
+    if !issue_code.iter().all(|&c| (0x20..=0x7E).contains(&c)) {
+        return Err(PayloadError::IssueCode {
+            offset: ISSUE_CODE_START,
+            bytes: issue_code,
+        });
+    }
+    Ok(issue_code)
+}
+
+// The fields of the bid and ask level numbered `level`.
+const BID_FIELDS: fn(usize) -> (Field, Field) =
+    |level| (Field::BidPrice(level), Field::BidQuantity(level));
+const ASK_FIELDS: fn(usize) -> (Field, Field) =
+    |level| (Field::AskPrice(level), Field::AskQuantity(level));
+
+// Parses the `N` levels of one side of the book from `start`, with `fields` naming those of a level.
+fn parse_side<const N: usize>(
+    payload: &[u8],
+    start: usize,
+    fields: fn(usize) -> (Field, Field),
+    strict: bool,
+) -> Result<[(u32, u32); N], PayloadError> {
+    let mut side = [(0, 0); N];
+    for (level, entry) in side.iter_mut().enumerate() {
+        let start = start + level * LEVEL_SIZE;
+        *entry = parse_level(payload, start, fields(level + 1), strict)?;
+    }
+    Ok(side)
+}
+
+// Parses the price and quantity of a book level, or of a trade, as `(quantity, price)`.
+fn parse_level(
+    payload: &[u8],
+    start: usize,
+    (price_field, quantity_field): (Field, Field),
+    strict: bool,
+) -> Result<(u32, u32), PayloadError> {
+    let quantity_start = start + PRICE_SIZE;
+    if strict {
+        check_digits(payload, start, PRICE_SIZE, price_field)?;
+        check_digits(payload, quantity_start, QUANTITY_SIZE, quantity_field)?;
+    }
+    let parse = |offset: usize, len: usize, field: Field| {
+        str::from_utf8(&payload[offset..offset + len])
+            .ok()
+            .and_then(|s| s.parse().ok())
+            .ok_or(PayloadError::Level { offset, len, field })
+    };
+    let price = parse(start, PRICE_SIZE, price_field)?;
+    let quantity = parse(quantity_start, QUANTITY_SIZE, quantity_field)?;
+    Ok((quantity, price))
+}
+
+fn parse_exchange_time(
+    payload: &[u8],
+    start: usize,
+    field: Field,
+    strict: bool,
+) -> Result<ExchangeTime, PayloadError> {
+    let buf = &payload[start..start + EXCHANGE_TIME_SIZE];
+    if strict {
+        check_digits(payload, start, EXCHANGE_TIME_SIZE, field)?;
+    }
+    // The digit after the seconds isn't used, so it's only checked when strict.
+    let digits = [0..2, 2..4, 4..6, 7..8];
+    if !digits
+        .iter()
+        .all(|digits| buf[digits.clone()].iter().all(u8::is_ascii_digit))
+    {
+        return Err(PayloadError::BadTime {
+            offset: start,
+            field,
+            bytes: copy_field(payload, start),
+        });
+    }
+    let number = |from: usize, len: usize| {
+        buf[from..from + len]
+            .iter()
+            .fold(0, |number, &digit| number * 10 + u32::from(digit - b'0'))
+    };
+    if strict {
+        for &(unit, from, max) in &[("hours", 0, 24), ("minutes", 2, 60), ("seconds", 4, 60)] {
+            let value = number(from, 2);
+            if value >= max {
+                return Err(PayloadError::TimeRange {
+                    offset: start + from,
+                    field,
+                    unit,
+                    value,
+                });
+            }
+        }
+    }
+    let seconds =
+        i64::from(number(0, 2)) * 3_600 + i64::from(number(2, 2)) * 60 + i64::from(number(4, 2));
+    Ok(ExchangeTime {
+        seconds,
+        nanoseconds: number(7, 1) * EXCHANGE_TIME_STEP,
+    })
+}
+
+fn parse_end_of_message(payload: &[u8]) -> Result<(), PayloadError> {
+    let offset = payload.len() - 1;
+    match payload[offset] {
+        END_OF_MESSAGE => Ok(()),
+        byte => Err(PayloadError::EndOfMessage { offset, byte }),
+    }
+}
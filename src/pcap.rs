@@ -0,0 +1,430 @@
+//! Finding quote and trade payloads in pcap captures, for callers that want the packets without
+//! everything the parse-quote binary does around them.
+//!
+//! Only the classic pcap format is read, version 2.4 in either byte order and with micro or
+//! nanosecond timestamps, whose global header [`PcapHeader`] describes. Payloads are expected
+//! right after the IPv4 and UDP headers, behind whichever link layer header [`LinkType`] says the
+//! packets start with, and to start with the standard message types. A payload can hold several
+//! quotes back to back, and records holding anything else are skipped.
+//!
+//! With the `async` feature, [`AsyncPacketReader`] reads captures from a Tokio `AsyncRead` too.
+
+use crate::payload::{
+    self, Quote, QuoteExt, Trade, MESSAGE_TYPE_SIZE, QUOTE_EXT_PAYLOAD_SIZE, QUOTE_MESSAGE_TYPE,
+    QUOTE_PAYLOAD_SIZE, TRADE_MESSAGE_TYPE, TRADE_PAYLOAD_SIZE,
+};
+use std::convert::TryInto;
+use std::io::{self, ErrorKind, Read};
+use std::ops::ControlFlow;
+
+#[cfg(feature = "async")]
+mod async_reader;
+#[cfg(feature = "async")]
+pub use async_reader::{AsyncPacketReader, OwnedPacket};
+
+const HEADER_SIZE: usize = 24;
+const RECORD_HEADER_SIZE: usize = 16;
+// Anything longer is taken to be garbage rather than allocated for.
+const MAX_RECORD_SIZE: usize = 256 * 1024;
+const ETHERNET_HEADER_SIZE: usize = 14;
+const SLL_HEADER_SIZE: usize = 16;
+const SLL2_HEADER_SIZE: usize = 20;
+const NULL_HEADER_SIZE: usize = 4;
+const IPV4_HEADER_SIZE: usize = 20;
+const UDP_HEADER_SIZE: usize = 8;
+const ETHERTYPE_OFFSET: usize = 12;
+const SLL_PROTOCOL_OFFSET: usize = 14;
+const SLL2_PROTOCOL_OFFSET: usize = 0;
+// The BSD address family of IPv4, which NULL headers hold in the byte order of the machine that
+// wrote the capture.
+const AF_INET: u32 = 2;
+const ETHERTYPE_IPV4: &[u8; 2] = &[0x08, 0x00];
+const IP_PROTOCOL_OFFSET: usize = 9;
+/// The IP protocol number of UDP, which carries the whole market feed.
+pub const UDP_PROTOCOL: u8 = 17;
+
+/// The payload of a packet.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Payload {
+    Quote(Quote),
+    QuoteExt(QuoteExt),
+    Trade(Trade),
+}
+
+/// How the packets of a capture are framed, going by the link type in its header.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LinkType {
+    /// LINKTYPE_ETHERNET, or DLT_EN10MB: an Ethernet header in front of the IPv4 one.
+    Ethernet,
+    /// LINKTYPE_RAW: the IPv4 header right at the start of the packet.
+    Raw,
+    /// LINKTYPE_LINUX_SLL, from `tcpdump -i any`: a 16-byte cooked header ending in the protocol.
+    LinuxSll,
+    /// LINKTYPE_LINUX_SLL2: a 20-byte cooked header starting with the protocol.
+    LinuxSll2,
+    /// LINKTYPE_NULL, from BSD loopback interfaces: the 4-byte address family.
+    Null,
+}
+
+impl LinkType {
+    /// The link type for [`PcapHeader::link_type`], or an [`ErrorKind::InvalidData`] error for
+    /// one whose packets can't be read.
+    pub fn from_header(link_type: u32) -> Result<Self, io::Error> {
+        // The upper half can hold the FCS length, which doesn't change where anything starts.
+        match link_type & 0xFFFF {
+            1 => Ok(LinkType::Ethernet),
+            // Some writers put the DLT_RAW value of their platform instead of LINKTYPE_RAW.
+            12 | 14 | 101 => Ok(LinkType::Raw),
+            113 => Ok(LinkType::LinuxSll),
+            276 => Ok(LinkType::LinuxSll2),
+            // LINKTYPE_LOOP only differs in always having the family in network byte order.
+            0 | 108 => Ok(LinkType::Null),
+            link_type => Err(invalid_data(&format!(
+                "Unsupported link type {}, only Ethernet (1), raw IP (101), Linux cooked (113 and \
+                 276) and BSD loopback (0) captures can be read",
+                link_type
+            ))),
+        }
+    }
+
+    /// What the link type is called, like "Linux cooked".
+    pub fn name(self) -> &'static str {
+        match self {
+            LinkType::Ethernet => "Ethernet",
+            LinkType::Raw => "raw IP",
+            LinkType::LinuxSll => "Linux cooked",
+            LinkType::LinuxSll2 => "Linux cooked v2",
+            LinkType::Null => "BSD loopback",
+        }
+    }
+
+    /// Where the IPv4 header starts in the captured bytes of a record.
+    pub fn ip_offset(self) -> usize {
+        match self {
+            LinkType::Ethernet => ETHERNET_HEADER_SIZE,
+            LinkType::Raw => 0,
+            LinkType::LinuxSll => SLL_HEADER_SIZE,
+            LinkType::LinuxSll2 => SLL2_HEADER_SIZE,
+            LinkType::Null => NULL_HEADER_SIZE,
+        }
+    }
+
+    /// Where the UDP payload, and with it the message type, starts in the captured bytes of a
+    /// record: after the link layer header, 20 bytes of IPv4 and 8 of UDP header.
+    pub fn payload_offset(self) -> usize {
+        self.ip_offset() + IPV4_HEADER_SIZE + UDP_HEADER_SIZE
+    }
+
+    /// The IP protocol of the packet whose captured bytes are `packet`, if its link layer header
+    /// says it's IPv4 and enough of it was captured to tell.
+    pub fn ip_protocol(self, packet: &[u8]) -> Option<u8> {
+        let ip_offset = self.ip_offset();
+        let packet = packet.get(..ip_offset + IP_PROTOCOL_OFFSET + 1)?;
+        let ethertype = |offset: usize| &packet[offset..offset + 2] == ETHERTYPE_IPV4;
+        let ipv4 = match self {
+            LinkType::Ethernet => ethertype(ETHERTYPE_OFFSET),
+            LinkType::LinuxSll => ethertype(SLL_PROTOCOL_OFFSET),
+            LinkType::LinuxSll2 => ethertype(SLL2_PROTOCOL_OFFSET),
+            LinkType::Null => {
+                let family = [packet[0], packet[1], packet[2], packet[3]];
+                u32::from_le_bytes(family) == AF_INET || u32::from_be_bytes(family) == AF_INET
+            }
+            LinkType::Raw => true,
+        };
+        Some(packet[ip_offset + IP_PROTOCOL_OFFSET]).filter(|_| ipv4 && packet[ip_offset] >> 4 == 4)
+    }
+
+    /// The UDP payload of the packet whose captured bytes are `packet`, unless it's an IPv4
+    /// packet of another protocol or too short to have one.
+    pub fn udp_payload(self, packet: &[u8]) -> Option<&[u8]> {
+        match self.ip_protocol(packet) {
+            Some(protocol) if protocol != UDP_PROTOCOL => None,
+            _ => packet.get(self.payload_offset()..),
+        }
+    }
+}
+
+/// A packet found in a capture, borrowing the bytes captured of its record.
+#[derive(Debug)]
+pub struct Packet<'a> {
+    /// The offset of the record in the capture.
+    pub offset: u64,
+    /// When the packet was captured, as Unix seconds corrected by the time zone offset of the
+    /// capture header, and the nanoseconds after them.
+    pub seconds: i64,
+    pub nanoseconds: u32,
+    /// The bytes captured of the record, from the link layer header on. A record whose payload
+    /// holds several quotes is visited once for each, with the same data.
+    pub data: &'a [u8],
+    pub payload: Payload,
+}
+
+/// The byte order a capture was written in.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Endianness {
+    LittleEndian,
+    BigEndian,
+}
+
+/// The resolution of the record timestamps of a capture.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Precision {
+    Microsecond,
+    Nanosecond,
+}
+
+impl Precision {
+    /// How many nanoseconds one unit of the fraction of a record timestamp stands for.
+    pub fn nanoseconds(self) -> u32 {
+        match self {
+            Precision::Microsecond => 1_000,
+            Precision::Nanosecond => 1,
+        }
+    }
+}
+
+/// The global header at the start of a capture.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PcapHeader {
+    /// The byte order and timestamp precision, both told by the magic number.
+    pub endianness: Endianness,
+    pub precision: Precision,
+    pub version_major: u16,
+    pub version_minor: u16,
+    /// The offset of the timestamps from UTC in seconds, which captures taken west of UTC have
+    /// negative. Almost always 0.
+    pub this_zone: i32,
+    /// The most bytes captured of any packet, or 0 when there's no limit.
+    pub snaplen: u32,
+    /// The LINKTYPE_ value for the link layer the packets start with, like 1 for Ethernet. The
+    /// upper bits can hold the length of a frame check sequence at the end of every packet.
+    pub link_type: u32,
+}
+
+impl PcapHeader {
+    /// The size of the header in bytes.
+    pub const SIZE: usize = HEADER_SIZE;
+
+    /// Parses the header at the start of `bytes`.
+    ///
+    /// Bytes that don't start with a pcap magic number are an [`ErrorKind::InvalidData`] error,
+    /// and so is a version other than 2.4, while fewer than [`PcapHeader::SIZE`] bytes with the
+    /// right magic number are [`ErrorKind::UnexpectedEof`].
+    pub fn parse(bytes: &[u8]) -> Result<Self, io::Error> {
+        let magic = bytes.get(..4).ok_or(ErrorKind::UnexpectedEof)?;
+        let (endianness, precision) =
+            Self::magic_format(magic).ok_or_else(|| invalid_data("Invalid file format"))?;
+        Self::parse_as(bytes, endianness, precision)
+    }
+
+    /// The byte order and timestamp precision the magic number at the start of `bytes` stands for,
+    /// if it's one.
+    pub fn magic_format(bytes: &[u8]) -> Option<(Endianness, Precision)> {
+        match bytes.get(..4)? {
+            [0xD4, 0xC3, 0xB2, 0xA1] => Some((Endianness::LittleEndian, Precision::Microsecond)),
+            [0xA1, 0xB2, 0xC3, 0xD4] => Some((Endianness::BigEndian, Precision::Microsecond)),
+            [0x4D, 0x3C, 0xB2, 0xA1] => Some((Endianness::LittleEndian, Precision::Nanosecond)),
+            [0xA1, 0xB2, 0x3C, 0x4D] => Some((Endianness::BigEndian, Precision::Nanosecond)),
+            _ => None,
+        }
+    }
+
+    /// Like [`PcapHeader::parse`], but takes the byte order and precision as given instead of
+    /// from the magic number, which isn't looked at.
+    pub fn parse_as(
+        bytes: &[u8],
+        endianness: Endianness,
+        precision: Precision,
+    ) -> Result<Self, io::Error> {
+        let header = bytes.get(..HEADER_SIZE).ok_or(ErrorKind::UnexpectedEof)?;
+        let u16_field = |i: usize| match endianness {
+            Endianness::LittleEndian => u16::from_le_bytes([header[i], header[i + 1]]),
+            Endianness::BigEndian => u16::from_be_bytes([header[i], header[i + 1]]),
+        };
+        let u32_field = |i: usize| {
+            let bytes = [header[i], header[i + 1], header[i + 2], header[i + 3]];
+            match endianness {
+                Endianness::LittleEndian => u32::from_le_bytes(bytes),
+                Endianness::BigEndian => u32::from_be_bytes(bytes),
+            }
+        };
+        let (version_major, version_minor) = (u16_field(4), u16_field(6));
+        if (version_major, version_minor) != (2, 4) {
+            return Err(invalid_data(&format!(
+                "Unsupported pcap version {}.{}",
+                version_major, version_minor
+            )));
+        }
+        Ok(PcapHeader {
+            endianness,
+            precision,
+            version_major,
+            version_minor,
+            this_zone: u32_field(8) as i32,
+            snaplen: u32_field(16),
+            link_type: u32_field(20),
+        })
+    }
+}
+
+// What the global header of a capture says about reading the records after it.
+#[derive(Clone, Copy)]
+struct Format {
+    link_type: LinkType,
+    big_endian: bool,
+    nanosecond: bool,
+    this_zone: i64,
+}
+
+impl Format {
+    fn parse(header: &[u8]) -> Result<Self, io::Error> {
+        let header = PcapHeader::parse(header)?;
+        Ok(Format {
+            link_type: LinkType::from_header(header.link_type)?,
+            big_endian: header.endianness == Endianness::BigEndian,
+            nanosecond: header.precision == Precision::Nanosecond,
+            this_zone: i64::from(header.this_zone),
+        })
+    }
+
+    fn field(&self, buf: &[u8], i: usize) -> u32 {
+        let bytes = [buf[i], buf[i + 1], buf[i + 2], buf[i + 3]];
+        if self.big_endian {
+            u32::from_be_bytes(bytes)
+        } else {
+            u32::from_le_bytes(bytes)
+        }
+    }
+
+    // How many bytes of the record were captured, which have to be read before the next one.
+    fn captured_length(&self, record_header: &[u8]) -> Result<usize, io::Error> {
+        let captured_length = self.field(record_header, 8) as usize;
+        if captured_length > MAX_RECORD_SIZE {
+            return Err(invalid_data("Record longer than 256 KiB"));
+        }
+        Ok(captured_length)
+    }
+
+    // When the record was captured, as Unix seconds and the nanoseconds after them.
+    fn time(&self, record_header: &[u8]) -> (i64, u32) {
+        let seconds = i64::from(self.field(record_header, 0)) + self.this_zone;
+        let fraction = self.field(record_header, 4);
+        if self.nanosecond {
+            (seconds, fraction)
+        } else {
+            (seconds, fraction.saturating_mul(1_000))
+        }
+    }
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(ErrorKind::InvalidData, message)
+}
+
+// Like `Read::read_exact`, but returns false instead of failing when the input ends before `buf`
+// is touched at all.
+fn read_or_end(reader: &mut impl Read, buf: &mut [u8]) -> Result<bool, io::Error> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        match reader.read(&mut buf[filled..]) {
+            Ok(0) if filled == 0 => return Ok(false),
+            Ok(0) => return Err(io::Error::from(ErrorKind::UnexpectedEof)),
+            Ok(read) => filled += read,
+            Err(e) if e.kind() == ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(true)
+}
+
+/// Returns the message at `start` of a datagram `payload` whose messages are each `stride` bytes,
+/// if a whole one is there and its message type is one `is_marker` accepts.
+///
+/// Feeds coalesce several quotes into one datagram back to back, some padding each out to a fixed
+/// stride longer than [`QUOTE_PAYLOAD_SIZE`], in which case only the start of a message is the
+/// quote. Stepping through a datagram stops at the first message this returns `None` for, and
+/// whatever is left of the payload from there is trailing bytes.
+pub fn datagram_message(
+    payload: &[u8],
+    start: usize,
+    stride: usize,
+    is_marker: impl Fn(&[u8]) -> bool,
+) -> Option<&[u8]> {
+    payload
+        .get(start..start.checked_add(stride)?)
+        .filter(|message| message.get(..MESSAGE_TYPE_SIZE).is_some_and(&is_marker))
+}
+
+// Parses the message at `start` in `payload`, the UDP payload of a packet, and whatever follows
+// it up to the next one that parses, returning it with where the message after it would start.
+// Quotes can come several to a payload, back to back, while an extended quote or a trade fills
+// one.
+fn next_payload(payload: &[u8], mut start: usize) -> Option<(Payload, usize)> {
+    if start == 0 && payload.starts_with(TRADE_MESSAGE_TYPE) {
+        let trade = payload.get(..TRADE_PAYLOAD_SIZE)?.try_into().unwrap();
+        let trade = payload::parse_trade(trade, false).ok()?;
+        return Some((Payload::Trade(trade), payload.len()));
+    }
+    if start == 0
+        && payload.len() == QUOTE_EXT_PAYLOAD_SIZE
+        && payload.starts_with(QUOTE_MESSAGE_TYPE)
+    {
+        let quote = payload::parse_quote_ext(payload.try_into().unwrap(), false).ok()?;
+        return Some((Payload::QuoteExt(quote), payload.len()));
+    }
+    let is_quote = |marker: &[u8]| marker == QUOTE_MESSAGE_TYPE;
+    while let Some(message) = datagram_message(payload, start, QUOTE_PAYLOAD_SIZE, is_quote) {
+        start += QUOTE_PAYLOAD_SIZE;
+        if let Ok(quote) = payload::parse_quote(message.try_into().unwrap(), false) {
+            return Some((Payload::Quote(quote), start));
+        }
+    }
+    None
+}
+
+/// Calls `f` with every quote and trade packet of the capture read from `reader`, in capture
+/// order, until it returns [`ControlFlow::Break`] or the capture ends.
+///
+/// The bytes of every record are read into one buffer, which is reused for the next record once
+/// `f` returns, so nothing is allocated per packet. That's why `f` only gets to borrow the
+/// packet: whatever it wants to keep of [`Packet::data`] has to be copied out, while the parsed
+/// payload can simply be cloned.
+///
+/// Records that aren't quotes or trades or fail to parse are skipped. A capture that doesn't start
+/// with a pcap header, has a link type other than those of [`LinkType`], has a record longer than
+/// 256 KiB or ends in the middle of a record is an error.
+pub fn for_each_packet<R, F>(mut reader: R, mut f: F) -> Result<(), io::Error>
+where
+    R: Read,
+    F: FnMut(&Packet<'_>) -> ControlFlow<()>,
+{
+    let mut header = [0; HEADER_SIZE];
+    reader.read_exact(&mut header)?;
+    let format = Format::parse(&header)?;
+    let mut offset = HEADER_SIZE as u64;
+    let mut data = Vec::new();
+    let mut record_header = [0; RECORD_HEADER_SIZE];
+    while read_or_end(&mut reader, &mut record_header)? {
+        let captured_length = format.captured_length(&record_header)?;
+        data.resize(captured_length, 0);
+        reader.read_exact(&mut data)?;
+        let (seconds, nanoseconds) = format.time(&record_header);
+        let udp_payload = format.link_type.udp_payload(&data).unwrap_or_default();
+        let mut start = 0;
+        while let Some((payload, next)) = next_payload(udp_payload, start) {
+            let packet = Packet {
+                offset,
+                seconds,
+                nanoseconds,
+                data: &data,
+                payload,
+            };
+            if f(&packet).is_break() {
+                return Ok(());
+            }
+            start = next;
+        }
+        offset += (RECORD_HEADER_SIZE + captured_length) as u64;
+    }
+    Ok(())
+}
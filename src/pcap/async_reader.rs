@@ -0,0 +1,165 @@
+//! Reading a capture from an [`AsyncRead`], for services that get it from the network and would
+//! rather not block a thread on it.
+
+use super::{next_payload, Format, Payload, HEADER_SIZE, RECORD_HEADER_SIZE};
+use futures_core::Stream;
+use std::future;
+use std::io::{self, ErrorKind};
+use std::pin::Pin;
+use std::task::{ready, Context, Poll};
+use tokio::io::{AsyncRead, ReadBuf};
+
+/// A packet yielded by [`AsyncPacketReader`]. Unlike [`Packet`](super::Packet) it owns what it
+/// holds, and so leaves the captured bytes of the record behind.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct OwnedPacket {
+    /// The offset of the record in the capture.
+    pub offset: u64,
+    /// When the packet was captured, as Unix seconds corrected by the time zone offset of the
+    /// capture header, and the nanoseconds after them.
+    pub seconds: i64,
+    pub nanoseconds: u32,
+    pub payload: Payload,
+}
+
+/// A [`Stream`] of the quote and trade packets of the capture read from `R`, in capture order.
+///
+/// Records are read into a buffer owned by the reader, so a read that's cancelled halfway through
+/// a record, say by dropping a [`next_packet`](Self::next_packet) future, loses nothing and the
+/// next one carries on from where it stopped. Records are skipped and errors reported as with
+/// [`for_each_packet`](super::for_each_packet), and the stream ends after an error.
+pub struct AsyncPacketReader<R> {
+    reader: R,
+    // `None` until the global header has been read.
+    format: Option<Format>,
+    // The header of the record whose data is being read, if it's been read already.
+    record_header: Option<[u8; RECORD_HEADER_SIZE]>,
+    // The record whose data was read last, while its payload may hold more messages.
+    record: Option<Record>,
+    // What's being read, the global header, a record header or the data of a record, and how much
+    // of it is in so far.
+    buf: Vec<u8>,
+    filled: usize,
+    offset: u64,
+    done: bool,
+}
+
+// A record read whole, whose data stays in `buf` until all of its messages have been yielded.
+struct Record {
+    offset: u64,
+    seconds: i64,
+    nanoseconds: u32,
+    // Where in the payload the next message starts.
+    next: usize,
+}
+
+impl<R: AsyncRead + Unpin> AsyncPacketReader<R> {
+    pub fn new(reader: R) -> Self {
+        AsyncPacketReader {
+            reader,
+            format: None,
+            record_header: None,
+            record: None,
+            buf: Vec::new(),
+            filled: 0,
+            offset: 0,
+            done: false,
+        }
+    }
+
+    /// Returns the next packet, or `None` once the capture has ended.
+    pub async fn next_packet(&mut self) -> Option<Result<OwnedPacket, io::Error>> {
+        future::poll_fn(|cx| Pin::new(&mut *self).poll_next(cx)).await
+    }
+
+    // Reads until `buf` holds `len` bytes, returning false if the input ended before any of them.
+    fn poll_fill(&mut self, cx: &mut Context<'_>, len: usize) -> Poll<Result<bool, io::Error>> {
+        self.buf.resize(len, 0);
+        while self.filled < len {
+            let mut buf = ReadBuf::new(&mut self.buf[self.filled..]);
+            ready!(Pin::new(&mut self.reader).poll_read(cx, &mut buf))?;
+            match buf.filled().len() {
+                0 if self.filled == 0 => return Poll::Ready(Ok(false)),
+                0 => return Poll::Ready(Err(io::Error::from(ErrorKind::UnexpectedEof))),
+                read => self.filled += read,
+            }
+        }
+        self.filled = 0;
+        Poll::Ready(Ok(true))
+    }
+
+    fn poll_packet(
+        &mut self,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<Option<OwnedPacket>, io::Error>> {
+        loop {
+            let format = match self.format {
+                Some(format) => format,
+                None => {
+                    if !ready!(self.poll_fill(cx, HEADER_SIZE))? {
+                        return Poll::Ready(Err(io::Error::from(ErrorKind::UnexpectedEof)));
+                    }
+                    self.format = Some(Format::parse(&self.buf)?);
+                    self.offset = HEADER_SIZE as u64;
+                    continue;
+                }
+            };
+            if let Some(record) = &mut self.record {
+                let udp_payload = format.link_type.udp_payload(&self.buf).unwrap_or_default();
+                if let Some((payload, next)) = next_payload(udp_payload, record.next) {
+                    record.next = next;
+                    return Poll::Ready(Ok(Some(OwnedPacket {
+                        offset: record.offset,
+                        seconds: record.seconds,
+                        nanoseconds: record.nanoseconds,
+                        payload,
+                    })));
+                }
+                self.record = None;
+            }
+            let record_header = match self.record_header {
+                Some(record_header) => record_header,
+                None => {
+                    if !ready!(self.poll_fill(cx, RECORD_HEADER_SIZE))? {
+                        return Poll::Ready(Ok(None));
+                    }
+                    let mut record_header = [0; RECORD_HEADER_SIZE];
+                    record_header.copy_from_slice(&self.buf);
+                    self.record_header = Some(record_header);
+                    continue;
+                }
+            };
+            let captured_length = format.captured_length(&record_header)?;
+            // An empty record is filled as soon as it's started.
+            if !ready!(self.poll_fill(cx, captured_length))? {
+                return Poll::Ready(Err(io::Error::from(ErrorKind::UnexpectedEof)));
+            }
+            let offset = self.offset;
+            self.offset += (RECORD_HEADER_SIZE + captured_length) as u64;
+            self.record_header = None;
+            let (seconds, nanoseconds) = format.time(&record_header);
+            self.record = Some(Record {
+                offset,
+                seconds,
+                nanoseconds,
+                next: 0,
+            });
+        }
+    }
+}
+
+impl<R: AsyncRead + Unpin> Stream for AsyncPacketReader<R> {
+    type Item = Result<OwnedPacket, io::Error>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        if this.done {
+            return Poll::Ready(None);
+        }
+        let packet = ready!(this.poll_packet(cx));
+        if !matches!(packet, Ok(Some(_))) {
+            this.done = true;
+        }
+        Poll::Ready(packet.transpose())
+    }
+}
@@ -0,0 +1,2041 @@
+use assert_cmd::Command;
+use predicates::prelude::*;
+use std::fs;
+
+const FIXTURES: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures");
+
+fn parse_quote() -> Command {
+    Command::cargo_bin("parse-quote").unwrap()
+}
+
+fn fixture(name: &str) -> String {
+    format!("{}/{}", FIXTURES, name)
+}
+
+fn golden(name: &str) -> String {
+    fs::read_to_string(fixture(name)).unwrap()
+}
+
+#[test]
+fn prints_quotes_in_capture_order() {
+    parse_quote()
+        .arg(fixture("quotes.pcap"))
+        .assert()
+        .success()
+        .stdout(golden("quotes.out"));
+}
+
+#[test]
+fn reorders_quotes_by_accept_time() {
+    parse_quote()
+        .args(["-r", &fixture("quotes.pcap")])
+        .assert()
+        .success()
+        .stdout(golden("quotes_reorder.out"));
+}
+
+#[test]
+fn reads_big_endian_captures() {
+    parse_quote()
+        .arg(fixture("quotes_be.pcap"))
+        .assert()
+        .success()
+        .stdout(golden("quotes.out"));
+    parse_quote()
+        .args(["-r", &fixture("quotes_be.pcap")])
+        .assert()
+        .success()
+        .stdout(golden("quotes_reorder.out"));
+}
+
+#[test]
+fn warps_accept_date_across_midnight() {
+    // 08:59:59 KST accepted just after midnight UTC belongs to the previous day.
+    parse_quote()
+        .arg(fixture("quotes.pcap"))
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(
+            "2011-02-16 00:00:01.000500 2011-02-15 23:59:59 KR0000000001",
+        ));
+}
+
+#[test]
+fn fails_on_missing_file() {
+    parse_quote()
+        .arg(fixture("missing.pcap"))
+        .assert()
+        .failure()
+        .code(1)
+        .stderr(predicate::str::starts_with("Error: "));
+}
+
+#[test]
+fn renders_empty_levels() {
+    // The second bid level has a price but no quantity, so it isn't empty.
+    let quote = "2011-02-16 00:00:01 2011-02-16 00:00:00.005 KR4201011009";
+    for &(style, levels) in &[
+        ("zero", "0@0 0@0 0@0 0@99 5@100 0@0 0@0 0@0 0@0 0@0"),
+        ("dash", "- - - 0@99 5@100 - - - - -"),
+        ("omit", "0@99 5@100"),
+    ] {
+        parse_quote()
+            .args(["--empty-as", style, &fixture("empty.pcap")])
+            .assert()
+            .success()
+            .stdout(format!("{} {}\n", quote, levels));
+    }
+}
+
+#[test]
+fn aggregates_by_symbol() {
+    let issues = |args: &[&str]| {
+        let output = parse_quote()
+            .args(args)
+            .arg(fixture("quotes.pcap"))
+            .output()
+            .unwrap();
+        assert!(output.status.success());
+        String::from_utf8(output.stdout)
+            .unwrap()
+            .lines()
+            .map(|line| line.split(' ').nth(4).unwrap().to_string())
+            .collect::<Vec<_>>()
+    };
+    let first_seen = vec![
+        "KR4201011009",
+        "KR4201011009",
+        "KR4201011009",
+        "KR0000000001",
+        "KR7005930003",
+    ];
+    assert_eq!(issues(&["--aggregate-by-symbol"]), first_seen);
+    let mut sorted = first_seen.clone();
+    sorted.sort_unstable();
+    assert_eq!(issues(&["--aggregate-by-symbol", "--sort-symbols"]), sorted);
+    parse_quote()
+        .args(["--aggregate-by-symbol", "--max-memory", "0"])
+        .arg(fixture("quotes.pcap"))
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("--max-memory 0 MB"));
+}
+
+#[test]
+fn skips_non_udp_packets() {
+    // The middle record carries a quote payload over TCP.
+    parse_quote()
+        .args(["--print-invalid-offsets", &fixture("mixed.pcap")])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("KR0000000006").not())
+        .stdout(predicate::function(|out: &str| out.lines().count() == 2))
+        .stderr(predicate::str::contains("SKIP byte_offset=297"));
+}
+
+#[test]
+fn normalizes_padded_issue_codes() {
+    let quote = "2011-02-16 00:00:01 2011-02-16 00:00:00.005 KR42010";
+    parse_quote()
+        .arg(fixture("padded.pcap"))
+        .assert()
+        .success()
+        .stdout(predicate::str::starts_with(format!(
+            "{}      50@104",
+            quote
+        )));
+    parse_quote()
+        .args(["--normalize-issue-code", &fixture("padded.pcap")])
+        .assert()
+        .success()
+        .stdout(predicate::str::starts_with(format!("{} 50@104", quote)));
+}
+
+#[test]
+fn excludes_symbols() {
+    parse_quote()
+        .args([
+            "--exclude-symbol",
+            "KR4201011009",
+            "--exclude-symbol",
+            "KR0000000001",
+        ])
+        .arg(fixture("quotes.pcap"))
+        .assert()
+        .success()
+        .stdout(predicate::function(|stdout: &str| {
+            stdout.lines().count() == 1 && stdout.contains("KR7005930003")
+        }));
+    parse_quote()
+        .args(["--exclude-symbol", "KR42010", &fixture("padded.pcap")])
+        .assert()
+        .success()
+        .stdout("");
+}
+
+#[test]
+fn samples_quotes() {
+    let sampled: String = golden("quotes.out")
+        .lines()
+        .step_by(2)
+        .map(|line| format!("{}\n", line))
+        .collect();
+    parse_quote()
+        .args(["--sample", "2", &fixture("quotes.pcap")])
+        .assert()
+        .success()
+        .stdout(sampled);
+    // The quotes of an excluded issue don't count toward the sample.
+    parse_quote()
+        .args(["--sample", "2", "--exclude-symbol", "KR0000000001"])
+        .arg(fixture("quotes.pcap"))
+        .assert()
+        .success()
+        .stdout(predicate::function(|stdout: &str| {
+            stdout.lines().count() == 2 && stdout.contains("00:00:03")
+        }));
+}
+
+#[test]
+fn splits_output_by_issue() {
+    let dir = std::env::temp_dir().join("parse-quote-split");
+    let _ = fs::remove_dir_all(&dir);
+    // With one file open at a time, every change of issue closes a file and reopens it later.
+    parse_quote()
+        .arg("--split-by-issue")
+        .arg(&dir)
+        .args(["--max-open-files", "1", &fixture("quotes.pcap")])
+        .assert()
+        .success()
+        .stdout("");
+    let quotes = golden("quotes.out");
+    for issue_code in &["KR4201011009", "KR0000000001", "KR7005930003"] {
+        let expected: String = quotes
+            .lines()
+            .filter(|line| line.contains(issue_code))
+            .map(|line| format!("{}\n", line))
+            .collect();
+        let path = dir.join(format!("{}.txt", issue_code));
+        assert_eq!(fs::read_to_string(path).unwrap(), expected);
+    }
+    assert_eq!(fs::read_dir(&dir).unwrap().count(), 3);
+}
+
+#[cfg(feature = "parquet")]
+#[test]
+fn writes_quotes_as_parquet() {
+    use arrow_array::cast::AsArray;
+    use arrow_array::types::{TimestampNanosecondType, UInt32Type, UInt64Type};
+    use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+
+    let path = std::env::temp_dir().join("parse-quote-quotes.parquet");
+    parse_quote()
+        .args(["--format", "parquet", "-o"])
+        .arg(&path)
+        .arg(fixture("quotes.pcap"))
+        .assert()
+        .success()
+        .stdout("");
+    let reader = ParquetRecordBatchReaderBuilder::try_new(fs::File::open(&path).unwrap())
+        .unwrap()
+        .build()
+        .unwrap();
+    let batches: Vec<_> = reader.map(Result::unwrap).collect();
+    assert_eq!(batches.len(), 1);
+    let batch = &batches[0];
+    assert_eq!(batch.num_rows(), 5);
+    assert_eq!(batch.num_columns(), 25);
+    let column = |name: &str| batch.column_by_name(name).unwrap();
+    let numbers = column("number").as_primitive::<UInt64Type>();
+    assert_eq!(numbers.values(), &[1, 2, 3, 4, 5]);
+    let issue_codes = column("issue_code").as_string::<i32>();
+    assert_eq!(issue_codes.value(1), "KR0000000001");
+    // 2011-02-15 23:59:59 UTC
+    let accept_times = column("accept_time").as_primitive::<TimestampNanosecondType>();
+    assert_eq!(accept_times.value(1), 1_297_814_399_000_000_000);
+    assert_eq!(
+        column("bid_price_1").as_primitive::<UInt32Type>().value(0),
+        100
+    );
+    assert_eq!(
+        column("ask_quantity_5")
+            .as_primitive::<UInt32Type>()
+            .value(0),
+        100
+    );
+}
+
+#[test]
+fn reorders_book_levels() {
+    let quote = "2011-02-16 00:00:01 2011-02-16 00:00:00.005 KR4201011009";
+    let bids = "50@104 40@103 30@102 20@101 10@100";
+    let asks = "20@110 40@111 60@112 80@113 100@114";
+    let best_bids = "10@100 20@101 30@102 40@103 50@104";
+    let deep_asks = "100@114 80@113 60@112 40@111 20@110";
+    for (order, levels) in &[
+        ("book", format!("{} {}", bids, asks)),
+        ("descending", format!("{} {}", deep_asks, best_bids)),
+        ("best-first", format!("{} {}", best_bids, asks)),
+        ("asks-first", format!("{} {}", asks, best_bids)),
+    ] {
+        parse_quote()
+            .args(["--field-order", order, "--head", "1"])
+            .arg(fixture("quotes.pcap"))
+            .assert()
+            .success()
+            .stdout(format!("{} {}\n", quote, levels));
+    }
+}
+
+#[test]
+fn accepts_flags_on_either_side_of_the_path() {
+    for args in &[
+        ["-r", &fixture("quotes.pcap")],
+        [&fixture("quotes.pcap"), "-r"],
+        [&fixture("quotes.pcap"), "--reorder"],
+    ] {
+        parse_quote()
+            .args(args)
+            .assert()
+            .success()
+            .stdout(golden("quotes_reorder.out"));
+    }
+}
+
+#[test]
+fn rejects_bad_usage() {
+    parse_quote()
+        .assert()
+        .failure()
+        .code(2)
+        .stderr(predicate::str::contains("<FILENAME>"));
+    parse_quote()
+        .args(["--bogus", &fixture("quotes.pcap")])
+        .assert()
+        .failure()
+        .code(2)
+        .stderr(predicate::str::contains("unexpected argument '--bogus'"));
+    parse_quote()
+        .args(["--strict", "--warn", &fixture("quotes.pcap")])
+        .assert()
+        .failure()
+        .code(2)
+        .stderr(predicate::str::contains("cannot be used with"));
+    parse_quote()
+        .args(["--head", "many", &fixture("quotes.pcap")])
+        .assert()
+        .failure()
+        .code(2)
+        .stderr(predicate::str::contains(
+            "invalid value 'many' for '--head <N>'",
+        ));
+}
+
+#[test]
+fn documents_every_option() {
+    let output = parse_quote().arg("--help").output().unwrap();
+    assert!(output.status.success());
+    let help = String::from_utf8(output.stdout).unwrap();
+    for option in &[
+        "--reorder",
+        "--strict",
+        "--only",
+        "--epoch",
+        "--field-order",
+    ] {
+        assert!(help.contains(option), "{} is missing from --help", option);
+    }
+}
+
+#[test]
+fn checks_timestamps_are_monotone() {
+    parse_quote()
+        .args(["--check-monotone", &fixture("backward.pcap")])
+        .assert()
+        .success()
+        .stdout(predicate::function(|out: &str| out.lines().count() == 3))
+        .stderr(predicate::str::starts_with(
+            "WARN: Timestamp went backward at offset 0x23a: \
+             2011-02-16 00:00:02 after 2011-02-16 00:00:03 at offset 0x129\n",
+        ));
+    parse_quote()
+        .args(["--check-monotone", "--strict", &fixture("backward.pcap")])
+        .assert()
+        .failure()
+        .code(1)
+        .stderr(predicate::str::starts_with(
+            "Error: Timestamp went backward at offset 0x23a",
+        ));
+    parse_quote()
+        .args(["--check-monotone", "-r", &fixture("quotes.pcap")])
+        .assert()
+        .success()
+        .stderr("Reorder buffer peaked at 3 packets, 1 left at the end\n");
+    // A packet after the late one is checked against it, not against the packet before it.
+    let mut capture = fs::read(fixture("backward.pcap")).unwrap();
+    let mut after = capture[570..].to_vec();
+    after[4..8].copy_from_slice(&500_000u32.to_le_bytes());
+    capture.extend(after);
+    let path = std::env::temp_dir().join("parse-quote-backward.pcap");
+    fs::write(&path, capture).unwrap();
+    parse_quote()
+        .arg("--check-monotone")
+        .arg(&path)
+        .assert()
+        .success()
+        .stderr(predicate::function(|err: &str| {
+            err.matches("went backward").count() == 1
+        }));
+    parse_quote()
+        .arg("--check-monotonic")
+        .arg(&path)
+        .assert()
+        .success()
+        .stdout(
+            "Timestamp went backward at offset 0x23a: \
+             2011-02-16 00:00:02 after 2011-02-16 00:00:03 at offset 0x129\n\
+             1 backward timestamps found\n\
+             Largest gap: 2.000000000s between offsets 0x18 and 0x129\n",
+        );
+}
+
+#[test]
+fn generates_shell_completions() {
+    for shell in &["bash", "zsh", "fish", "powershell"] {
+        let assert = parse_quote()
+            .args(["completions", shell])
+            .assert()
+            .success()
+            .stdout(predicate::str::contains("reorder"))
+            .stdout(predicate::str::contains("field-order"));
+        // PowerShell completion scripts only list the options, not their values.
+        if *shell != "powershell" {
+            assert.stdout(predicate::str::contains("best-first"));
+        }
+    }
+    parse_quote()
+        .args(["completions", "tcsh"])
+        .assert()
+        .failure()
+        .code(2);
+    // Like `parse-quote completions bash | head -2`, with the pipe closed before anything is
+    // written to it.
+    let (reader, writer) = std::io::pipe().unwrap();
+    drop(reader);
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_parse-quote"))
+        .args(["completions", "bash"])
+        .stdout(writer)
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&output.stderr).starts_with("Error: Broken pipe"));
+}
+
+#[test]
+fn applies_and_warns_about_header_time_zone() {
+    // The record is stamped 05:00:01 in a capture taken at UTC-5, which is 00:00:01 UTC.
+    parse_quote()
+        .arg(fixture("zone.pcap"))
+        .assert()
+        .success()
+        .stdout(predicate::str::starts_with(
+            "2011-02-16 00:00:01 2011-02-16 00:00:00.005 KR4201011009",
+        ))
+        .stderr(predicate::str::contains(
+            "time zone offset of -18000 seconds",
+        ));
+    parse_quote()
+        .arg(fixture("quotes.pcap"))
+        .assert()
+        .success()
+        .stderr("");
+}
+
+#[test]
+fn reads_accept_times_in_other_zones() {
+    for zone in &["UTC", "+00:00", "-00"] {
+        let output = parse_quote()
+            .args(["--accept-tz", zone, &fixture("utc.pcap")])
+            .output()
+            .unwrap();
+        assert!(output.status.success());
+        let stdout = String::from_utf8(output.stdout).unwrap();
+        let mut lines = stdout.lines();
+        assert!(lines
+            .next()
+            .unwrap()
+            .starts_with("2011-02-16 00:00:01 2011-02-16 00:00:00.005 KR4201011009"));
+        assert!(lines
+            .next()
+            .unwrap()
+            .starts_with("2011-02-16 00:00:01 2011-02-15 23:59:59 KR4201011009"));
+    }
+    parse_quote()
+        .args(["--accept-tz", "+15:00", &fixture("utc.pcap")])
+        .assert()
+        .failure()
+        .code(2);
+}
+
+#[test]
+fn reorders_ties_in_arrival_order() {
+    // The first three packets share their accept time and timestamp, and the fourth goes before
+    // them. The last one is late enough to flush them from the buffer.
+    for _ in 0..3 {
+        parse_quote()
+            .args(["-r", &fixture("ties.pcap")])
+            .assert()
+            .success()
+            .stdout(predicate::function(|stdout: &str| {
+                let issue_codes: Vec<_> = stdout
+                    .lines()
+                    .map(|line| line.split(' ').nth(4).unwrap())
+                    .collect();
+                issue_codes
+                    == [
+                        "KR4201011009",
+                        "KR0000000003",
+                        "KR0000000001",
+                        "KR0000000002",
+                        "KR4201011009",
+                    ]
+            }));
+    }
+}
+
+// Sorts the lines of packets by `key`, keeping ties in capture order. The key is given the fields
+// from the timestamp on, after skipping `skip` of them.
+fn sorted_lines(stdout: &str, skip: usize, key: impl Fn(&[&str]) -> Vec<String>) -> String {
+    let mut lines: Vec<_> = stdout.lines().collect();
+    lines.sort_by_cached_key(|line| key(&line.split(' ').skip(skip).collect::<Vec<_>>()));
+    lines.iter().map(|line| format!("{}\n", line)).collect()
+}
+
+type LineKey = fn(&[&str]) -> Vec<String>;
+
+// The accept time and then the timestamp, which is what --full-sort and -r order packets by.
+fn accept_time(fields: &[&str]) -> Vec<String> {
+    vec![fields[2..4].join(" "), fields[..2].join(" ")]
+}
+
+fn time_stamp(fields: &[&str]) -> Vec<String> {
+    vec![fields[..2].join(" ")]
+}
+
+fn issue_accept_time(fields: &[&str]) -> Vec<String> {
+    let mut key = vec![fields[4].to_string()];
+    key.extend(accept_time(fields));
+    key
+}
+
+#[test]
+fn reorders_by_each_sort_key() {
+    // Three issues with timestamps a little out of order, and accept times up to 2s behind them.
+    let unsorted = parse_quote()
+        .arg(fixture("shuffled.pcap"))
+        .output()
+        .unwrap();
+    let unsorted = String::from_utf8(unsorted.stdout).unwrap();
+    let keys: [(&str, LineKey); 3] = [
+        ("accept", accept_time),
+        ("packet", time_stamp),
+        ("issue-accept", issue_accept_time),
+    ];
+    for (name, key) in &keys {
+        let sorted = sorted_lines(&unsorted, 0, key);
+        assert_ne!(sorted, unsorted);
+        parse_quote()
+            .args(["-r", "--sort-key", name, &fixture("shuffled.pcap")])
+            .assert()
+            .success()
+            .stdout(sorted);
+    }
+    parse_quote()
+        .args(["--sort-key", "packet", &fixture("shuffled.pcap")])
+        .assert()
+        .failure()
+        .code(2);
+}
+
+#[test]
+fn sorts_through_temporary_files() {
+    let dir = std::env::temp_dir().join("parse-quote-full-sort");
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir(&dir).unwrap();
+    // utc.pcap has its accept times a day off the timestamps, so -r can't put it in order.
+    for (name, only, skip) in &[
+        ("quotes.pcap", "quotes", 0),
+        ("formats.pcap", "all", 1),
+        ("utc.pcap", "quotes", 0),
+    ] {
+        let unsorted = parse_quote()
+            .args(["--only", only, &fixture(name)])
+            .output()
+            .unwrap();
+        let unsorted = String::from_utf8(unsorted.stdout).unwrap();
+        // With two packets per run, every capture is sorted in several runs.
+        parse_quote()
+            .args(["--full-sort", "--sort-run-size", "2", "--only", only])
+            .arg("--tmpdir")
+            .arg(&dir)
+            .arg(fixture(name))
+            .assert()
+            .success()
+            .stdout(sorted_lines(&unsorted, *skip, accept_time));
+    }
+    // The run files are removed when parsing fails too.
+    parse_quote()
+        .args([
+            "--full-sort",
+            "--sort-run-size",
+            "1",
+            "--strict",
+            "--tmpdir",
+        ])
+        .arg(&dir)
+        .arg(fixture("accept_times.pcap"))
+        .assert()
+        .failure();
+    assert_eq!(fs::read_dir(&dir).unwrap().count(), 0);
+}
+
+#[test]
+fn resyncs_past_a_damaged_header() {
+    for (name, order) in &[("quotes.pcap", "little"), ("quotes_be.pcap", "big")] {
+        let mut capture = fs::read(fixture(name)).unwrap();
+        capture[..4].copy_from_slice(b"XXXX");
+        let path = std::env::temp_dir().join(format!("parse-quote-damaged-{}", name));
+        fs::write(&path, capture).unwrap();
+        parse_quote()
+            .arg(&path)
+            .assert()
+            .failure()
+            .stderr("Error: Invalid file format\n");
+        parse_quote()
+            .arg("--resync")
+            .arg(&path)
+            .assert()
+            .success()
+            .stdout(golden("quotes.out"))
+            .stderr(predicate::str::contains(format!(
+                "assuming a {}-endian microsecond capture",
+                order
+            )));
+    }
+}
+
+#[test]
+fn reports_gaps_between_accept_times() {
+    let gap = "GAP: KR4201011009 from=2011-02-16 00:00:01 to=2011-02-16 00:00:04 duration=3s\n";
+    parse_quote()
+        .args(["--gap-report", "2", &fixture("quotes.pcap")])
+        .assert()
+        .success()
+        .stdout(golden("quotes.out"))
+        .stderr(gap);
+    parse_quote()
+        .args(["--gap-report", "2", "--market-hours", "08:00-09:00"])
+        .arg(fixture("quotes.pcap"))
+        .assert()
+        .success()
+        .stderr("");
+    let path = std::env::temp_dir().join("parse-quote-gaps.txt");
+    parse_quote()
+        .args(["-r", "--gap-report", "2", "--gap-output"])
+        .arg(&path)
+        .arg(fixture("quotes.pcap"))
+        .assert()
+        .success()
+        .stderr("Reorder buffer peaked at 3 packets, 1 left at the end\n");
+    assert_eq!(fs::read_to_string(&path).unwrap(), gap);
+}
+
+#[test]
+fn reports_quote_stuffing() {
+    // KR4201011009 has four quotes within 8ms, then three more a second later.
+    parse_quote()
+        .args(["--quote-stuffing-threshold", "2", &fixture("stuffing.pcap")])
+        .assert()
+        .success()
+        .stderr(concat!(
+            "STUFFING_ALERT: KR4201011009 rate=30 updates/sec at 2011-02-16 00:00:00.005\n",
+            "STUFFING_ALERT: KR4201011009 rate=30 updates/sec at 2011-02-16 00:00:01.002\n",
+        ));
+    parse_quote()
+        .args(["--quote-stuffing-threshold", "4", &fixture("stuffing.pcap")])
+        .assert()
+        .success()
+        .stderr("");
+}
+
+#[test]
+fn benchmarks_without_printing_packets() {
+    parse_quote()
+        .args(["--bench", &fixture("quotes.pcap")])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(
+            "Parsed 1756 bytes, 7 records, 5 packets",
+        ))
+        .stdout(predicate::str::contains("KR4201011009").not());
+    parse_quote()
+        .args(["--bench", "-r", "--repeat", "3", &fixture("quotes.pcap")])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Pass 3:"))
+        .stdout(predicate::str::contains("over 3 passes"))
+        .stdout(predicate::str::contains("reordering"));
+}
+
+#[cfg(feature = "count-allocations")]
+#[test]
+fn parses_packets_without_allocating() {
+    // Between them these have skipped, malformed, trade and quote records.
+    for name in &[
+        "mixed.pcap",
+        "accept_times.pcap",
+        "issue_codes.pcap",
+        "formats.pcap",
+    ] {
+        for scan_marker in &[false, true] {
+            let mut command = parse_quote();
+            command.args(["--bench", &fixture(name)]);
+            if *scan_marker {
+                command.arg("--scan-marker");
+            }
+            command.assert().success().stdout(predicate::str::contains(
+                "Allocations in the packet loop: 0\n",
+            ));
+        }
+    }
+}
+
+#[test]
+fn counts_quotes_per_window() {
+    parse_quote()
+        .args(["--quote-rate", "60", &fixture("quotes.pcap")])
+        .assert()
+        .success()
+        .stdout(
+            "2011-02-15 23:59:00 KR0000000001 1\n\
+             2011-02-16 00:00:00 KR4201011009 3\n\
+             2011-02-16 00:00:00 KR0000000001 0\n\
+             2011-02-16 00:00:00 KR7005930003 1\n",
+        );
+    parse_quote()
+        .args(["--quote-rate", "0", &fixture("quotes.pcap")])
+        .assert()
+        .failure()
+        .code(2);
+}
+
+#[test]
+fn sums_volume_per_price() {
+    parse_quote()
+        .args(["--volume-profile", &fixture("quotes.pcap")])
+        .assert()
+        .success()
+        .stdout(predicate::str::starts_with(
+            "KR4201011009 100 30\n\
+             KR4201011009 101 60\n",
+        ))
+        .stdout(predicate::str::ends_with("KR7005930003 114 100\n"));
+    parse_quote()
+        .args(["--volume-profile", "--volume-profile-resolution", "5"])
+        .arg(fixture("quotes.pcap"))
+        .assert()
+        .success()
+        .stdout(
+            "KR4201011009 100 450\n\
+             KR4201011009 110 900\n\
+             KR0000000001 100 150\n\
+             KR0000000001 110 300\n\
+             KR7005930003 100 150\n\
+             KR7005930003 110 300\n",
+        );
+}
+
+#[test]
+fn reports_latency_from_accept_time() {
+    // The quotes were captured 995ms, 2000.5ms, 1250ms, 2997ms and 2s after their accept times.
+    parse_quote()
+        .args(["--latency-stats", &fixture("quotes.pcap")])
+        .assert()
+        .success()
+        .stdout("quotes=5 min=995.000 median=2004.877 p99=2997.000 max=2997.000\n");
+}
+
+#[test]
+fn prints_moving_averages_of_mid_prices() {
+    // 105.5 moves the average of KR0000000001 to 105.25, and 106.5 then to 105.875.
+    parse_quote()
+        .args(["--ema", "0.5", &fixture("shuffled.pcap")])
+        .assert()
+        .success()
+        .stdout(predicate::str::starts_with(
+            "2011-02-16 00:00:01.004 KR0000000001 105 105\n\
+             2011-02-16 00:00:01.007 KR0000000001 105.5 105\n\
+             2011-02-16 00:00:00.008 KR4201011009 106 106\n\
+             2011-02-16 00:00:00.002 KR0000000001 106.5 106\n",
+        ));
+    // Every quote of quotes.pcap has the same mid price.
+    parse_quote()
+        .args(["--ema", "0.1", &fixture("quotes.pcap")])
+        .assert()
+        .success()
+        .stdout(
+            "2011-02-16 00:00:00.005 KR4201011009 105 105\n\
+             2011-02-15 23:59:59 KR0000000001 105 105\n\
+             2011-02-16 00:00:00.003 KR7005930003 105 105\n",
+        );
+    parse_quote()
+        .args(["--ema", "0.1", "--ema-output-all", &fixture("quotes.pcap")])
+        .assert()
+        .success()
+        .stdout(predicate::function(|stdout: &str| {
+            stdout.lines().count() == 5
+        }));
+    parse_quote()
+        .args(["--ema", "1.5", &fixture("quotes.pcap")])
+        .assert()
+        .failure()
+        .code(2);
+}
+
+#[test]
+fn prints_snapshots_every_interval() {
+    // shuffled.pcap has quotes of all three issues in every 2 seconds from 00:00:01 to 00:00:09.
+    parse_quote()
+        .args(["--every", "2s", &fixture("shuffled.pcap")])
+        .assert()
+        .success()
+        .stdout(predicate::function(|stdout: &str| {
+            let lines: Vec<_> = stdout.lines().map(|line| &line[..60]).collect();
+            lines.len() == 15
+                && lines[..4]
+                    == [
+                        "2011-02-16 00:00:01.600 2011-02-16 00:00:00.002 KR0000000001",
+                        "2011-02-16 00:00:01.400 2011-02-16 00:00:00.008 KR4201011009",
+                        "2011-02-16 00:00:01.800 2011-02-16 00:00:01.001 KR7005930003",
+                        "2011-02-16 00:00:03.600 2011-02-16 00:00:02.005 KR0000000001",
+                    ]
+        }));
+    parse_quote()
+        .args(["--every", "500ms", "--head", "2", &fixture("shuffled.pcap")])
+        .assert()
+        .success()
+        .stdout(predicate::function(|stdout: &str| {
+            stdout.lines().count() == 2
+        }));
+    for every in ["0s", "5", "1d"] {
+        parse_quote()
+            .args(["--every", every, &fixture("shuffled.pcap")])
+            .assert()
+            .failure()
+            .code(2);
+    }
+    parse_quote()
+        .args(["--every", "3000000h", &fixture("shuffled.pcap")])
+        .assert()
+        .failure()
+        .code(2)
+        .stderr(predicate::str::contains("is too long"));
+}
+
+#[test]
+fn prints_touch_prices() {
+    parse_quote()
+        .args(["--touch", &fixture("shuffled.pcap")])
+        .assert()
+        .success()
+        .stdout(predicate::str::starts_with(
+            "2011-02-16 00:00:01.004 KR0000000001 100 110\n\
+             2011-02-16 00:00:01.007 KR0000000001 101 110\n",
+        ));
+    // The first quote of empty.pcap has no asks.
+    parse_quote()
+        .args(["--touching-price", &fixture("empty.pcap")])
+        .assert()
+        .success()
+        .stdout("2011-02-16 00:00:00.005 KR4201011009 100 0\n");
+    parse_quote()
+        .args(["--touch-mid", &fixture("shuffled.pcap")])
+        .assert()
+        .success()
+        .stdout(predicate::str::starts_with(
+            "2011-02-16 00:00:01.004 KR0000000001 105 0\n\
+             2011-02-16 00:00:01.007 KR0000000001 105 1\n",
+        ));
+    parse_quote()
+        .args(["--touch-mid", &fixture("empty.pcap")])
+        .assert()
+        .success()
+        .stdout("");
+    parse_quote()
+        .args(["--touch", "--touch-mid", &fixture("quotes.pcap")])
+        .assert()
+        .failure()
+        .code(2);
+}
+
+#[test]
+fn correlates_mid_price_returns() {
+    parse_quote()
+        .args(["--correlation-matrix", "--correlation-interval", "1"])
+        .arg(fixture("shuffled.pcap"))
+        .assert()
+        .success()
+        .stdout(
+            ",KR0000000001,KR4201011009,KR7005930003\n\
+             KR0000000001,1.0000,-0.7151,0.2345\n\
+             KR4201011009,-0.7151,1.0000,-0.4136\n\
+             KR7005930003,0.2345,-0.4136,1.0000\n",
+        );
+    parse_quote()
+        .args(["--correlation-matrix", "--correlation-interval", "1"])
+        .args(["--max-symbols", "1", &fixture("shuffled.pcap")])
+        .assert()
+        .success()
+        .stdout(",KR0000000001\nKR0000000001,1.0000\n")
+        .stderr("Warning: more than 1 issues, only correlating the first of them\n");
+    // The mid prices of quotes.pcap never change, so nothing correlates.
+    parse_quote()
+        .args([
+            "--correlation-matrix",
+            "--max-symbols",
+            "2",
+            &fixture("quotes.pcap"),
+        ])
+        .assert()
+        .success()
+        .stdout(predicate::str::ends_with("KR0000000001,NaN,NaN\n"));
+}
+
+#[test]
+fn formats_times_and_fields_like_before() {
+    let formats = fixture("formats.pcap");
+    parse_quote()
+        .args(["--tape", &formats])
+        .assert()
+        .success()
+        .stdout(golden("formats_tape.out"));
+    parse_quote()
+        .args([
+            "--only",
+            "all",
+            "--packet-number",
+            "--with-offset",
+            "--verbose",
+        ])
+        .args([
+            "--trade-magic",
+            "A3034",
+            "--empty-as",
+            "dash",
+            "--field-order",
+            "best-first",
+        ])
+        .args(["--flag-crossed", "--normalize-issue-code", &formats])
+        .assert()
+        .success()
+        .stdout(golden("formats_fields.out"));
+    parse_quote()
+        .args([
+            "--only",
+            "all",
+            "--decimal-offset",
+            "--epoch",
+            "ns",
+            "--empty-as",
+            "omit",
+        ])
+        .arg(&formats)
+        .assert()
+        .success()
+        .stdout(golden("formats_epoch.out"));
+}
+
+#[test]
+fn prints_depth_imbalance() {
+    parse_quote()
+        .args(["--mdi", "--tail", "3", &fixture("formats.pcap")])
+        .assert()
+        .success()
+        .stdout(
+            "2011-02-16 00:00:01.001 KR4201011009 -3333 -7777 -10000 -10000 -10000\n\
+             2011-02-17 00:00:00 KR4201011009 -3333 -3333 -3333 -3333 -3333\n\
+             2011-02-17 00:00:01 KR0000000001 -3333 -3333 -3333 -3333 -3333\n",
+        );
+}
+
+#[test]
+fn bounds_the_reorder_buffer() {
+    parse_quote()
+        .args(["-r", "--max-buffer", "3", &fixture("quotes.pcap")])
+        .assert()
+        .success()
+        .stdout(golden("quotes_reorder.out"))
+        .stderr("Reorder buffer peaked at 3 packets, 1 left at the end\n");
+    parse_quote()
+        .args(["-r", "--max-buffer", "2", &fixture("quotes.pcap")])
+        .assert()
+        .failure()
+        .code(1)
+        .stderr(
+            "Error: More than 2 packets buffered for reordering at offset 0x3c1, the accept times \
+             may never become final\n",
+        );
+}
+
+#[test]
+fn merges_captures_without_duplicates() {
+    parse_quote()
+        .args(["--merge", "--with-offset"])
+        .args([fixture("tap_a.pcap"), fixture("tap_b.pcap")])
+        .assert()
+        .success()
+        .stdout(predicate::function(|stdout: &str| {
+            let offsets: Vec<_> = stdout.lines().map(|line| &line[..5]).collect();
+            offsets == ["0x18 ", "0x129", "0x23a", "0x23a"]
+        }))
+        .stderr(
+            "Dropped 2 duplicate packets\nReorder buffer peaked at 5 packets, 2 left at the end\n",
+        );
+    // The same packets in the other byte order are all duplicates.
+    parse_quote()
+        .args([
+            "-r",
+            "--merge",
+            &fixture("quotes.pcap"),
+            &fixture("quotes_be.pcap"),
+        ])
+        .assert()
+        .success()
+        .stdout(golden("quotes_reorder.out"))
+        .stderr(predicate::str::starts_with("Dropped 5 duplicate packets\n"));
+    // empty.pcap ends after its first packet, and the rest of quotes.pcap still comes through.
+    parse_quote()
+        .args(["--merge", &fixture("empty.pcap"), &fixture("quotes.pcap")])
+        .assert()
+        .success()
+        .stdout(predicate::function(|stdout: &str| {
+            let rest: String = stdout
+                .lines()
+                .filter(|line| !line.contains(" 0@0 "))
+                .map(|line| format!("{}\n", line))
+                .collect();
+            stdout.lines().count() == 6 && rest == golden("quotes_reorder.out")
+        }));
+    parse_quote()
+        .args([fixture("tap_a.pcap"), fixture("tap_b.pcap")])
+        .assert()
+        .failure()
+        .code(2);
+}
+
+#[test]
+fn merge_sorts_captures_keeping_every_packet() {
+    parse_quote()
+        .args(["--merge-sort-files", "--with-offset"])
+        .args([fixture("tap_a.pcap"), fixture("tap_b.pcap")])
+        .assert()
+        .success()
+        .stdout(predicate::function(|stdout: &str| {
+            let offsets: Vec<_> = stdout.lines().map(|line| &line[..5]).collect();
+            offsets == ["0x18 ", "0x18 ", "0x129", "0x129", "0x23a", "0x23a"]
+        }));
+    // Packets at the same time come out in the order the captures were listed.
+    let doubled: String = golden("quotes_reorder.out")
+        .lines()
+        .map(|line| format!("{}\n{}\n", line, line))
+        .collect();
+    parse_quote()
+        .args([
+            "--merge-sort-files",
+            &fixture("quotes.pcap"),
+            &fixture("quotes_be.pcap"),
+        ])
+        .assert()
+        .success()
+        .stdout(doubled)
+        .stderr("Reorder buffer peaked at 6 packets, 2 left at the end\n");
+    parse_quote()
+        .args(["--merge-sort-files", "--merge"])
+        .args([fixture("tap_a.pcap"), fixture("tap_b.pcap")])
+        .assert()
+        .failure()
+        .code(2);
+}
+
+#[test]
+fn skips_issue_codes_that_are_not_printable_ascii() {
+    let issue_codes = fixture("issue_codes.pcap");
+    parse_quote()
+        .args(["--warn", &issue_codes])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("KR4201011009").count(1))
+        .stderr(predicate::str::contains(
+            "not printable ASCII: [4b, 52, 34, 32, 30, 31, 30, 31, 31, 30, 30, 00]",
+        ))
+        .stderr(predicate::str::contains(
+            "not printable ASCII: [4b, 52, 34, 32, 30, 31, 30, 31, 31, 30, c3, a9]",
+        ))
+        .stderr(predicate::str::contains("Skipped 2 malformed packets"));
+    parse_quote()
+        .args(["--strict", &issue_codes])
+        .assert()
+        .failure()
+        .code(1)
+        .stderr(predicate::str::contains("Issue code at offset 0x168"));
+}
+
+#[test]
+fn prints_selected_price_levels() {
+    let quote = "2011-02-16 00:00:01 2011-02-16 00:00:00.005 KR4201011009";
+    parse_quote()
+        .args(["--price-level", "3", &fixture("quotes.pcap")])
+        .assert()
+        .success()
+        .stdout(predicate::str::starts_with(format!(
+            "{} 30@102 60@112\n",
+            quote
+        )));
+    parse_quote()
+        .args(["--price-levels", "1,3,5", "--field-order", "best-first"])
+        .arg(fixture("quotes.pcap"))
+        .assert()
+        .success()
+        .stdout(predicate::str::starts_with(format!(
+            "{} 10@100 30@102 50@104 20@110 60@112 100@114\n",
+            quote
+        )));
+    parse_quote()
+        .args(["--price-level", "2", "--price-levels", "1"])
+        .arg(fixture("quotes.pcap"))
+        .assert()
+        .failure()
+        .code(2);
+}
+
+#[test]
+fn prints_cumulative_depth() {
+    let output = parse_quote()
+        .args([
+            "--cumulative-depth",
+            "--empty-as",
+            "dash",
+            &fixture("formats.pcap"),
+        ])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let levels = " - - - C15@99 C10@100 C20@110 C60@111 C120@112 C200@113 C300@114";
+    assert!(stdout.lines().nth(2).unwrap().ends_with(levels));
+}
+
+#[test]
+fn reports_malformed_accept_times() {
+    parse_quote()
+        .args([
+            "--warn",
+            "--error-format",
+            "json",
+            &fixture("accept_times.pcap"),
+        ])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("KR4201011009").count(1))
+        .stderr(predicate::str::contains(r#""kind":"bad_accept_time""#))
+        .stderr(predicate::str::contains(
+            "Malformed quote accept time at offset 0x231 from [30, 39, 3a, 30, 30, 3a, 30, 30]",
+        ));
+}
+
+#[test]
+fn reads_directories_and_glob_patterns() {
+    let dir = std::env::temp_dir().join("parse-quote-inputs");
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(dir.join("nested")).unwrap();
+    fs::copy(fixture("quotes.pcap"), dir.join("nested/quotes.pcap")).unwrap();
+    fs::write(dir.join("notes.txt"), "not a capture").unwrap();
+    parse_quote()
+        .arg(&dir)
+        .assert()
+        .failure()
+        .code(2)
+        .stderr(predicate::str::contains("no captures found in"));
+    parse_quote()
+        .arg("--recursive")
+        .arg(&dir)
+        .arg("-v")
+        .assert()
+        .success()
+        .stdout(golden("quotes.out"))
+        .stderr(predicate::str::contains("Reading "));
+    fs::copy(fixture("quotes_be.pcap"), dir.join("quotes_be.pcap")).unwrap();
+    let pattern = format!("{}/*/*.pcap", dir.display());
+    parse_quote()
+        .arg(&pattern)
+        .assert()
+        .success()
+        .stdout(golden("quotes.out"));
+    parse_quote()
+        .args(["--recursive", "--merge"])
+        .arg(&dir)
+        .assert()
+        .success()
+        .stdout(golden("quotes_reorder.out"))
+        .stderr(predicate::str::starts_with("Dropped 5 duplicate packets\n"));
+    parse_quote()
+        .arg("--recursive")
+        .arg(&dir)
+        .assert()
+        .failure()
+        .code(2)
+        .stderr(predicate::str::contains("matched 2 captures"));
+}
+
+#[test]
+fn alerts_about_crossed_books() {
+    // The third quote is locked rather than crossed, which isn't reported.
+    parse_quote()
+        .args(["--alert-crossed-book", &fixture("crossed.pcap")])
+        .assert()
+        .success()
+        .stdout(predicate::function(|stdout: &str| {
+            stdout.lines().count() == 5
+        }))
+        .stderr(
+            "CROSSED: KR4201011009 bid=100 ask=99 at 2011-02-16 00:00:00.005\n\
+             CROSSED: KR7005930003 bid=100 ask=98 at 2011-02-16 00:00:00.003\n\
+             CROSSED: KR4201011009 bid=100 ask=97 at 2011-02-16 00:00:04\n\
+             KR4201011009: 2 crossed-book events\n\
+             KR7005930003: 1 crossed-book events\n",
+        );
+}
+
+// Writes quotes.pcap with its header time zone offset and first record timestamp replaced.
+fn with_timestamp(name: &str, this_zone: i32, seconds: u32, fraction: u32) -> std::path::PathBuf {
+    let mut capture = fs::read(fixture("quotes.pcap")).unwrap();
+    capture[8..12].copy_from_slice(&this_zone.to_le_bytes());
+    capture[24..28].copy_from_slice(&seconds.to_le_bytes());
+    capture[28..32].copy_from_slice(&fraction.to_le_bytes());
+    let path = std::env::temp_dir().join(format!("parse-quote-timestamp-{}.pcap", name));
+    fs::write(&path, capture).unwrap();
+    path
+}
+
+#[test]
+fn handles_timestamps_at_the_ends_of_their_range() {
+    // The earliest and latest times a capture can stamp a packet with.
+    let earliest = with_timestamp("earliest", i32::MIN, 0, 0);
+    parse_quote()
+        .args(["--epoch", "ns", "--head", "1"])
+        .arg(&earliest)
+        .assert()
+        .success()
+        .stdout(predicate::str::starts_with("-2147483648000000000 "));
+    let latest = with_timestamp("latest", i32::MAX, u32::MAX, 999_999);
+    parse_quote()
+        .args(["--epoch", "ns", "--head", "1"])
+        .arg(&latest)
+        .assert()
+        .success()
+        .stdout(predicate::str::starts_with("6442450942999999000 "));
+    let fraction = with_timestamp("fraction", 0, 1_297_814_401, u32::MAX);
+    parse_quote().arg(&fraction).assert().failure().stderr(
+        "Error: Timestamp of 1297814401 seconds and 4294967295000 nanoseconds at offset 0x18 \
+             is out of range\n",
+    );
+}
+
+#[test]
+fn colors_the_best_levels() {
+    parse_quote()
+        .args(["--color", "always", "--head", "1", &fixture("quotes.pcap")])
+        .assert()
+        .success()
+        .stdout(
+            "2011-02-16 00:00:01 2011-02-16 00:00:00.005 KR4201011009 50@104 40@103 30@102 \
+             20@101 \x1b[32m10@100\x1b[0m \x1b[31m20@110\x1b[0m 40@111 60@112 80@113 100@114\n",
+        );
+    // Piped output isn't colored unless asked to.
+    parse_quote()
+        .arg(fixture("quotes.pcap"))
+        .env_remove("NO_COLOR")
+        .assert()
+        .success()
+        .stdout(golden("quotes.out"));
+}
+
+#[test]
+fn prints_the_capture_header() {
+    parse_quote()
+        .args(["--info", &fixture("quotes_raw.pcap")])
+        .assert()
+        .success()
+        .stdout(
+            "Byte order: little-endian\n\
+             Timestamps: microseconds\n\
+             Version: 2.4\n\
+             Time zone offset: 0 seconds\n\
+             Snaplen: 65535\n\
+             Link type: 101 (raw IP)\n",
+        );
+    let mut capture = fs::read(fixture("quotes.pcap")).unwrap();
+    capture[6] = 3;
+    let path = std::env::temp_dir().join("parse-quote-version.pcap");
+    fs::write(&path, capture).unwrap();
+    for info in [&["--info"][..], &[]] {
+        parse_quote()
+            .args(info)
+            .arg(&path)
+            .assert()
+            .failure()
+            .stderr("Error: Unsupported pcap version 2.3\n");
+    }
+}
+
+#[test]
+fn forces_the_byte_order_and_precision() {
+    let mut capture = fs::read(fixture("quotes_be.pcap")).unwrap();
+    capture[..4].copy_from_slice(&[0; 4]);
+    let path = std::env::temp_dir().join("parse-quote-no-magic.pcap");
+    fs::write(&path, capture).unwrap();
+    parse_quote()
+        .arg(&path)
+        .assert()
+        .failure()
+        .stderr("Error: Invalid file format\n");
+    parse_quote()
+        .args(["--endian", "be"])
+        .arg(&path)
+        .assert()
+        .success()
+        .stdout(golden("quotes.out"));
+    // Read the wrong way, the version of the header is garbage already.
+    parse_quote()
+        .args(["--endian", "le"])
+        .arg(&path)
+        .assert()
+        .failure()
+        .stderr("Error: Unsupported pcap version 512.1024\n");
+    // Microsecond fractions read as nanoseconds.
+    parse_quote()
+        .args(["--precision", "ns", &fixture("quotes.pcap")])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(
+            "2011-02-16 00:00:01.000000500 2011-02-15 23:59:59 KR0000000001",
+        ));
+}
+
+#[test]
+fn splits_datagrams_into_their_messages() {
+    // The quotes of quotes.pcap, two to the first datagram and three to the second.
+    let quotes = "2011-02-16 00:00:01 2011-02-16 00:00:00.005 KR4201011009 50@104 40@103 30@102 \
+                  20@101 10@100 20@110 40@111 60@112 80@113 100@114\n\
+                  2011-02-16 00:00:01 2011-02-15 23:59:59 KR0000000001 50@104 40@103 30@102 \
+                  20@101 10@100 20@110 40@111 60@112 80@113 100@114\n\
+                  2011-02-16 00:00:02.250 2011-02-16 00:00:01 KR4201011009 50@104 40@103 30@102 \
+                  20@101 10@100 20@110 40@111 60@112 80@113 100@114\n\
+                  2011-02-16 00:00:02.250 2011-02-16 00:00:00.003 KR7005930003 50@104 40@103 \
+                  30@102 20@101 10@100 20@110 40@111 60@112 80@113 100@114\n\
+                  2011-02-16 00:00:02.250 2011-02-16 00:00:04 KR4201011009 50@104 40@103 30@102 \
+                  20@101 10@100 20@110 40@111 60@112 80@113 100@114\n";
+    parse_quote()
+        .arg(fixture("datagrams.pcap"))
+        .assert()
+        .success()
+        .stdout(quotes)
+        .stderr("");
+    parse_quote()
+        .args(["--dry-run", &fixture("datagrams.pcap")])
+        .assert()
+        .success()
+        .stderr("Dry run passed: 5 records, 5 quotes, 0 trades, 0 skipped\n");
+    // Ten more bytes at the end of the second datagram, less than a message.
+    let mut capture = fs::read(fixture("datagrams.pcap")).unwrap();
+    let second = capture.len() - (4 + 42 + 3 * 215) - 12;
+    let length = (42 + 3 * 215 + 10u32).to_le_bytes();
+    capture[second + 8..second + 12].copy_from_slice(&length);
+    capture[second + 12..second + 16].copy_from_slice(&length);
+    capture.extend_from_slice(&[0; 10]);
+    let path = std::env::temp_dir().join("parse-quote-trailing.pcap");
+    fs::write(&path, &capture).unwrap();
+    parse_quote()
+        .arg(&path)
+        .assert()
+        .success()
+        .stdout(quotes)
+        .stderr("Skipped 10 bytes trailing the quote messages of datagrams\n");
+    // Messages padded out to 220 bytes, with --payload-size saying so.
+    let capture = fs::read(fixture("datagrams.pcap")).unwrap();
+    let mut padded = capture[..24].to_vec();
+    for &(record, messages) in &[(24, 2), (24 + 16 + 42 + 2 * 215, 3)] {
+        let length = (42 + 220 * messages as u32).to_le_bytes();
+        padded.extend_from_slice(&capture[record..record + 8]);
+        padded.extend_from_slice(&length);
+        padded.extend_from_slice(&length);
+        padded.extend_from_slice(&capture[record + 16..record + 16 + 42]);
+        for message in 0..messages {
+            let start = record + 16 + 42 + 215 * message;
+            padded.extend_from_slice(&capture[start..start + 215]);
+            padded.extend_from_slice(&[0; 5]);
+        }
+    }
+    fs::write(&path, &padded).unwrap();
+    parse_quote()
+        .args(["--payload-size", "220"])
+        .arg(&path)
+        .assert()
+        .success()
+        .stdout(quotes)
+        .stderr("");
+    // Every message counts as a record, so malformed ones are never more than the records.
+    let mut capture = fs::read(fixture("datagrams.pcap")).unwrap();
+    let mut corrupt = |record: usize, messages: usize| {
+        for message in 0..messages {
+            capture[record + 16 + 42 + 215 * (message + 1) - 1] = 0x00;
+        }
+        fs::write(&path, &capture).unwrap();
+    };
+    let skipped_ratio = |ratio: &str| {
+        parse_quote()
+            .args(["--print-skipped-ratio", "--dry-run"])
+            .arg(&path)
+            .assert()
+            .code(1)
+            .stderr(predicate::str::starts_with(ratio.to_string()));
+    };
+    corrupt(24, 2);
+    skipped_ratio("Parsed: 3 valid, 2 invalid (40.0% skipped)\n");
+    corrupt(24 + 16 + 42 + 2 * 215, 3);
+    skipped_ratio("Parsed: 0 valid, 5 invalid (100.0% skipped)\n");
+    parse_quote()
+        .args(["--dry-run"])
+        .arg(&path)
+        .assert()
+        .code(1)
+        .stderr(predicate::str::ends_with(
+            "Error: Dry run failed: 5 of 5 records malformed, 0 bytes discarded\n",
+        ));
+}
+
+#[test]
+fn watches_a_capture_as_it_grows() {
+    use std::io::Write;
+    use std::process::{Command, Stdio};
+    use std::thread;
+    use std::time::Duration;
+
+    // The capture is written up to the middle of its second record, and the rest after a while.
+    let capture = fs::read(fixture("quotes.pcap")).unwrap();
+    let path = std::env::temp_dir().join("parse-quote-watch.pcap");
+    fs::write(&path, &capture[..24 + 273 + 100]).unwrap();
+    let child = Command::new(env!("CARGO_BIN_EXE_parse-quote"))
+        .args(["--watch", "--watch-timeout", "1"])
+        .arg(&path)
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    thread::sleep(Duration::from_millis(300));
+    let mut file = fs::OpenOptions::new().append(true).open(&path).unwrap();
+    file.write_all(&capture[24 + 273 + 100..]).unwrap();
+    drop(file);
+    let output = child.wait_with_output().unwrap();
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        golden("quotes.out")
+    );
+}
+
+#[test]
+fn flushes_every_line_while_watching() {
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::process::{Command, Stdio};
+
+    let capture = fs::read(fixture("quotes.pcap")).unwrap();
+    let path = std::env::temp_dir().join("parse-quote-line-buffered.pcap");
+    fs::write(&path, &capture[..24 + 273 + 100]).unwrap();
+    let mut child = Command::new(env!("CARGO_BIN_EXE_parse-quote"))
+        .args(["--watch", "--watch-timeout", "2"])
+        .arg(&path)
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    // The first quote comes through a pipe while the second one is still being waited for.
+    let mut stdout = BufReader::new(child.stdout.take().unwrap());
+    let mut first = String::new();
+    stdout.read_line(&mut first).unwrap();
+    assert!(child.try_wait().unwrap().is_none());
+    let mut file = fs::OpenOptions::new().append(true).open(&path).unwrap();
+    file.write_all(&capture[24 + 273 + 100..]).unwrap();
+    drop(file);
+    let mut rest = String::new();
+    stdout.read_to_string(&mut rest).unwrap();
+    assert!(child.wait().unwrap().success());
+    assert_eq!(first + &rest, golden("quotes.out"));
+    parse_quote()
+        .args(["--line-buffered", &fixture("quotes.pcap")])
+        .assert()
+        .success()
+        .stdout(golden("quotes.out"));
+}
+
+#[test]
+fn reads_captures_by_their_link_type() {
+    // The same packets as quotes.pcap without their Ethernet headers.
+    parse_quote()
+        .arg(fixture("quotes_raw.pcap"))
+        .assert()
+        .success()
+        .stdout(golden("quotes.out"));
+    // And with Linux cooked headers in their place, as `tcpdump -i any` writes them.
+    for name in &["quotes_sll.pcap", "quotes_sll2.pcap"] {
+        parse_quote()
+            .arg(fixture(name))
+            .assert()
+            .success()
+            .stdout(golden("quotes.out"));
+    }
+    // And behind the address family of a BSD loopback interface.
+    let raw = fs::read(fixture("quotes_raw.pcap")).unwrap();
+    let mut capture = raw[..24].to_vec();
+    capture[20] = 0;
+    let mut offset = 24;
+    while offset < raw.len() {
+        let field = |i: usize| u32::from_le_bytes([raw[i], raw[i + 1], raw[i + 2], raw[i + 3]]);
+        let length = field(offset + 8) as usize;
+        capture.extend_from_slice(&raw[offset..offset + 8]);
+        capture.extend_from_slice(&(field(offset + 8) + 4).to_le_bytes());
+        capture.extend_from_slice(&(field(offset + 12) + 4).to_le_bytes());
+        capture.extend_from_slice(&2u32.to_le_bytes());
+        capture.extend_from_slice(&raw[offset + 16..offset + 16 + length]);
+        offset += 16 + length;
+    }
+    let path = std::env::temp_dir().join("parse-quote-loopback.pcap");
+    fs::write(&path, capture).unwrap();
+    parse_quote()
+        .arg(&path)
+        .assert()
+        .success()
+        .stdout(golden("quotes.out"));
+    let mut capture = fs::read(fixture("quotes.pcap")).unwrap();
+    capture[20] = 105;
+    let path = std::env::temp_dir().join("parse-quote-link-type.pcap");
+    fs::write(&path, capture).unwrap();
+    parse_quote().arg(&path).assert().failure().stderr(
+        "Error: Unsupported link type 105, only Ethernet (1), raw IP (101), Linux cooked (113 and \
+         276) and BSD loopback (0) captures can be read\n",
+    );
+}
+
+#[test]
+fn suspects_the_framing_when_nothing_parses() {
+    // quotes_raw.pcap claiming Ethernet frames, so that every record is 14 bytes short.
+    let raw = fs::read(fixture("quotes_raw.pcap")).unwrap();
+    let mut capture = raw.clone();
+    capture[20] = 1;
+    let path = std::env::temp_dir().join("parse-quote-framing-few.pcap");
+    fs::write(&path, &capture).unwrap();
+    parse_quote()
+        .arg(&path)
+        .assert()
+        .success()
+        .stdout("")
+        .stderr("");
+    capture.extend_from_slice(&raw[24..]);
+    let path = std::env::temp_dir().join("parse-quote-framing.pcap");
+    fs::write(&path, &capture).unwrap();
+    parse_quote()
+        .arg(&path)
+        .assert()
+        .success()
+        .stdout("")
+        .stderr(predicate::str::starts_with(
+            "Warning: none of the 14 records (88 to 243 bytes each) held a quote or trade packet.",
+        ));
+}
+
+#[test]
+fn warns_about_a_snaplen_too_small_for_quotes() {
+    use std::convert::TryInto;
+
+    // quotes.pcap as if taken with `tcpdump -s 96`, every record keeping its original length.
+    let full = fs::read(fixture("quotes.pcap")).unwrap();
+    let mut capture = full[..24].to_vec();
+    capture[16..20].copy_from_slice(&96u32.to_le_bytes());
+    let mut offset = 24;
+    while offset < full.len() {
+        let mut header = full[offset..offset + 16].to_vec();
+        let length = u32::from_le_bytes(header[8..12].try_into().unwrap()) as usize;
+        let kept = length.min(96);
+        header[8..12].copy_from_slice(&(kept as u32).to_le_bytes());
+        capture.extend_from_slice(&header);
+        capture.extend_from_slice(&full[offset + 16..offset + 16 + kept]);
+        offset += 16 + length;
+    }
+    let path = std::env::temp_dir().join("parse-quote-snaplen.pcap");
+    fs::write(&path, &capture).unwrap();
+    parse_quote()
+        .arg(&path)
+        .assert()
+        .success()
+        .stdout("")
+        .stderr(
+            "Warning: the capture header has a snaplen of 96 bytes, but a whole quote packet \
+             takes at least 257, so every quote in the capture is truncated and skipped\n\
+             Skipped 7 records truncated during capture\n\
+             Skipped 6 records captured shorter than a quote packet\n",
+        );
+}
+
+#[test]
+fn skips_truncated_packets() {
+    use std::convert::TryInto;
+
+    // The first quote in quotes.pcap with only 200 of its 257 bytes captured.
+    let full = fs::read(fixture("quotes.pcap")).unwrap();
+    let length = u32::from_le_bytes(full[32..36].try_into().unwrap()) as usize;
+    let mut capture = full[..40].to_vec();
+    capture[32..36].copy_from_slice(&200u32.to_le_bytes());
+    capture.extend_from_slice(&full[40..240]);
+    capture.extend_from_slice(&full[40 + length..]);
+    let path = std::env::temp_dir().join("parse-quote-truncated.pcap");
+    fs::write(&path, &capture).unwrap();
+    let output = parse_quote().arg(&path).output().unwrap();
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8(output.stdout).unwrap().lines().count(), 4);
+    assert_eq!(
+        String::from_utf8(output.stderr).unwrap(),
+        "Skipped 1 records truncated during capture\n\
+         Skipped 1 records captured shorter than a quote packet\n"
+    );
+    parse_quote()
+        .args(["--warn", "--dry-run"])
+        .arg(&path)
+        .assert()
+        .success()
+        .stderr(
+            "Warning: skipped truncated packet: Record at offset 0x18 captured 200 of its 257 \
+             bytes\n\
+             Skipped 1 records truncated during capture\n\
+             Skipped 1 records captured shorter than a quote packet\n\
+             Dry run passed: 7 records, 4 quotes, 0 trades, 3 skipped\n",
+        );
+    parse_quote()
+        .args(["--warn", "--error-format", "json", "--dry-run"])
+        .arg(&path)
+        .assert()
+        .success()
+        .stderr(predicate::str::starts_with(
+            r#"{"kind":"truncated","message":"Record at offset 0x18 captured 200 of its 257 bytes","offset":24}"#,
+        ));
+}
+
+#[test]
+fn skips_packets_without_an_end_of_message_byte() {
+    // The first quote of quotes.pcap with its last byte zeroed, as if its fields had drifted.
+    let mut capture = fs::read(fixture("quotes.pcap")).unwrap();
+    capture[24 + 16 + 256] = 0x00;
+    let path = std::env::temp_dir().join("parse-quote-end-of-message.pcap");
+    fs::write(&path, &capture).unwrap();
+    let output = parse_quote().arg(&path).output().unwrap();
+    assert!(output.status.success());
+    let expected = golden("quotes.out");
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        &expected[expected.find('\n').unwrap() + 1..]
+    );
+    assert_eq!(
+        String::from_utf8(output.stderr).unwrap(),
+        "Skipped 1 malformed packets\n\
+         1 of them didn't end with the end-of-message byte, so the capture may be of another feed \
+         variant or corrupted\n"
+    );
+    parse_quote()
+        .args(["--strict"])
+        .arg(&path)
+        .assert()
+        .code(1)
+        .stdout("")
+        .stderr(predicate::str::contains(
+            "End-of-message byte at offset 0x128 is 0x00 instead of 0xff (packet at offset 0x18)",
+        ));
+}
+
+#[test]
+fn dry_run_fails_on_malformed_packets() {
+    parse_quote()
+        .args(["--dry-run", &fixture("quotes.pcap")])
+        .assert()
+        .success()
+        .stdout("")
+        .stderr("Dry run passed: 7 records, 5 quotes, 0 trades, 2 skipped\n");
+    parse_quote()
+        .args(["--dry-run", &fixture("issue_codes.pcap")])
+        .assert()
+        .code(1)
+        .stdout("")
+        .stderr(predicate::str::ends_with(
+            "Error: Dry run failed: 2 of 3 records malformed, 0 bytes discarded\n",
+        ));
+}
+
+#[test]
+fn prints_extended_quotes() {
+    // The first quote of quotes.pcap with five more levels on each side, after the deepest ones.
+    let full = fs::read(fixture("quotes.pcap")).unwrap();
+    let record = &full[24..24 + 16 + 257];
+    let payload = &record[16 + 42..];
+    let mut extended = record[..16 + 42].to_vec();
+    extended.extend_from_slice(&payload[..89]);
+    for level in 0..5 {
+        extended.extend_from_slice(format!("{:05}{:07}", 99 - level, 60 + level).as_bytes());
+    }
+    extended.extend_from_slice(&payload[89..156]);
+    for level in 0..5 {
+        extended.extend_from_slice(format!("{:05}{:07}", 115 + level, 120 + level).as_bytes());
+    }
+    extended.extend_from_slice(&payload[156..]);
+    for field in &[8, 12] {
+        extended[*field..*field + 4].copy_from_slice(&377u32.to_le_bytes());
+    }
+    let mut capture = full[..24].to_vec();
+    capture.extend_from_slice(&extended);
+    capture.extend_from_slice(&full[24..]);
+    let path = std::env::temp_dir().join("parse-quote-extended.pcap");
+    fs::write(&path, &capture).unwrap();
+    let output = parse_quote().arg(&path).output().unwrap();
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert_eq!(stdout.lines().count(), 6);
+    assert_eq!(
+        stdout.lines().next().unwrap(),
+        "2011-02-16 00:00:01 2011-02-16 00:00:00.005 KR4201011009 64@95 63@96 62@97 61@98 60@99 \
+         50@104 40@103 30@102 20@101 10@100 20@110 40@111 60@112 80@113 100@114 120@115 121@116 \
+         122@117 123@118 124@119"
+    );
+    parse_quote()
+        .args(["--price-level", "7"])
+        .arg(&path)
+        .assert()
+        .success()
+        .stdout(predicate::str::starts_with(
+            "2011-02-16 00:00:01 2011-02-16 00:00:00.005 KR4201011009 61@98 121@116\n\
+             2011-02-16 00:00:01 2011-02-16 00:00:00.005 KR4201011009\n",
+        ));
+}
+
+#[test]
+fn prints_yaml_documents() {
+    let documents = |args: &[&str]| {
+        let output = parse_quote()
+            .args(args)
+            .arg(fixture("quotes.pcap"))
+            .output()
+            .unwrap();
+        assert!(output.status.success());
+        let stdout = String::from_utf8(output.stdout).unwrap();
+        stdout
+            .split("---")
+            .skip(1)
+            .map(|document| serde_yaml::from_str::<serde_yaml::Value>(document).unwrap())
+            .collect::<Vec<_>>()
+    };
+    let block = documents(&["-r", "--yaml"]);
+    let issue_codes: Vec<_> = block
+        .iter()
+        .map(|document| document["issue_code"].as_str().unwrap())
+        .collect();
+    assert_eq!(
+        issue_codes,
+        [
+            "KR0000000001",
+            "KR7005930003",
+            "KR4201011009",
+            "KR4201011009",
+            "KR4201011009",
+        ]
+    );
+    let first = &block[0];
+    assert_eq!(first["type"], "quote");
+    assert_eq!(first["number"], 2);
+    assert_eq!(first["accept_time"], "2011-02-15 23:59:59");
+    assert_eq!(first["bids"][0]["price"], 100);
+    assert_eq!(first["asks"][4]["quantity"], 100);
+    assert_eq!(documents(&["-r", "--yaml-flow"]), block);
+    parse_quote()
+        .args(["--yaml", "--head", "1"])
+        .arg(fixture("quotes.pcap"))
+        .assert()
+        .success()
+        .stdout(predicate::str::starts_with(
+            "---\ntype: quote\nnumber: 1\noffset: 24\nmessage_type: B6034\n",
+        ));
+    parse_quote()
+        .args(["--yaml-flow", "--head", "1"])
+        .arg(fixture("quotes.pcap"))
+        .assert()
+        .success()
+        .stdout(predicate::str::starts_with(
+            "--- {\"type\":\"quote\",\"number\":1,\"offset\":24,",
+        ));
+}
+
+#[test]
+fn writes_length_prefixed_msgpack() {
+    let output = |format: &str| {
+        let output = parse_quote()
+            .args(["--only", "all", format])
+            .arg(fixture("formats.pcap"))
+            .output()
+            .unwrap();
+        assert!(output.status.success());
+        output.stdout
+    };
+    let mut msgpack = &output("--msgpack")[..];
+    let mut values = Vec::new();
+    while !msgpack.is_empty() {
+        let length = u32::from_be_bytes([msgpack[0], msgpack[1], msgpack[2], msgpack[3]]);
+        let (value, rest) = msgpack[4..].split_at(length as usize);
+        values.push(rmp_serde::from_slice::<serde_json::Value>(value).unwrap());
+        msgpack = rest;
+    }
+    let json: Vec<serde_json::Value> = String::from_utf8(output("--yaml-flow"))
+        .unwrap()
+        .lines()
+        .map(|line| serde_json::from_str(line.strip_prefix("--- ").unwrap()).unwrap())
+        .collect();
+    assert!(!values.is_empty());
+    assert_eq!(values, json);
+}
+
+#[test]
+fn finds_payloads_behind_unknown_headers() {
+    // quotes.pcap with 8 more bytes of some tunnel header after the Ethernet one.
+    let full = fs::read(fixture("quotes.pcap")).unwrap();
+    let mut capture = full[..24].to_vec();
+    let mut offset = 24;
+    while offset < full.len() {
+        let field = |i: usize| u32::from_le_bytes([full[i], full[i + 1], full[i + 2], full[i + 3]]);
+        let length = field(offset + 8) as usize;
+        capture.extend_from_slice(&full[offset..offset + 8]);
+        capture.extend_from_slice(&(field(offset + 8) + 8).to_le_bytes());
+        capture.extend_from_slice(&(field(offset + 12) + 8).to_le_bytes());
+        capture.extend_from_slice(&full[offset + 16..offset + 30]);
+        capture.extend_from_slice(&[0; 8]);
+        capture.extend_from_slice(&full[offset + 30..offset + 16 + length]);
+        offset += 16 + length;
+    }
+    let path = std::env::temp_dir().join("parse-quote-tunnel.pcap");
+    fs::write(&path, &capture).unwrap();
+    parse_quote().arg(&path).assert().success().stdout("");
+    parse_quote()
+        .args(["--payload-offset", "50"])
+        .arg(&path)
+        .assert()
+        .success()
+        .stdout(golden("quotes.out"));
+    parse_quote()
+        .arg("--auto-offset")
+        .arg(&path)
+        .assert()
+        .success()
+        .stdout(golden("quotes.out"))
+        .stderr(
+            "Found the quote payload 50 bytes into the packet at offset 0x18, reading the capture \
+             with --payload-offset 50\n",
+        );
+    // A record without the tunnel header after those with it.
+    capture.extend_from_slice(&full[24..24 + 16 + 257]);
+    fs::write(&path, &capture).unwrap();
+    parse_quote()
+        .arg("--auto-offset")
+        .arg(&path)
+        .assert()
+        .failure()
+        .stderr(predicate::str::ends_with(format!(
+            "Error: The quote payload of the record at offset {:#x} is 42 bytes into its packet \
+             instead of the 50 found by --auto-offset\n",
+            capture.len() - 16 - 257
+        )));
+}
+
+#[cfg(feature = "http")]
+#[test]
+fn resumes_downloads_that_break_off() {
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::TcpListener;
+
+    let capture = fs::read(fixture("quotes.pcap")).unwrap();
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}/quotes.pcap", listener.local_addr().unwrap());
+    let server = std::thread::spawn(move || {
+        let mut ranges = Vec::new();
+        for (i, stream) in listener.incoming().take(2).enumerate() {
+            let mut stream = stream.unwrap();
+            let mut range = None;
+            for line in BufReader::new(&stream).lines() {
+                let line = line.unwrap();
+                if line.is_empty() {
+                    break;
+                }
+                if let Some(start) = line.strip_prefix("Range: bytes=") {
+                    range = Some(start.trim_end_matches('-').parse::<usize>().unwrap());
+                }
+            }
+            ranges.push(range);
+            if i == 0 {
+                // Promise the whole capture, then hang up halfway through it.
+                write!(
+                    stream,
+                    "HTTP/1.1 200 OK\r\nContent-Length: {}\r\n\r\n",
+                    capture.len()
+                )
+                .unwrap();
+                stream.write_all(&capture[..capture.len() / 2]).unwrap();
+            } else {
+                let start = range.unwrap();
+                write!(
+                    stream,
+                    "HTTP/1.1 206 Partial Content\r\nContent-Range: bytes {}-{}/{}\r\n\
+                     Content-Length: {}\r\n\r\n",
+                    start,
+                    capture.len() - 1,
+                    capture.len(),
+                    capture.len() - start
+                )
+                .unwrap();
+                stream.write_all(&capture[start..]).unwrap();
+            }
+        }
+        ranges
+    });
+    parse_quote()
+        .arg(&url)
+        .assert()
+        .success()
+        .stdout(golden("quotes.out"))
+        .stderr(predicate::str::contains("broke off after 890 bytes"));
+    assert_eq!(server.join().unwrap(), [None, Some(890)]);
+}
+
+#[cfg(feature = "http")]
+#[test]
+fn starts_gzip_downloads_over_when_they_break_off() {
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::TcpListener;
+
+    let capture = fs::read(fixture("quotes.pcap")).unwrap();
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(&capture).unwrap();
+    let compressed = encoder.finish().unwrap();
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}/quotes.pcap", listener.local_addr().unwrap());
+    let server = std::thread::spawn(move || {
+        let mut requests = Vec::new();
+        for (i, stream) in listener.incoming().take(2).enumerate() {
+            let mut stream = stream.unwrap();
+            let mut range = None;
+            let mut encoding = None;
+            for line in BufReader::new(&stream).lines() {
+                let line = line.unwrap();
+                if line.is_empty() {
+                    break;
+                }
+                if let Some(start) = line.strip_prefix("Range: bytes=") {
+                    range = Some(start.trim_end_matches('-').parse::<usize>().unwrap());
+                }
+                if let Some(value) = line.strip_prefix("Accept-Encoding: ") {
+                    encoding = Some(value.to_string());
+                }
+            }
+            requests.push((range, encoding));
+            write!(
+                stream,
+                "HTTP/1.1 200 OK\r\nContent-Encoding: gzip\r\nContent-Length: {}\r\n\r\n",
+                compressed.len()
+            )
+            .unwrap();
+            // The first time, hang up halfway through the compressed body. The second time, send
+            // all of it again instead of the range asked for.
+            let end = if i == 0 {
+                compressed.len() / 2
+            } else {
+                compressed.len()
+            };
+            stream.write_all(&compressed[..end]).unwrap();
+        }
+        requests
+    });
+    parse_quote()
+        .args(["-v", &url])
+        .assert()
+        .success()
+        .stdout(golden("quotes.out"))
+        .stderr(predicate::str::contains("broke off after"))
+        .stderr(predicate::str::contains(
+            "The server didn't resume the download, starting over",
+        ));
+    let requests = server.join().unwrap();
+    assert_eq!(requests[0].0, None);
+    // The resumed request asks for the bytes after those decoded so far, unencoded.
+    assert!(requests[1].0.is_some_and(|start| start > 0));
+    assert_eq!(requests[1].1.as_deref(), Some("identity"));
+}
+
+#[cfg(not(feature = "http"))]
+#[test]
+fn needs_the_http_feature_for_urls() {
+    parse_quote()
+        .arg("http://127.0.0.1:9/quotes.pcap")
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("built with the http feature"));
+}
+
+#[test]
+fn prints_the_skipped_ratio() {
+    parse_quote()
+        .args(["--print-skipped-ratio", &fixture("quotes.pcap")])
+        .assert()
+        .success()
+        .stdout(golden("quotes.out"))
+        .stderr("Parsed: 5 valid, 2 invalid (28.6% skipped)\n");
+    // Malformed packets are skipped too.
+    parse_quote()
+        .args(["--print-skipped-ratio", &fixture("issue_codes.pcap")])
+        .assert()
+        .success()
+        .stderr(predicate::str::starts_with(
+            "Parsed: 1 valid, 2 invalid (66.7% skipped)\n",
+        ));
+}
+
+#[test]
+fn counts_populated_levels() {
+    parse_quote()
+        .args(["--levels", "--flag-crossed", &fixture("formats.pcap")])
+        .assert()
+        .success()
+        .stdout(predicate::function(|stdout: &str| {
+            let levels: Vec<_> = stdout
+                .lines()
+                .map(|line| line.rsplit(' ').next().unwrap())
+                .collect();
+            levels
+                == [
+                    "levels=5/5",
+                    "levels=5/5",
+                    "levels=2/5",
+                    "levels=5/5",
+                    "levels=5/5",
+                ]
+        }));
+}
@@ -0,0 +1,153 @@
+use parse_quote::payload::{
+    self, ExchangeTime, Field, PayloadError, QuoteData, QUOTE_EXT_PAYLOAD_SIZE, QUOTE_PAYLOAD_SIZE,
+};
+use std::convert::TryInto;
+
+fn book_payload(levels: usize, accept_time: &[u8; 8]) -> Vec<u8> {
+    let mut payload = Vec::new();
+    payload.extend_from_slice(b"B6034KR420101100900001");
+    payload.extend_from_slice(&[b'0'; 7]);
+    for level in 0..levels {
+        payload.extend_from_slice(format!("{:05}{:07}", 100 - level, 10 * (level + 1)).as_bytes());
+    }
+    payload.extend_from_slice(&[b'0'; 7]);
+    for level in 0..levels {
+        payload.extend_from_slice(format!("{:05}{:07}", 101 + level, 20 * (level + 1)).as_bytes());
+    }
+    payload.extend_from_slice(&[b'0'; 50]);
+    payload.extend_from_slice(accept_time);
+    payload.push(payload::END_OF_MESSAGE);
+    payload
+}
+
+fn quote_payload(accept_time: &[u8; 8]) -> [u8; QUOTE_PAYLOAD_SIZE] {
+    book_payload(5, accept_time).try_into().unwrap()
+}
+
+#[test]
+fn parses_quote_payloads() {
+    let payload = quote_payload(b"09000105");
+    assert_eq!(payload[QUOTE_PAYLOAD_SIZE - 1], payload::END_OF_MESSAGE);
+    let quote = payload::parse_quote(&payload, true).unwrap();
+    assert_eq!(&quote.issue_code, b"KR4201011009");
+    assert_eq!(&quote.market_status, b"01");
+    assert_eq!(quote.bids[0], (10, 100));
+    assert_eq!(quote.asks[4], (100, 105));
+    assert_eq!(
+        quote.accept_time,
+        ExchangeTime {
+            seconds: 9 * 3_600 + 1,
+            nanoseconds: 5_000_000,
+        }
+    );
+}
+
+#[test]
+fn reports_payload_errors_by_offset() {
+    let mut bad_price = quote_payload(b"09000105");
+    bad_price[payload::ASKS_START + 1] = b'x';
+    assert_eq!(
+        payload::parse_quote(&bad_price, false),
+        Err(PayloadError::Level {
+            offset: payload::ASKS_START,
+            len: payload::PRICE_SIZE,
+            field: Field::AskPrice(1),
+        })
+    );
+    assert_eq!(
+        payload::parse_quote(&bad_price, true),
+        Err(PayloadError::NonDigit {
+            offset: payload::ASKS_START,
+            field: Field::AskPrice(1),
+        })
+    );
+    assert_eq!(
+        payload::parse_quote(&quote_payload(b"24000000"), true),
+        Err(PayloadError::TimeRange {
+            offset: payload::QUOTE_ACCEPT_START,
+            field: Field::QuoteAcceptTime,
+            unit: "hours",
+            value: 24,
+        })
+    );
+    assert!(payload::parse_quote(&quote_payload(b"24000000"), false).is_ok());
+}
+
+#[test]
+fn rejects_payloads_without_an_end_of_message_byte() {
+    let mut payload = quote_payload(b"09000105");
+    payload[QUOTE_PAYLOAD_SIZE - 1] = 0x00;
+    for &strict in &[false, true] {
+        assert_eq!(
+            payload::parse_quote(&payload, strict),
+            Err(PayloadError::EndOfMessage {
+                offset: QUOTE_PAYLOAD_SIZE - 1,
+                byte: 0x00,
+            })
+        );
+    }
+}
+
+#[test]
+fn rejects_issue_codes_that_are_not_printable_ascii() {
+    for issue_code in &[b"KR420101100\x00", b"KR42010110\xc3\xa9"] {
+        let mut payload = quote_payload(b"09000105");
+        let start = payload::ISSUE_CODE_START;
+        payload[start..start + payload::ISSUE_CODE_SIZE].copy_from_slice(*issue_code);
+        assert_eq!(
+            payload::parse_quote(&payload, false),
+            Err(PayloadError::IssueCode {
+                offset: payload::ISSUE_CODE_START,
+                bytes: **issue_code,
+            })
+        );
+    }
+}
+
+#[test]
+fn names_the_bytes_of_malformed_accept_times() {
+    assert_eq!(
+        payload::parse_quote(&quote_payload(b"09:00:01"), false),
+        Err(PayloadError::BadTime {
+            offset: payload::QUOTE_ACCEPT_START,
+            field: Field::QuoteAcceptTime,
+            bytes: *b"09:00:01",
+        })
+    );
+    // The first digit of the fraction isn't used, so it doesn't have to be a digit either.
+    assert!(payload::parse_quote(&quote_payload(b"090001 5"), false).is_ok());
+}
+
+#[test]
+fn keeps_exchange_time_fractions_below_a_second() {
+    for digit in b'0'..=b'9' {
+        let accept_time = [b'2', b'3', b'5', b'9', b'5', b'9', b'9', digit];
+        let quote = payload::parse_quote(&quote_payload(&accept_time), true).unwrap();
+        assert_eq!(quote.accept_time.seconds, 86_399);
+        assert!(quote.accept_time.nanoseconds < 1_000_000_000);
+    }
+}
+
+#[test]
+fn parses_extended_quote_payloads() {
+    let payload: [u8; QUOTE_EXT_PAYLOAD_SIZE] = book_payload(10, b"09000105").try_into().unwrap();
+    let quote = payload::parse_quote_ext(&payload, true).unwrap();
+    assert_eq!(&quote.issue_code, b"KR4201011009");
+    assert_eq!(quote.bids().len(), 10);
+    assert_eq!(quote.bids()[9], (100, 91));
+    assert_eq!(quote.asks()[9], (200, 110));
+    assert_eq!(quote.accept_time.seconds, 9 * 3_600 + 1);
+    let short = payload::parse_quote(&quote_payload(b"09000105"), true).unwrap();
+    assert_eq!(quote.bids()[..5], *short.bids());
+    assert_eq!(quote.asks()[..5], *short.asks());
+
+    let mut bad_price = payload;
+    bad_price[payload::QUOTE_EXT_ASKS_START + 9 * payload::LEVEL_SIZE] = b'x';
+    assert_eq!(
+        payload::parse_quote_ext(&bad_price, true),
+        Err(PayloadError::NonDigit {
+            offset: payload::QUOTE_EXT_ASKS_START + 9 * payload::LEVEL_SIZE,
+            field: Field::AskPrice(10),
+        })
+    );
+}
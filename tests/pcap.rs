@@ -0,0 +1,224 @@
+use parse_quote::pcap::{self, Endianness, LinkType, Payload, PcapHeader, Precision};
+use std::fs::File;
+use std::ops::ControlFlow;
+
+const FIXTURES: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures");
+
+fn issue_codes(name: &str, limit: usize) -> Vec<String> {
+    let file = File::open(format!("{}/{}", FIXTURES, name)).unwrap();
+    let mut codes = Vec::new();
+    pcap::for_each_packet(file, |packet| {
+        if let Payload::Quote(quote) = &packet.payload {
+            codes.push(String::from_utf8_lossy(&quote.issue_code).into_owned());
+        }
+        if codes.len() == limit {
+            ControlFlow::Break(())
+        } else {
+            ControlFlow::Continue(())
+        }
+    })
+    .unwrap();
+    codes
+}
+
+#[test]
+fn visits_quotes_in_capture_order() {
+    let codes = issue_codes("quotes.pcap", usize::MAX);
+    assert_eq!(
+        codes,
+        [
+            "KR4201011009",
+            "KR0000000001",
+            "KR4201011009",
+            "KR7005930003",
+            "KR4201011009",
+        ]
+    );
+    assert_eq!(issue_codes("quotes_be.pcap", usize::MAX), codes);
+}
+
+#[test]
+fn reads_every_link_type() {
+    let codes = issue_codes("quotes.pcap", usize::MAX);
+    for name in &["quotes_raw.pcap", "quotes_sll.pcap", "quotes_sll2.pcap"] {
+        assert_eq!(issue_codes(name, usize::MAX), codes, "{}", name);
+    }
+    assert_eq!(LinkType::from_header(113).unwrap(), LinkType::LinuxSll);
+    assert_eq!(LinkType::LinuxSll.payload_offset(), 44);
+    let error = LinkType::from_header(105).unwrap_err();
+    assert_eq!(error.kind(), std::io::ErrorKind::InvalidData);
+}
+
+#[test]
+fn visits_every_quote_of_a_datagram() {
+    let mut offsets = Vec::new();
+    let file = File::open(format!("{}/datagrams.pcap", FIXTURES)).unwrap();
+    pcap::for_each_packet(file, |packet| {
+        offsets.push(packet.offset);
+        ControlFlow::Continue(())
+    })
+    .unwrap();
+    assert_eq!(offsets.len(), 5);
+    assert_eq!(offsets[0], offsets[1]);
+}
+
+#[test]
+fn steps_through_datagrams_by_their_stride() {
+    let is_quote = |marker: &[u8]| marker == b"B6034";
+    let mut payload = b"B6034 first, padded".to_vec();
+    payload.extend_from_slice(b"B6034 second, short");
+    payload.extend_from_slice(b"A3034 third, trade");
+    assert_eq!(
+        pcap::datagram_message(&payload, 0, 19, is_quote),
+        Some(&b"B6034 first, padded"[..])
+    );
+    assert_eq!(
+        pcap::datagram_message(&payload, 19, 19, is_quote),
+        Some(&b"B6034 second, short"[..])
+    );
+    assert_eq!(pcap::datagram_message(&payload, 38, 18, is_quote), None);
+    assert_eq!(pcap::datagram_message(&payload, 19, 40, is_quote), None);
+    assert_eq!(
+        pcap::datagram_message(&payload, usize::MAX, 19, is_quote),
+        None
+    );
+}
+
+#[test]
+fn stops_when_told_to() {
+    assert_eq!(
+        issue_codes("quotes.pcap", 2),
+        ["KR4201011009", "KR0000000001"]
+    );
+}
+
+#[test]
+fn rejects_what_is_not_a_capture() {
+    let error = pcap::for_each_packet(&b"not a pcap capture at all"[..], |_| {
+        ControlFlow::Continue(())
+    })
+    .unwrap_err();
+    assert_eq!(error.kind(), std::io::ErrorKind::InvalidData);
+}
+
+#[test]
+fn parses_the_global_header() {
+    let capture = std::fs::read(format!("{}/quotes_be.pcap", FIXTURES)).unwrap();
+    let header = PcapHeader::parse(&capture).unwrap();
+    assert_eq!(
+        header,
+        PcapHeader {
+            endianness: Endianness::BigEndian,
+            precision: Precision::Microsecond,
+            version_major: 2,
+            version_minor: 4,
+            this_zone: 0,
+            snaplen: 65535,
+            link_type: 1,
+        }
+    );
+    let error = PcapHeader::parse(&capture[..PcapHeader::SIZE - 1]).unwrap_err();
+    assert_eq!(error.kind(), std::io::ErrorKind::UnexpectedEof);
+    let mut old = capture[..PcapHeader::SIZE].to_vec();
+    old[7] = 3;
+    let error = PcapHeader::parse(&old).unwrap_err();
+    assert_eq!(error.to_string(), "Unsupported pcap version 2.3");
+}
+
+#[cfg(feature = "async")]
+mod async_reader {
+    use parse_quote::pcap::{AsyncPacketReader, Payload};
+    use std::fs;
+    use std::time::Duration;
+    use tokio::io::AsyncWriteExt;
+
+    fn capture() -> Vec<u8> {
+        fs::read(format!("{}/quotes.pcap", super::FIXTURES)).unwrap()
+    }
+
+    fn issue_code(payload: &Payload) -> String {
+        match payload {
+            Payload::Quote(quote) => String::from_utf8_lossy(&quote.issue_code).into_owned(),
+            Payload::QuoteExt(quote) => String::from_utf8_lossy(&quote.issue_code).into_owned(),
+            Payload::Trade(trade) => String::from_utf8_lossy(&trade.issue_code).into_owned(),
+        }
+    }
+
+    #[tokio::test]
+    async fn reads_the_same_packets_as_the_visitor() {
+        let (mut writer, reader) = tokio::io::duplex(64);
+        let capture = capture();
+        let write = tokio::spawn(async move {
+            // Small chunks, so that headers and records arrive in pieces.
+            for chunk in capture.chunks(7) {
+                writer.write_all(chunk).await.unwrap();
+            }
+        });
+        let mut reader = AsyncPacketReader::new(reader);
+        let mut codes = Vec::new();
+        while let Some(packet) = reader.next_packet().await {
+            codes.push(issue_code(&packet.unwrap().payload));
+        }
+        write.await.unwrap();
+        assert_eq!(codes, super::issue_codes("quotes.pcap", usize::MAX));
+    }
+
+    #[tokio::test]
+    async fn carries_on_after_a_cancelled_read() {
+        let (mut writer, reader) = tokio::io::duplex(4096);
+        let capture = capture();
+        // The global header and part of the first record.
+        writer.write_all(&capture[..100]).await.unwrap();
+        let mut reader = AsyncPacketReader::new(reader);
+        let cancelled = tokio::time::timeout(Duration::from_millis(10), reader.next_packet());
+        assert!(cancelled.await.is_err());
+        writer.write_all(&capture[100..]).await.unwrap();
+        drop(writer);
+        let first = reader.next_packet().await.unwrap().unwrap();
+        assert_eq!(first.offset, 24);
+        assert_eq!(issue_code(&first.payload), "KR4201011009");
+        let mut count = 1;
+        while let Some(packet) = reader.next_packet().await {
+            packet.unwrap();
+            count += 1;
+        }
+        assert_eq!(count, 5);
+    }
+
+    #[tokio::test]
+    async fn reads_every_link_type() {
+        for name in &["quotes_raw.pcap", "quotes_sll.pcap", "quotes_sll2.pcap"] {
+            let capture = fs::read(format!("{}/{}", super::FIXTURES, name)).unwrap();
+            let mut reader = AsyncPacketReader::new(&capture[..]);
+            let mut codes = Vec::new();
+            while let Some(packet) = reader.next_packet().await {
+                codes.push(issue_code(&packet.unwrap().payload));
+            }
+            assert_eq!(
+                codes,
+                super::issue_codes("quotes.pcap", usize::MAX),
+                "{}",
+                name
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn yields_every_quote_of_a_datagram() {
+        let capture = fs::read(format!("{}/datagrams.pcap", super::FIXTURES)).unwrap();
+        let mut reader = AsyncPacketReader::new(&capture[..]);
+        let mut offsets = Vec::new();
+        while let Some(packet) = reader.next_packet().await {
+            offsets.push(packet.unwrap().offset);
+        }
+        assert_eq!(offsets.len(), 5);
+        assert_eq!(offsets[0], offsets[1]);
+    }
+
+    #[tokio::test]
+    async fn ends_after_an_error() {
+        let mut reader = AsyncPacketReader::new(&b"not a pcap capture at all"[..]);
+        assert!(reader.next_packet().await.unwrap().is_err());
+        assert!(reader.next_packet().await.is_none());
+    }
+}